@@ -0,0 +1,267 @@
+
+// The physical address map.  Every physical access the hart makes goes through the bus, which
+// hands it to whichever region covers the address: RAM, ROM, or a memory-mapped device.  Accesses
+// to unmapped addresses, or that run off the end of a region, fail and the hart raises an access
+// fault.
+
+
+use std::{ cell::RefCell, io::{ Error, ErrorKind }, rc::Rc };
+
+
+pub const DEFAULT_RAM_BASE: u64 = 0x_8000_0000;
+pub const DEFAULT_RAM_SIZE: u64 = 128 * 1024 * 1024;
+
+
+
+// A memory-mapped peripheral.  Offsets are relative to the device's base address, and an access
+// the device doesn't support returns false.
+pub trait Device
+{
+    fn read(&mut self, offset: u64, bytes: &mut [ u8 ]) -> bool;
+    fn write(&mut self, offset: u64, bytes: &[ u8 ]) -> bool;
+
+    // The level of the device's interrupt output, for devices that have one.
+    fn interrupt_pending(&mut self) -> bool
+    {
+        false
+    }
+}
+
+
+// A device that something else also needs to reach, such as the hart or an interrupt controller,
+// is shared through a RefCell.
+impl<T: Device> Device for Rc<RefCell<T>>
+{
+    fn read(&mut self, offset: u64, bytes: &mut [ u8 ]) -> bool
+    {
+        self.borrow_mut().read(offset, bytes)
+    }
+
+
+    fn write(&mut self, offset: u64, bytes: &[ u8 ]) -> bool
+    {
+        self.borrow_mut().write(offset, bytes)
+    }
+
+
+    fn interrupt_pending(&mut self) -> bool
+    {
+        self.borrow_mut().interrupt_pending()
+    }
+}
+
+
+enum Backing
+{
+    Ram(Vec<u8>),
+    Rom(Vec<u8>),
+    Device(Box<dyn Device>)
+}
+
+
+struct Region
+{
+    base: u64,
+    size: u64,
+    backing: Backing
+}
+
+
+impl Region
+{
+    fn contains(&self, address: u64, size: u64) -> bool
+    {
+        address.checked_sub(self.base)
+               .is_some_and(|offset| size <= self.size && offset <= self.size - size)
+    }
+}
+
+
+
+pub struct Bus
+{
+    regions: Vec<Region>
+}
+
+
+impl Bus
+{
+    pub fn new() -> Self
+    {
+        Self { regions: Vec::new() }
+    }
+
+
+    pub fn add_ram(&mut self, base: u64, size: u64) -> Result<(), Error>
+    {
+        self.add(Region { base, size, backing: Backing::Ram(vec![ 0; size as usize ]) })
+    }
+
+
+    pub fn add_rom(&mut self, base: u64, contents: Vec<u8>) -> Result<(), Error>
+    {
+        let size = contents.len() as u64;
+        self.add(Region { base, size, backing: Backing::Rom(contents) })
+    }
+
+
+    pub fn add_device(&mut self,
+                      base: u64,
+                      size: u64,
+                      device: Box<dyn Device>) -> Result<(), Error>
+    {
+        self.add(Region { base, size, backing: Backing::Device(device) })
+    }
+
+
+    fn add(&mut self, region: Region) -> Result<(), Error>
+    {
+        let end = region.base.checked_add(region.size);
+        let overlaps = |other: &Region| region.base < other.base + other.size
+                                        && other.base < region.base + region.size;
+
+        if region.size == 0 || end.is_none() || self.regions.iter().any(overlaps)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("Memory region at {:#x} of {:#x} bytes overlaps another \
+                                           or doesn't fit the address space.",
+                                          region.base,
+                                          region.size)));
+        }
+
+        self.regions.push(region);
+        Ok(())
+    }
+
+
+    fn find(&mut self, address: u64, size: usize) -> Option<( &mut Region, u64 )>
+    {
+        self.regions
+            .iter_mut()
+            .find(|region| region.contains(address, size as u64))
+            .map(|region| { let offset = address - region.base; ( region, offset ) })
+    }
+
+
+    pub fn read(&mut self, address: u64, bytes: &mut [ u8 ]) -> bool
+    {
+        let size = bytes.len();
+
+        match self.find(address, size)
+        {
+            Some(( Region { backing: Backing::Ram(data), .. }, offset )) |
+            Some(( Region { backing: Backing::Rom(data), .. }, offset )) =>
+                {
+                    let offset = offset as usize;
+
+                    bytes.copy_from_slice(&data[offset..offset + size]);
+                    true
+                },
+
+            Some(( Region { backing: Backing::Device(device), .. }, offset )) =>
+                device.read(offset, bytes),
+
+            None =>
+                false
+        }
+    }
+
+
+    // Stores to ROM fail like any other unsupported access.
+    pub fn write(&mut self, address: u64, bytes: &[ u8 ]) -> bool
+    {
+        let size = bytes.len();
+
+        match self.find(address, size)
+        {
+            Some(( Region { backing: Backing::Ram(data), .. }, offset )) =>
+                {
+                    let offset = offset as usize;
+
+                    data[offset..offset + size].copy_from_slice(bytes);
+                    true
+                },
+
+            Some(( Region { backing: Backing::Device(device), .. }, offset )) =>
+                device.write(offset, bytes),
+
+            _ =>
+                false
+        }
+    }
+
+
+    // Fill RAM or ROM with an image before the hart starts running.
+    pub fn load(&mut self, address: u64, bytes: &[ u8 ]) -> bool
+    {
+        let size = bytes.len();
+
+        match self.find(address, size)
+        {
+            Some(( Region { backing: Backing::Ram(data), .. }, offset )) |
+            Some(( Region { backing: Backing::Rom(data), .. }, offset )) =>
+                {
+                    let offset = offset as usize;
+
+                    data[offset..offset + size].copy_from_slice(bytes);
+                    true
+                },
+
+            _ =>
+                false
+        }
+    }
+}
+
+
+impl Default for Bus
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    // Accesses at either end of RAM, and ones that would wrap around the top of the address space,
+    // for RAM at the bottom of memory and just above it.
+    #[test]
+    fn region_bounds()
+    {
+        for &base in &[ 0, 0x_1_0000 ]
+        {
+            let mut bus = Bus::new();
+            let size = 0x_1000;
+
+            bus.add_ram(base, size).unwrap();
+
+            let table =
+                [
+                    ( base,                    8,          true ),
+                    ( base + size - 8,         8,          true ),
+                    ( base,                    0x_1000,    true ),
+                    ( base + size - 4,         8,          false ),
+                    ( base + size,             1,          false ),
+                    ( base,                    0x_1001,    false ),
+                    ( base.wrapping_sub(4),    8,          false ),
+                    ( u64::MAX - 3,            4,          false ),
+                    ( u64::MAX - 0x_fff,       0x_10_0000, false )
+                ];
+
+            for &( address, size, expected ) in &table
+            {
+                let mut bytes = vec![ 0; size ];
+
+                assert_eq!(bus.read(address, &mut bytes), expected, "{:#x} {:#x}", address, size);
+                assert_eq!(bus.write(address, &bytes), expected, "{:#x} {:#x}", address, size);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+
+// A trace of execution in the format Spike gives with -l and --log-commits, so that the two can be
+// diffed.  Every instruction fetched is logged with its disassembly, and again once it retires,
+// along with the registers and CSRs it wrote and the memory it touched.  Traps are logged as they
+// are taken.
+
+
+use super::{ opcodes::*, instruction::Instruction, csr::*, cpu::{ Cpu, PrivilegeLvel },
+             trap::{ Exception, Interrupt } };
+
+
+// The operation in the rest of an OP-FP instruction's func7, for those that write an integer
+// register.
+const FP_COMPARE:    u32 = F7_FEQ_S >> 2;
+const FP_CVT_TO_INT: u32 = F7_FCVT_W___ >> 2;
+const FP_MV_TO_INT:  u32 = F7_FMV_X_W >> 2;
+
+
+
+// What the instruction running has done so far, beyond writing its destination register.
+#[derive(Default)]
+pub struct CommitLog
+{
+    loads: Vec<usize>,
+    stores: Vec<( usize, u64, usize )>,
+    csrs: Vec<usize>
+}
+
+
+impl CommitLog
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+
+    fn clear(&mut self)
+    {
+        self.loads.clear();
+        self.stores.clear();
+        self.csrs.clear();
+    }
+}
+
+
+enum Destination
+{
+    Integer(usize),
+    Float(usize)
+}
+
+
+// The register an instruction writes, if it writes one.
+fn destination(instruction: &Instruction) -> Option<Destination>
+{
+    match instruction.opcode
+    {
+        OP_LUI | OP_AUIPC | OP_JAL | OP_JALR | OP_LD___ | OP_MO1___ | OP_MO2___ | OP_MO3___ |
+        OP_MO4___ | OP_EXT_A___ =>
+            Some(Destination::Integer(instruction.rd)),
+
+        // Only the CSR instructions, the rest of the system instructions don't have one.
+        OP_SYSTEM___ if instruction.func3 != 0 =>
+            Some(Destination::Integer(instruction.rd)),
+
+        OP_FLW | OP_FMADD_S___ | OP_FMSUB_S___ | OP_FNMSUB_S___ | OP_FNMADD_S___ =>
+            Some(Destination::Float(instruction.rd)),
+
+        OP_RV_F___ =>
+            match instruction.func7 >> 2
+            {
+                FP_COMPARE | FP_CVT_TO_INT | FP_MV_TO_INT =>
+                    Some(Destination::Integer(instruction.rd)),
+
+                _ =>
+                    Some(Destination::Float(instruction.rd))
+            },
+
+        _ =>
+            None
+    }
+}
+
+
+// Spike's names for the exceptions.
+fn trap_name(exception: Exception) -> &'static str
+{
+    match exception
+    {
+        Exception::InstructionAddressMisaligned(_) => "trap_instruction_address_misaligned",
+        Exception::InstructionAccessFault(_)       => "trap_instruction_access_fault",
+        Exception::IllegalInstruction(_)           => "trap_illegal_instruction",
+        Exception::Breakpoint(_)                   => "trap_breakpoint",
+        Exception::LoadAddressMisaligned(_)        => "trap_load_address_misaligned",
+        Exception::LoadAccessFault(_)              => "trap_load_access_fault",
+        Exception::StoreAddressMisaligned(_)       => "trap_store_address_misaligned",
+        Exception::StoreAccessFault(_)             => "trap_store_access_fault",
+        Exception::EnvironmentCallFromUMode        => "trap_user_ecall",
+        Exception::EnvironmentCallFromSMode        => "trap_supervisor_ecall",
+        Exception::EnvironmentCallFromMMode        => "trap_machine_ecall",
+        Exception::InstructionPageFault(_)         => "trap_instruction_page_fault",
+        Exception::LoadPageFault(_)                => "trap_load_page_fault",
+        Exception::StorePageFault(_)               => "trap_store_page_fault"
+    }
+}
+
+
+// Spike pads the mnemonic out to eight columns, and puts a space after each comma.
+fn spike_disassembly(disassembly: &str) -> String
+{
+    match disassembly.split_once('\t')
+    {
+        Some(( mnemonic, operands )) => format!("{:<7} {}", mnemonic, operands.replace(',', ", ")),
+        None                         => disassembly.to_string()
+    }
+}
+
+
+
+impl Cpu
+{
+    pub fn log_load(&mut self, address: usize)
+    {
+        if let Some(log) = &mut self.commit_log
+        {
+            log.loads.push(address);
+        }
+    }
+
+
+    pub fn log_store(&mut self, address: usize, bytes: &[ u8 ])
+    {
+        if let Some(log) = &mut self.commit_log
+        {
+            let value = bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64);
+            log.stores.push(( address, value, bytes.len() ));
+        }
+    }
+
+
+    pub fn log_csr(&mut self, address: usize)
+    {
+        if let Some(log) = &mut self.commit_log
+        {
+            if !log.csrs.contains(&address)
+            {
+                log.csrs.push(address);
+            }
+        }
+    }
+
+
+    // An instruction is logged as it starts, whether or not it goes on to retire.
+    pub fn log_fetch(&mut self, pc: usize, instruction: &Instruction)
+    {
+        if let Some(log) = &mut self.commit_log
+        {
+            log.clear();
+
+            eprintln!("core {:3}: 0x{:016x} (0x{:08x}) {}",
+                      self.csrs[CSR_MHARTID],
+                      pc,
+                      instruction.encoding,
+                      spike_disassembly(&instruction.disassemble(pc)));
+        }
+    }
+
+
+    // Once it retires it's logged along with the privilege level it ran at, and what it did.
+    pub fn log_commit(&mut self, pc: usize, privilege: PrivilegeLvel, instruction: &Instruction)
+    {
+        let log = match self.commit_log.take()
+            {
+                Some(log) => log,
+                None      => return
+            };
+
+        let mut line = format!("core {:3}: {} 0x{:016x} (0x{:0width$x})",
+                               self.csrs[CSR_MHARTID],
+                               privilege as u64,
+                               pc,
+                               instruction.encoding,
+                               width = instruction.length * 2);
+
+        // Writes to x0 are left out.
+        match destination(instruction)
+        {
+            Some(Destination::Integer(rd)) if rd != 0 =>
+                line += &format!(" x{:<2} 0x{:016x}", rd, self.regs[rd - 1]),
+
+            Some(Destination::Float(rd)) =>
+                line += &format!(" f{:<2} 0x{:016x}", rd, self.fregs[rd]),
+
+            _ =>
+                {
+                }
+        }
+
+        for &address in &log.csrs
+        {
+            let name = csr_name(address).map_or_else(|| format!("unknown_{:03x}", address),
+                                                      |name| name.to_string());
+            let value = self.read_csr_debug(address).unwrap_or(0);
+
+            line += &format!(" c{}_{} 0x{:016x}", address, name, value);
+        }
+
+        for &address in &log.loads
+        {
+            line += &format!(" mem 0x{:016x}", address);
+        }
+
+        for &( address, value, size ) in &log.stores
+        {
+            line += &format!(" mem 0x{:016x} 0x{:0width$x}", address, value, width = size * 2);
+        }
+
+        eprintln!("{}", line);
+
+        self.commit_log = Some(log);
+    }
+
+
+    // Traps are logged with the pc they're taken at, and for most exceptions the trap value.
+    pub fn log_exception(&mut self, exception: Exception)
+    {
+        if self.commit_log.is_none()
+        {
+            return;
+        }
+
+        let hart = self.csrs[CSR_MHARTID];
+
+        eprintln!("core {:3}: exception {}, epc 0x{:016x}", hart, trap_name(exception), self.pc);
+
+        if !matches!(exception, Exception::EnvironmentCallFromUMode |
+                                Exception::EnvironmentCallFromSMode |
+                                Exception::EnvironmentCallFromMMode)
+        {
+            eprintln!("core {:3}:           tval 0x{:016x}", hart, exception.tval());
+        }
+    }
+
+
+    pub fn log_interrupt(&mut self, interrupt: Interrupt)
+    {
+        if self.commit_log.is_some()
+        {
+            eprintln!("core {:3}: exception interrupt #{}, epc 0x{:016x}",
+                      self.csrs[CSR_MHARTID],
+                      interrupt.code(),
+                      self.pc);
+        }
+    }
+}
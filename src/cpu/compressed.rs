@@ -0,0 +1,530 @@
+
+// The C extension.  Each 16-bit instruction is expanded into the 32-bit instruction it stands for,
+// which then executes like any other.  Reserved encodings, and those of RV32 or RV128 only forms,
+// don't expand at all and are illegal.
+
+
+use super::opcodes::*;
+
+
+// Register x2 is the stack pointer, x1 the link register.
+const REG_RA: u32 = 1;
+const REG_SP: u32 = 2;
+
+
+
+// Field extraction from the compressed instruction.
+fn bits(half: u16, high: u32, low: u32) -> u32
+{
+    (half as u32 >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+
+fn bit(half: u16, index: u32) -> u32
+{
+    bits(half, index, index)
+}
+
+
+fn sign_extend(value: u32, size: u32) -> u32
+{
+    (((value << (32 - size)) as i32) >> (32 - size)) as u32
+}
+
+
+// The full register numbers, and the x8-x15 registers addressed by the 3-bit fields.
+fn rd(half: u16) -> u32
+{
+    bits(half, 11, 7)
+}
+
+
+fn rs2(half: u16) -> u32
+{
+    bits(half, 6, 2)
+}
+
+
+fn rd_prime(half: u16) -> u32
+{
+    8 + bits(half, 4, 2)
+}
+
+
+fn rs1_prime(half: u16) -> u32
+{
+    8 + bits(half, 9, 7)
+}
+
+
+
+// The 32-bit instruction formats.
+fn r_type(func7: u32, rs2: u32, rs1: u32, func3: u32, rd: u32, opcode: u32) -> u32
+{
+    (func7 << 25) | (rs2 << 20) | (rs1 << 15) | (func3 << 12) | (rd << 7) | opcode
+}
+
+
+fn i_type(immediate: u32, rs1: u32, func3: u32, rd: u32, opcode: u32) -> u32
+{
+    ((immediate & 0x_fff) << 20) | (rs1 << 15) | (func3 << 12) | (rd << 7) | opcode
+}
+
+
+fn s_type(immediate: u32, rs2: u32, rs1: u32, func3: u32, opcode: u32) -> u32
+{
+    (((immediate >> 5) & 0b_1111111) << 25)
+    | (rs2 << 20)
+    | (rs1 << 15)
+    | (func3 << 12)
+    | ((immediate & 0b_11111) << 7)
+    | opcode
+}
+
+
+fn b_type(immediate: u32, rs2: u32, rs1: u32, func3: u32, opcode: u32) -> u32
+{
+    (((immediate >> 12) & 1) << 31)
+    | (((immediate >> 5) & 0b_111111) << 25)
+    | (rs2 << 20)
+    | (rs1 << 15)
+    | (func3 << 12)
+    | (((immediate >> 1) & 0b_1111) << 8)
+    | (((immediate >> 11) & 1) << 7)
+    | opcode
+}
+
+
+fn u_type(immediate: u32, rd: u32, opcode: u32) -> u32
+{
+    (immediate & 0x_fffff000) | (rd << 7) | opcode
+}
+
+
+fn j_type(immediate: u32, rd: u32, opcode: u32) -> u32
+{
+    (((immediate >> 20) & 1) << 31)
+    | (((immediate >> 1) & 0b_1111111111) << 21)
+    | (((immediate >> 11) & 1) << 20)
+    | (((immediate >> 12) & 0b_11111111) << 12)
+    | (rd << 7)
+    | opcode
+}
+
+
+
+// The immediates shared by several instructions.
+
+// c.addi, c.addiw, c.li and c.andi: a signed 6-bit value.
+fn ci_immediate(half: u16) -> u32
+{
+    sign_extend((bit(half, 12) << 5) | bits(half, 6, 2), 6)
+}
+
+
+// c.slli, c.srli and c.srai: a 6-bit shift amount.
+fn shamt(half: u16) -> u32
+{
+    (bit(half, 12) << 5) | bits(half, 6, 2)
+}
+
+
+// c.ld, c.sd, c.fld and c.fsd: a double-word scaled offset.
+fn cl_double_offset(half: u16) -> u32
+{
+    (bits(half, 12, 10) << 3) | (bits(half, 6, 5) << 6)
+}
+
+
+// c.lw and c.sw: a word scaled offset.
+fn cl_word_offset(half: u16) -> u32
+{
+    (bits(half, 12, 10) << 3) | (bit(half, 6) << 2) | (bit(half, 5) << 6)
+}
+
+
+// c.ldsp and c.fldsp: a double-word scaled offset from the stack pointer.
+fn ci_double_sp_offset(half: u16) -> u32
+{
+    (bit(half, 12) << 5) | (bits(half, 6, 5) << 3) | (bits(half, 4, 2) << 6)
+}
+
+
+// c.lwsp: a word scaled offset from the stack pointer.
+fn ci_word_sp_offset(half: u16) -> u32
+{
+    (bit(half, 12) << 5) | (bits(half, 6, 4) << 2) | (bits(half, 3, 2) << 6)
+}
+
+
+// c.sdsp and c.fsdsp: a double-word scaled offset from the stack pointer.
+fn css_double_sp_offset(half: u16) -> u32
+{
+    (bits(half, 12, 10) << 3) | (bits(half, 9, 7) << 6)
+}
+
+
+// c.swsp: a word scaled offset from the stack pointer.
+fn css_word_sp_offset(half: u16) -> u32
+{
+    (bits(half, 12, 9) << 2) | (bits(half, 8, 7) << 6)
+}
+
+
+// c.j: a signed 12-bit offset.
+fn cj_offset(half: u16) -> u32
+{
+    let offset = (bit(half, 12) << 11)
+                 | (bit(half, 11) << 4)
+                 | (bits(half, 10, 9) << 8)
+                 | (bit(half, 8) << 10)
+                 | (bit(half, 7) << 6)
+                 | (bit(half, 6) << 7)
+                 | (bits(half, 5, 3) << 1)
+                 | (bit(half, 2) << 5);
+
+    sign_extend(offset, 12)
+}
+
+
+// c.beqz and c.bnez: a signed 9-bit offset.
+fn cb_offset(half: u16) -> u32
+{
+    let offset = (bit(half, 12) << 8)
+                 | (bits(half, 11, 10) << 3)
+                 | (bits(half, 6, 5) << 6)
+                 | (bits(half, 4, 3) << 1)
+                 | (bit(half, 2) << 5);
+
+    sign_extend(offset, 9)
+}
+
+
+
+pub fn is_compressed(half: u16) -> bool
+{
+    half & C_QUADRANT_MASK != 0b_11
+}
+
+
+pub fn expand(half: u16) -> Option<u32>
+{
+    let func3 = bits(half, 15, 13) as u16;
+
+    let expanded = match ( half & C_QUADRANT_MASK, func3 )
+        {
+            // c.addi4spn  ciw-type
+            ( C_Q0___, C_F3_ADDI4SPN ) =>
+                {
+                    let immediate = (bits(half, 12, 11) << 4)
+                                    | (bits(half, 10, 7) << 6)
+                                    | (bit(half, 6) << 2)
+                                    | (bit(half, 5) << 3);
+
+                    if immediate == 0
+                    {
+                        return None;
+                    }
+
+                    i_type(immediate, REG_SP, F3_ADDI, rd_prime(half), OP_MO1___)
+                },
+
+            // c.fld  cl-type
+            ( C_Q0___, C_F3_FLD ) =>
+                i_type(cl_double_offset(half), rs1_prime(half), F3_FLD, rd_prime(half), OP_FLW),
+
+            // c.lw  cl-type
+            ( C_Q0___, C_F3_LW ) =>
+                i_type(cl_word_offset(half), rs1_prime(half), F3_LW, rd_prime(half), OP_LD___),
+
+            // c.ld  cl-type
+            ( C_Q0___, C_F3_LD ) =>
+                i_type(cl_double_offset(half), rs1_prime(half), F3_LD, rd_prime(half), OP_LD___),
+
+            // c.fsd  cs-type
+            ( C_Q0___, C_F3_FSD ) =>
+                s_type(cl_double_offset(half), rd_prime(half), rs1_prime(half), F3_FSD, OP_FSW),
+
+            // c.sw  cs-type
+            ( C_Q0___, C_F3_SW ) =>
+                s_type(cl_word_offset(half), rd_prime(half), rs1_prime(half), F3_SW, OP_ST___),
+
+            // c.sd  cs-type
+            ( C_Q0___, C_F3_SD ) =>
+                s_type(cl_double_offset(half), rd_prime(half), rs1_prime(half), F3_SD, OP_ST___),
+
+            // c.addi  ci-type (c.nop when rd is x0)
+            ( C_Q1___, C_F3_ADDI ) =>
+                i_type(ci_immediate(half), rd(half), F3_ADDI, rd(half), OP_MO1___),
+
+            // c.addiw  ci-type
+            ( C_Q1___, C_F3_ADDIW ) =>
+                {
+                    if rd(half) == 0
+                    {
+                        return None;
+                    }
+
+                    i_type(ci_immediate(half), rd(half), F3_ADDIW, rd(half), OP_MO3___)
+                },
+
+            // c.li  ci-type
+            ( C_Q1___, C_F3_LI ) =>
+                i_type(ci_immediate(half), 0, F3_ADDI, rd(half), OP_MO1___),
+
+            // c.addi16sp  ci-type
+            ( C_Q1___, C_F3_LUI___ ) if rd(half) == REG_SP =>
+                {
+                    let immediate = (bit(half, 12) << 9)
+                                    | (bit(half, 6) << 4)
+                                    | (bit(half, 5) << 6)
+                                    | (bits(half, 4, 3) << 7)
+                                    | (bit(half, 2) << 5);
+
+                    if immediate == 0
+                    {
+                        return None;
+                    }
+
+                    i_type(sign_extend(immediate, 10), REG_SP, F3_ADDI, REG_SP, OP_MO1___)
+                },
+
+            // c.lui  ci-type
+            ( C_Q1___, C_F3_LUI___ ) =>
+                {
+                    let immediate = (bit(half, 12) << 17) | (bits(half, 6, 2) << 12);
+                    let immediate = sign_extend(immediate, 18);
+
+                    if immediate == 0
+                    {
+                        return None;
+                    }
+
+                    u_type(immediate, rd(half), OP_LUI)
+                },
+
+            ( C_Q1___, C_F3_ALU___ ) =>
+                {
+                    let rd = rs1_prime(half);
+
+                    match bits(half, 11, 10) as u16
+                    {
+                        // c.srli  cb-type
+                        C_F2_SRLI =>
+                            i_type((F7_SRLI << 5) | shamt(half), rd, F3_SR___, rd, OP_MO1___),
+
+                        // c.srai  cb-type
+                        C_F2_SRAI =>
+                            i_type((F7_SRAI << 5) | shamt(half), rd, F3_SR___, rd, OP_MO1___),
+
+                        // c.andi  cb-type
+                        C_F2_ANDI =>
+                            i_type(ci_immediate(half), rd, F3_ANDI, rd, OP_MO1___),
+
+                        C_F2_REG___ =>
+                            {
+                                let rs2 = rd_prime(half);
+
+                                match ((bit(half, 12) << 2) | bits(half, 6, 5)) as u16
+                                {
+                                    // c.sub  ca-type
+                                    C_SUB  => r_type(F7_SUB, rs2, rd, F3_AS___, rd, OP_MO2___),
+
+                                    // c.xor  ca-type
+                                    C_XOR  => r_type(F7_XOR, rs2, rd, F3_XOR, rd, OP_MO2___),
+
+                                    // c.or  ca-type
+                                    C_OR   => r_type(F7_OR, rs2, rd, F3_OR, rd, OP_MO2___),
+
+                                    // c.and  ca-type
+                                    C_AND  => r_type(F7_AND, rs2, rd, F3_AND, rd, OP_MO2___),
+
+                                    // c.subw  ca-type
+                                    C_SUBW => r_type(F7_SUBW, rs2, rd, F3_ASW___, rd, OP_MO4___),
+
+                                    // c.addw  ca-type
+                                    C_ADDW => r_type(F7_ADDW, rs2, rd, F3_ASW___, rd, OP_MO4___),
+
+                                    _      => return None
+                                }
+                            },
+
+                        _ =>
+                            return None
+                    }
+                },
+
+            // c.j  cj-type
+            ( C_Q1___, C_F3_J ) =>
+                j_type(cj_offset(half), 0, OP_JAL),
+
+            // c.beqz  cb-type
+            ( C_Q1___, C_F3_BEQZ ) =>
+                b_type(cb_offset(half), 0, rs1_prime(half), F3_BEQ, OP_BR___),
+
+            // c.bnez  cb-type
+            ( C_Q1___, C_F3_BNEZ ) =>
+                b_type(cb_offset(half), 0, rs1_prime(half), F3_BNE, OP_BR___),
+
+            // c.slli  ci-type
+            ( C_Q2___, C_F3_SLLI ) =>
+                i_type((F7_SLLI << 5) | shamt(half), rd(half), F3_SL___, rd(half), OP_MO1___),
+
+            // c.fldsp  ci-type
+            ( C_Q2___, C_F3_FLDSP ) =>
+                {
+                    i_type(ci_double_sp_offset(half), REG_SP, F3_FLD, rd(half), OP_FLW)
+                },
+
+            // c.lwsp  ci-type
+            ( C_Q2___, C_F3_LWSP ) =>
+                {
+                    if rd(half) == 0
+                    {
+                        return None;
+                    }
+
+                    i_type(ci_word_sp_offset(half), REG_SP, F3_LW, rd(half), OP_LD___)
+                },
+
+            // c.ldsp  ci-type
+            ( C_Q2___, C_F3_LDSP ) =>
+                {
+                    if rd(half) == 0
+                    {
+                        return None;
+                    }
+
+                    i_type(ci_double_sp_offset(half), REG_SP, F3_LD, rd(half), OP_LD___)
+                },
+
+            ( C_Q2___, C_F3_JR_MV___ ) =>
+                {
+                    match ( bit(half, 12), rd(half), rs2(half) )
+                    {
+                        // c.jr  cr-type
+                        ( 0, 0, 0 ) => return None,
+                        ( 0, rs1, 0 ) => i_type(0, rs1, 0, 0, OP_JALR),
+
+                        // c.mv  cr-type
+                        ( 0, rd, rs2 ) => r_type(F7_ADD, rs2, 0, F3_AS___, rd, OP_MO2___),
+
+                        // c.ebreak  cr-type
+                        ( _, 0, 0 ) => i_type(F12_EBREAK, 0, F3_SYS_PRIV___, 0, OP_SYSTEM___),
+
+                        // c.jalr  cr-type
+                        ( _, rs1, 0 ) => i_type(0, rs1, 0, REG_RA, OP_JALR),
+
+                        // c.add  cr-type
+                        ( _, rd, rs2 ) => r_type(F7_ADD, rs2, rd, F3_AS___, rd, OP_MO2___)
+                    }
+                },
+
+            // c.fsdsp  css-type
+            ( C_Q2___, C_F3_FSDSP ) =>
+                {
+                    s_type(css_double_sp_offset(half), rs2(half), REG_SP, F3_FSD, OP_FSW)
+                },
+
+            // c.swsp  css-type
+            ( C_Q2___, C_F3_SWSP ) =>
+                {
+                    s_type(css_word_sp_offset(half), rs2(half), REG_SP, F3_SW, OP_ST___)
+                },
+
+            // c.sdsp  css-type
+            ( C_Q2___, C_F3_SDSP ) =>
+                {
+                    s_type(css_double_sp_offset(half), rs2(half), REG_SP, F3_SD, OP_ST___)
+                },
+
+            _ =>
+                return None
+        };
+
+    Some(expanded)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    // Each compressed instruction against the 32-bit instruction it stands for.
+    #[test]
+    fn expansions()
+    {
+        let table =
+            [
+                ( 0x_0800, 0x_01010413 ),  // c.addi4spn  s0, sp, 16
+                ( 0x_2588, 0x_0085b507 ),  // c.fld       fa0, 8(a1)
+                ( 0x_41c8, 0x_0045a503 ),  // c.lw        a0, 4(a1)
+                ( 0x_7de8, 0x_0f85b503 ),  // c.ld        a0, 248(a1)
+                ( 0x_a988, 0x_00a5b827 ),  // c.fsd       fa0, 16(a1)
+                ( 0x_dde8, 0x_06a5ae23 ),  // c.sw        a0, 124(a1)
+                ( 0x_e588, 0x_00a5b423 ),  // c.sd        a0, 8(a1)
+                ( 0x_0001, 0x_00000013 ),  // c.nop
+                ( 0x_1501, 0x_fe050513 ),  // c.addi      a0, -32
+                ( 0x_2505, 0x_0015051b ),  // c.addiw     a0, 1
+                ( 0x_457d, 0x_01f00513 ),  // c.li        a0, 31
+                ( 0x_7101, 0x_e0010113 ),  // c.addi16sp  sp, -512
+                ( 0x_7501, 0x_fffe0537 ),  // c.lui       a0, 0xfffe0
+                ( 0x_917d, 0x_03f55513 ),  // c.srli      a0, 63
+                ( 0x_8585, 0x_4015d593 ),  // c.srai      a1, 1
+                ( 0x_9a7d, 0x_fff67613 ),  // c.andi      a2, -1
+                ( 0x_8d0d, 0x_40b50533 ),  // c.sub       a0, a1
+                ( 0x_8d2d, 0x_00b54533 ),  // c.xor       a0, a1
+                ( 0x_8d4d, 0x_00b56533 ),  // c.or        a0, a1
+                ( 0x_8d6d, 0x_00b57533 ),  // c.and       a0, a1
+                ( 0x_9d0d, 0x_40b5053b ),  // c.subw      a0, a1
+                ( 0x_9d2d, 0x_00b5053b ),  // c.addw      a0, a1
+                ( 0x_b001, 0x_801ff06f ),  // c.j         -2048
+                ( 0x_d101, 0x_f00500e3 ),  // c.beqz      a0, -256
+                ( 0x_ecfd, 0x_0e049f63 ),  // c.bnez      s1, 254
+                ( 0x_1502, 0x_02051513 ),  // c.slli      a0, 32
+                ( 0x_357e, 0x_1f813507 ),  // c.fldsp     fa0, 504(sp)
+                ( 0x_557e, 0x_0fc12503 ),  // c.lwsp      a0, 252(sp)
+                ( 0x_60a2, 0x_00813083 ),  // c.ldsp      ra, 8(sp)
+                ( 0x_8082, 0x_00008067 ),  // c.jr        ra
+                ( 0x_852e, 0x_00b00533 ),  // c.mv        a0, a1
+                ( 0x_9002, 0x_00100073 ),  // c.ebreak
+                ( 0x_9502, 0x_000500e7 ),  // c.jalr      a0
+                ( 0x_952e, 0x_00b50533 ),  // c.add       a0, a1
+                ( 0x_a42a, 0x_00a13427 ),  // c.fsdsp     fa0, 8(sp)
+                ( 0x_dfaa, 0x_0ea12e23 ),  // c.swsp      a0, 252(sp)
+                ( 0x_ff86, 0x_1e113c23 )   // c.sdsp      ra, 504(sp)
+            ];
+
+        for &( half, expanded ) in &table
+        {
+            assert!(is_compressed(half), "{:04x}", half);
+            assert_eq!(expand(half), Some(expanded), "{:04x}", half);
+        }
+    }
+
+
+    #[test]
+    fn reserved_encodings()
+    {
+        let table =
+            [
+                0x_0000,  // all zeros, c.addi4spn with a zero immediate
+                0x_6101,  // c.addi16sp with a zero immediate
+                0x_6501,  // c.lui with a zero immediate
+                0x_4002,  // c.lwsp into x0
+                0x_6002,  // c.ldsp into x0
+                0x_8002,  // c.jr through x0
+                0x_9c41   // c.subw's neighbour, reserved in RV64
+            ];
+
+        for &half in &table
+        {
+            assert_eq!(expand(half), None, "{:04x}", half);
+        }
+
+        assert!(!is_compressed(0x_0513));
+    }
+}
@@ -1,13 +1,17 @@
 
-use std::convert::TryInto;
-use super::{ opcodes::*, instruction::Instruction };
+use std::{ cell::RefCell, convert::TryInto, rc::Rc };
+use crate::{ bus::Bus, devices::{ Clint, Plic, PLIC_CONTEXT_MACHINE, PLIC_CONTEXT_SUPERVISOR } };
+use super::{ opcodes::*, instruction::Instruction, compressed::*, csr::*, float::*, softfloat::*,
+             trap::*, mmu::{ Tlb, AccessType, PAGE_SIZE }, commit_log::CommitLog };
 
 
-pub const IALIGN: u32 = 32;
-pub const ILEN: u32 = 32;
-pub const XLEN: u32 = 64;
+pub const IALIGN: u32 = 16;
 
+// Load reservations cover a naturally aligned block of this many bytes.
+pub const RESERVATION_SIZE: usize = 8;
 
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
 pub enum PrivilegeLvel
 {
     User       = 0b_00,
@@ -16,97 +20,327 @@ pub enum PrivilegeLvel
 }
 
 
-// TODO: Look at implementing memory as u32s and possibly disabling misaligned reads/writes.
+impl PrivilegeLvel
+{
+    // Decode a privilege level as held in mstatus.MPP, the reserved encoding never gets there.
+    pub fn from_bits(bits: u64) -> Self
+    {
+        match bits & 0b_11
+        {
+            0b_00 => PrivilegeLvel::User,
+            0b_01 => PrivilegeLvel::Supervisor,
+            _     => PrivilegeLvel::Machine
+        }
+    }
+}
+
+
+// A debugger's watchpoint, on the bytes from the address for the length, stopping on the kinds of
+// access given.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatchKind
+{
+    Read,
+    Write,
+    Access
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Watchpoint
+{
+    pub kind: WatchKind,
+    pub address: usize,
+    pub length: usize
+}
+
+
+impl Watchpoint
+{
+    fn is_hit(&self, address: usize, size: usize, access: AccessType) -> bool
+    {
+        let kind = match access
+            {
+                AccessType::Store => WatchKind::Write,
+                _                 => WatchKind::Read
+            };
+
+           (self.kind == kind || self.kind == WatchKind::Access)
+        && address < self.address.wrapping_add(self.length)
+        && self.address < address.wrapping_add(size)
+    }
+}
+
+
+// TODO: Look at implementing memory as u32s.
 
 
 pub struct Cpu
 {
     pub regs: [u64; 31],
+    pub fregs: [u64; 32],
     pub csrs: [u64; 4096],
     pub pc: usize,
-    pub memory: Vec<u8>
+    pub privilege: PrivilegeLvel,
+    pub tlb: Tlb,
+    pub bus: Bus,
+    pub reservation: Option<usize>,
+
+    // Loads and stores that touch a watchpoint leave it here for the debugger to pick up.
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hit: Option<Watchpoint>,
+
+    // Set to trace execution in Spike's format.
+    pub commit_log: Option<CommitLog>,
+
+    // The hart's timer and software interrupts, when there's a CLINT to raise them, and its
+    // external interrupts, when there's a PLIC.
+    pub clint: Option<Rc<RefCell<Clint>>>,
+    pub plic: Option<Rc<RefCell<Plic>>>
 }
 
 
 impl Cpu
 {
-    pub fn new(binary: Vec<u8>) -> Self
+    pub fn new(bus: Bus) -> Self
     {
+        let mut csrs = [0; 4096];
+
+        // Start out in machine mode with the floating-point unit enabled.
+        csrs[CSR_MISA] = MISA;
+        csrs[CSR_MSTATUS] = (XL_64 << MSTATUS_SXL_SHIFT)
+                            | (XL_64 << MSTATUS_UXL_SHIFT)
+                            | ((PrivilegeLvel::Machine as u64) << MSTATUS_MPP_SHIFT)
+                            | MSTATUS_FS_INITIAL;
+
         Self
         {
             regs: [0; 31],
-            csrs: [0; 4096],
+            fregs: [0; 32],
+            csrs,
             pc: 0,
-            memory: binary
+            privilege: PrivilegeLvel::Machine,
+            tlb: Tlb::new(),
+            bus,
+            reservation: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            commit_log: None,
+            clint: None,
+            plic: None
+        }
+    }
+
+
+    // Physical memory accesses go straight to the bus, false when nothing there accepts them.
+    pub fn read_physical(&mut self, address: usize, bytes: &mut [ u8 ]) -> bool
+    {
+        self.bus.read(address as u64, bytes)
+    }
+
+
+    pub fn write_physical(&mut self, address: usize, bytes: &[ u8 ]) -> bool
+    {
+        self.bus.write(address as u64, bytes)
+    }
+
+
+    // The debugger's view of memory, which may cross pages.  An access fails as a whole if any of
+    // it isn't mapped to anything.
+    pub fn read_debug(&mut self, address: usize, bytes: &mut [ u8 ]) -> bool
+    {
+        let mut offset = 0;
+
+        while offset < bytes.len()
+        {
+            let virtual_address = address.wrapping_add(offset);
+            let size = (bytes.len() - offset).min(PAGE_SIZE - virtual_address % PAGE_SIZE);
+
+            match self.debug_translate(virtual_address)
+            {
+                Some(physical) if self.read_physical(physical, &mut bytes[offset..offset + size]) =>
+                    offset += size,
+
+                _ =>
+                    return false
+            }
+        }
+
+        true
+    }
+
+
+    pub fn write_debug(&mut self, address: usize, bytes: &[ u8 ]) -> bool
+    {
+        let mut offset = 0;
+
+        while offset < bytes.len()
+        {
+            let virtual_address = address.wrapping_add(offset);
+            let size = (bytes.len() - offset).min(PAGE_SIZE - virtual_address % PAGE_SIZE);
+
+            match self.debug_translate(virtual_address)
+            {
+                Some(physical) if self.write_physical(physical, &bytes[offset..offset + size]) =>
+                    offset += size,
+
+                _ =>
+                    return false
+            }
         }
+
+        true
     }
 
 
-    fn read_u8(&self, address: usize) -> u8
+    fn check_watchpoints(&mut self, address: usize, size: usize, access: AccessType)
     {
-        self.memory[address]
+        if let Some(watchpoint) = self.watchpoints.iter()
+                                                  .find(|watch| watch.is_hit(address, size, access))
+        {
+            self.watch_hit = Some(*watchpoint);
+        }
     }
 
 
-    fn read_u16(&self, address: usize) -> u16
+    // Virtual memory accesses.  One that straddles two pages is split between their translations,
+    // and both are translated before anything is stored.
+    fn load_bytes<const N: usize>(&mut self, address: usize) -> Result<[ u8; N ], Exception>
     {
-        let slice: &[u8; 2] = &self.memory[address..2].try_into().unwrap_or([0; 2]);
-        u16::from_le_bytes(*slice)
+        let mut bytes = [ 0; N ];
+        let split = N.min(PAGE_SIZE - address % PAGE_SIZE);
+
+        self.check_watchpoints(address, N, AccessType::Load);
+
+        let first = self.translate(address, AccessType::Load)?;
+
+        if !self.read_physical(first, &mut bytes[..split])
+        {
+            return Err(AccessType::Load.access_fault(address));
+        }
+
+        if split < N
+        {
+            let second = self.translate(address.wrapping_add(split), AccessType::Load)?;
+
+            if !self.read_physical(second, &mut bytes[split..])
+            {
+                return Err(AccessType::Load.access_fault(address));
+            }
+        }
+
+        self.log_load(address);
+
+        Ok(bytes)
     }
 
 
-    fn read_u32(&self, address: usize) -> u32
+    fn store_bytes<const N: usize>(&mut self,
+                                   address: usize,
+                                   bytes: [ u8; N ]) -> Result<(), Exception>
     {
-        let slice: &[u8; 4] = &self.memory[address..4].try_into().unwrap_or([0; 4]);
-        u32::from_le_bytes(*slice)
+        let split = N.min(PAGE_SIZE - address % PAGE_SIZE);
+
+        self.check_watchpoints(address, N, AccessType::Store);
+
+        let first = self.translate(address, AccessType::Store)?;
+        let second = if split < N
+            {
+                self.translate(address.wrapping_add(split), AccessType::Store)?
+            }
+            else
+            {
+                0
+            };
+
+        if    !self.write_physical(first, &bytes[..split])
+           || (split < N && !self.write_physical(second, &bytes[split..]))
+        {
+            return Err(AccessType::Store.access_fault(address));
+        }
+
+        self.log_store(address, &bytes);
+
+        Ok(())
     }
 
 
-    fn read_u64(&self, address: usize) -> u64
+    fn read_u8(&mut self, address: usize) -> Result<u8, Exception>
     {
-        let slice: &[u8; 8] = &self.memory[address..8].try_into().unwrap_or([0; 8]);
-        u64::from_le_bytes(*slice)
+        Ok(u8::from_le_bytes(self.load_bytes(address)?))
     }
 
 
-    fn write_u8(&mut self, address: usize, value: u8)
+    fn read_u16(&mut self, address: usize) -> Result<u16, Exception>
     {
-        self.memory[address] = value;
+        Ok(u16::from_le_bytes(self.load_bytes(address)?))
     }
 
 
-    fn write_u16(&mut self, address: usize, value: u16)
+    fn read_u32(&mut self, address: usize) -> Result<u32, Exception>
     {
-        let bytes = value.to_le_bytes();
+        Ok(u32::from_le_bytes(self.load_bytes(address)?))
+    }
+
 
-        self.memory[address    ] = bytes[0];
-        self.memory[address + 1] = bytes[1];
+    fn read_u64(&mut self, address: usize) -> Result<u64, Exception>
+    {
+        Ok(u64::from_le_bytes(self.load_bytes(address)?))
     }
 
 
-    fn write_u32(&mut self, address: usize, value: u32)
+    fn write_u8(&mut self, address: usize, value: u8) -> Result<(), Exception>
     {
-        let bytes = value.to_le_bytes();
+        self.invalidate_reservation(address, 1);
+        self.store_bytes(address, value.to_le_bytes())
+    }
+
 
-        self.memory[address    ] = bytes[0];
-        self.memory[address + 1] = bytes[1];
-        self.memory[address + 2] = bytes[2];
-        self.memory[address + 3] = bytes[3];
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), Exception>
+    {
+        self.invalidate_reservation(address, 2);
+        self.store_bytes(address, value.to_le_bytes())
     }
 
 
-    fn write_u64(&mut self, address: usize, value: u64)
+    fn write_u32(&mut self, address: usize, value: u32) -> Result<(), Exception>
     {
-        let bytes = value.to_le_bytes();
+        self.invalidate_reservation(address, 4);
+        self.store_bytes(address, value.to_le_bytes())
+    }
+
 
-        self.memory[address    ] = bytes[0];
-        self.memory[address + 1] = bytes[1];
-        self.memory[address + 2] = bytes[2];
-        self.memory[address + 3] = bytes[3];
-        self.memory[address + 4] = bytes[4];
-        self.memory[address + 5] = bytes[5];
-        self.memory[address + 6] = bytes[6];
-        self.memory[address + 7] = bytes[7];
+    fn write_u64(&mut self, address: usize, value: u64) -> Result<(), Exception>
+    {
+        self.invalidate_reservation(address, 8);
+        self.store_bytes(address, value.to_le_bytes())
+    }
+
+
+
+    fn reserve(&mut self, address: usize)
+    {
+        self.reservation = Some(address & !(RESERVATION_SIZE - 1));
+    }
+
+
+    fn is_reserved(&self, address: usize) -> bool
+    {
+        self.reservation == Some(address & !(RESERVATION_SIZE - 1))
+    }
+
+
+    // Any store that touches the reserved block causes a following store-conditional to fail.
+    fn invalidate_reservation(&mut self, address: usize, size: usize)
+    {
+        if let Some(reserved) = self.reservation
+        {
+            if    address < reserved.wrapping_add(RESERVATION_SIZE)
+               && reserved < address.wrapping_add(size)
+            {
+                self.reservation = None;
+            }
+        }
     }
 
 
@@ -157,399 +391,1041 @@ impl Cpu
     }
 
 
-    fn address_from_bt(&self, base: usize, instruction: &Instruction) -> usize
+    // Single-precision values live NaN-boxed in the 64-bit floating-point registers, they're
+    // handled as raw bit patterns.
+    fn read_f32_reg(&self, index: usize) -> u64
+    {
+        unbox_f32(self.fregs[index])
+    }
+
+
+    fn write_f32_reg(&mut self, index: usize, value: u64)
+    {
+        self.fregs[index] = box_f32(value);
+    }
+
+
+    fn f32_values_from_registers(&self, instruction: &Instruction) -> ( u64, u64 )
+    {
+        ( self.read_f32_reg(instruction.rs1), self.read_f32_reg(instruction.rs2) )
+    }
+
+
+    fn f32_values_from_registers_r4(&self, instruction: &Instruction) -> ( u64, u64, u64 )
+    {
+        ( self.read_f32_reg(instruction.rs1),
+          self.read_f32_reg(instruction.rs2),
+          self.read_f32_reg(instruction.rs3) )
+    }
+
+
+    fn f64_values_from_registers(&self, instruction: &Instruction) -> ( u64, u64 )
+    {
+        ( self.fregs[instruction.rs1], self.fregs[instruction.rs2] )
+    }
+
+
+    fn f64_values_from_registers_r4(&self, instruction: &Instruction) -> ( u64, u64, u64 )
+    {
+        ( self.fregs[instruction.rs1], self.fregs[instruction.rs2], self.fregs[instruction.rs3] )
+    }
+
+
+    // The rm field either names a rounding mode directly or defers to the one held in frm.  A
+    // reserved mode, reached either way, makes the instruction illegal.
+    fn rounding_mode(&self, instruction: &Instruction) -> Result<u32, Exception>
     {
-        let base = base as u64;
-        let offset = instruction.bt_immediate() as i64;
-        let address = if offset >= 0
+        let mode = if instruction.func3 == RM_DYN
             {
-                base + offset as u64
+                self.csrs[CSR_FRM] as u32
             }
             else
             {
-                base - (offset.abs() as u64)
+                instruction.func3
             };
 
-        address as usize
+        match mode
+        {
+            RM_RNE..=RM_RMM => Ok(mode),
+            _               => Err(Exception::IllegalInstruction(instruction.encoding))
+        }
+    }
+
+
+    // fflags and frm are both views of fcsr, so all three are kept in step.
+    fn accrue_fflags(&mut self, flags: u32)
+    {
+        self.csrs[CSR_FFLAGS] |= flags as u64 & FCSR_FFLAGS_MASK;
+        self.csrs[CSR_FCSR] = (self.csrs[CSR_FRM] << FCSR_FRM_SHIFT) | self.csrs[CSR_FFLAGS];
+
+        if flags != 0
+        {
+            self.log_csr(CSR_FFLAGS);
+        }
+    }
+
+
+    // Evaluate a floating-point operation in software using the instruction's rounding mode, any
+    // exceptions it raises are accrued into fflags.
+    fn float_op<T>(&mut self,
+                   instruction: &Instruction,
+                   operation: impl FnOnce(&mut SoftFloat) -> T) -> Result<T, Exception>
+    {
+        let mut float = SoftFloat::new(self.rounding_mode(instruction)?);
+        let result = operation(&mut float);
+
+        self.accrue_fflags(float.flags);
+        Ok(result)
+    }
+
+
+    // The atomic instructions require naturally aligned addresses.
+    fn atomic_address(&self,
+                      instruction: &Instruction,
+                      size: usize,
+                      fault: fn(u64) -> Exception) -> Result<usize, Exception>
+    {
+        let address = self.read_gp_reg(instruction.rs1) as usize;
+
+        if address.is_multiple_of(size)
+        {
+            Ok(address)
+        }
+        else
+        {
+            Err(fault(address as u64))
+        }
+    }
+
+
+    // Atomic read-modify-write of a word in memory, rd receives the original value, sign extended.
+    fn atomic_word(&mut self,
+                   instruction: &Instruction,
+                   operation: impl Fn(u32, u32) -> u32) -> Result<(), Exception>
+    {
+        let address = self.atomic_address(instruction, 4, Exception::StoreAddressMisaligned)?;
+        let rs2 = self.read_gp_reg(instruction.rs2) as u32;
+        let value = self.read_u32(address).map_err(Exception::as_store_fault)?;
+
+        self.write_u32(address, operation(value, rs2))?;
+        self.write_gp_reg(instruction.rd, value as i32 as i64 as u64);
+
+        Ok(())
+    }
+
+
+    // Atomic read-modify-write of a double-word in memory, rd receives the original value.
+    fn atomic_double(&mut self,
+                     instruction: &Instruction,
+                     operation: impl Fn(u64, u64) -> u64) -> Result<(), Exception>
+    {
+        let address = self.atomic_address(instruction, 8, Exception::StoreAddressMisaligned)?;
+        let rs2 = self.read_gp_reg(instruction.rs2);
+        let value = self.read_u64(address).map_err(Exception::as_store_fault)?;
+
+        self.write_u64(address, operation(value, rs2))?;
+        self.write_gp_reg(instruction.rd, value);
+
+        Ok(())
+    }
+
+
+    // csrrw and csrrwi always write the CSR, but skip the read and its side effects when rd is x0.
+    fn csr_write_op(&mut self,
+                    instruction: &Instruction,
+                    operation: impl FnOnce(u64) -> u64) -> Option<()>
+    {
+        let address = instruction.func12 as usize;
+
+        let value = if instruction.rd == 0 { 0 } else { self.read_csr(address)? };
+
+        self.write_csr(address, operation(value))?;
+        self.log_csr(address);
+        self.write_gp_reg(instruction.rd, value);
+
+        Some(())
+    }
+
+
+    // The set and clear forms always read the CSR, but don't write it when rs1 (or the immediate)
+    // is zero.  That way read-only CSRs can still be read with them.
+    fn csr_set_op(&mut self,
+                  instruction: &Instruction,
+                  operation: impl FnOnce(u64) -> u64) -> Option<()>
+    {
+        let address = instruction.func12 as usize;
+        let value = self.read_csr(address)?;
+
+        if instruction.rs1 != 0
+        {
+            self.write_csr(address, operation(value))?;
+            self.log_csr(address);
+        }
+
+        self.write_gp_reg(instruction.rd, value);
+
+        Some(())
+    }
+
+
+    fn address_from_bt(&self, instruction: &Instruction) -> usize
+    {
+        self.instruction_address(instruction).wrapping_add(instruction.bt_immediate() as usize)
     }
 
 
     fn address_from_it(&self, instruction: &Instruction) -> usize
     {
-        let base = self.read_gp_reg(instruction.rs1);
-        let offset = instruction.it_immediate() as i64;
-        let address = if offset >= 0
-            {
-                base + offset as u64
-            }
-            else
+        self.read_gp_reg(instruction.rs1).wrapping_add(instruction.it_immediate()) as usize
+    }
+
+
+    fn address_from_st(&self, instruction: &Instruction) -> usize
+    {
+        self.read_gp_reg(instruction.rs1).wrapping_add(instruction.st_immediate()) as usize
+    }
+
+
+    // The address of the instruction being executed, the pc has already moved past it.
+    fn instruction_address(&self, instruction: &Instruction) -> usize
+    {
+        self.pc - instruction.length
+    }
+
+
+    fn address_from_jt(&self, instruction: &Instruction) -> usize
+    {
+        self.instruction_address(instruction).wrapping_add(instruction.jt_immediate() as usize)
+    }
+
+
+    fn address_from_ut(&self, instruction: &Instruction) -> usize
+    {
+        self.instruction_address(instruction).wrapping_add(instruction.ut_immediate() as usize)
+    }
+
+
+    // Instructions are fetched a parcel of 16 bits at a time, as a full size instruction that isn't
+    // 32-bit aligned can straddle two pages.  Compressed instructions are expanded as they're
+    // fetched.
+    pub fn fetch(&mut self) -> Result<Instruction, Exception>
+    {
+        if !self.pc.is_multiple_of(IALIGN as usize / 8)
+        {
+            return Err(Exception::InstructionAddressMisaligned(self.pc as u64));
+        }
+
+        let low = self.fetch_parcel(self.pc)?;
+
+        if is_compressed(low)
+        {
+            return expand(low).map(|expanded| Instruction::compressed(low, expanded))
+                              .ok_or(Exception::IllegalInstruction(low as u32));
+        }
+
+        let high = self.fetch_parcel(self.pc.wrapping_add(2))?;
+
+        Ok(Instruction::new(((high as u32) << 16) | low as u32))
+    }
+
+
+    fn fetch_parcel(&mut self, pc: usize) -> Result<u16, Exception>
+    {
+        let address = self.translate(pc, AccessType::Instruction)?;
+        let mut bytes = [ 0; 2 ];
+
+        if !self.read_physical(address, &mut bytes)
+        {
+            return Err(AccessType::Instruction.access_fault(pc));
+        }
+
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+
+    // Run a single instruction.  If it raises an exception the pc is left pointing at it, ready for
+    // the trap to be taken.
+    pub fn step(&mut self) -> Result<(), Exception>
+    {
+        let pc = self.pc;
+        let privilege = self.privilege;
+
+        let result = self.fetch().and_then(|instruction|
             {
-                base - (offset.abs() as u64)
-            };
+                self.log_fetch(pc, &instruction);
+
+                self.pc += instruction.length;
+                self.execute(&instruction)?;
 
-        address as usize
+                self.log_commit(pc, privilege, &instruction);
+                Ok(())
+            });
+
+        self.tick();
+
+        result.inspect_err(|&exception|
+            {
+                self.pc = pc;
+                self.log_exception(exception);
+            })
     }
 
 
-    fn address_from_st(&self, instruction: &Instruction) -> usize
+    // Time moves on by one tick per instruction, and the CLINT's and PLIC's outputs are reflected
+    // in mip.  With a PLIC, SEIP follows its supervisor context and software can't set it.
+    fn tick(&mut self)
     {
-        let base = self.read_gp_reg(instruction.rs1);
-        let offset = instruction.st_immediate() as i64;
-        let address = if offset >= 0
+        let mut driven = 0;
+        let mut pending = 0;
+
+        if let Some(clint) = &self.clint
+        {
+            let mut clint = clint.borrow_mut();
+
+            clint.tick();
+            driven |= MIP_MTIP | MIP_MSIP;
+
+            if clint.timer_pending()
             {
-                base + offset as u64
+                pending |= MIP_MTIP;
             }
-            else
+
+            if clint.software_pending()
             {
-                base - (offset.abs() as u64)
-            };
+                pending |= MIP_MSIP;
+            }
+        }
 
-        address as usize
-    }
+        if let Some(plic) = &self.plic
+        {
+            let mut plic = plic.borrow_mut();
 
+            plic.update();
+            driven |= MIP_MEIP | MIP_SEIP;
 
-    fn address_from_jt(&self, instruction: &Instruction) -> usize
-    {
-        let base = self.pc as u64 - 4;
-        let offset = instruction.jt_immediate() as i64;
-        let address = if offset >= 0
+            if plic.context_pending(PLIC_CONTEXT_MACHINE)
             {
-                base + offset as u64
+                pending |= MIP_MEIP;
             }
-            else
+
+            if plic.context_pending(PLIC_CONTEXT_SUPERVISOR)
             {
-                base - (offset.abs() as u64)
-            };
+                pending |= MIP_SEIP;
+            }
+        }
 
-        address as usize
+        self.csrs[CSR_MIP] = (self.csrs[CSR_MIP] & !driven) | pending;
     }
 
 
-    fn address_from_ut(&self, instruction: &Instruction) -> usize
+    // The interrupt to take before the next instruction, if any.  Interrupts for a more privileged
+    // level than the current one are always taken, those for the current level only when its
+    // interrupt enable is set in mstatus, and those for a less privileged level never are.
+    pub fn pending_interrupt(&self) -> Option<Interrupt>
+    {
+        let pending = self.csrs[CSR_MIP] & self.csrs[CSR_MIE];
+
+        if pending == 0
+        {
+            return None;
+        }
+
+        let mstatus = self.csrs[CSR_MSTATUS];
+        let mideleg = self.csrs[CSR_MIDELEG];
+
+        let machine_enabled =    self.privilege < PrivilegeLvel::Machine
+                              || mstatus & MSTATUS_MIE != 0;
+
+        let supervisor_enabled =    self.privilege < PrivilegeLvel::Supervisor
+                                 || (   self.privilege == PrivilegeLvel::Supervisor
+                                     && mstatus & MSTATUS_SIE != 0);
+
+        let mut enabled = 0;
+
+        if machine_enabled
+        {
+            enabled |= pending & !mideleg;
+        }
+
+        if supervisor_enabled
+        {
+            enabled |= pending & mideleg;
+        }
+
+        INTERRUPT_PRIORITY.iter().copied().find(|interrupt| enabled & (1 << interrupt.code()) != 0)
+    }
+
+
+    // Enter the trap handler for an exception.  Exceptions raised below machine mode go to
+    // supervisor mode when medeleg delegates them, everything else is taken in machine mode.  The
+    // exception pc holds the address of the instruction that raised the exception.
+    pub fn trap(&mut self, exception: Exception)
+    {
+        let delegated = (self.csrs[CSR_MEDELEG] >> exception.cause()) & 1 != 0;
+
+        // Exceptions always go to the vector base, only interrupts are offset by their cause in
+        // vectored mode.
+        let tvec = self.enter_trap(delegated, exception.cause(), exception.tval());
+
+        self.pc = (tvec & !MTVEC_MODE_MASK) as usize;
+    }
+
+
+    // Enter the trap handler for an interrupt, delegated by mideleg.  The exception pc holds the
+    // address of the next instruction to run.
+    pub fn interrupt(&mut self, interrupt: Interrupt)
     {
-        let base = self.pc as u64 - 4;
-        let offset = instruction.ut_immediate() as i64;
-        let address = if offset >= 0
+        self.log_interrupt(interrupt);
+
+        let delegated = (self.csrs[CSR_MIDELEG] >> interrupt.code()) & 1 != 0;
+        let tvec = self.enter_trap(delegated, interrupt.cause(), 0);
+
+        let base = tvec & !MTVEC_MODE_MASK;
+
+        let handler = if tvec & MTVEC_MODE_MASK == MTVEC_VECTORED
             {
-                base + offset as u64
+                base + 4 * interrupt.code()
             }
             else
             {
-                base - offset.abs() as u64
+                base
             };
 
-        address as usize
+        self.pc = handler as usize;
+    }
+
+
+    // Record the trap in the CSRs of the level taking it, and stack the interrupt enable and
+    // previous privilege in mstatus.  Nothing is ever delegated away from machine mode.  Gives the
+    // tvec to go to.  A trap gives up any load reservation, so a store-conditional can't succeed
+    // across a context switch.
+    fn enter_trap(&mut self, delegated: bool, cause: u64, tval: u64) -> u64
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+        let previous = self.privilege as u64;
+
+        self.reservation = None;
+
+        if delegated && self.privilege <= PrivilegeLvel::Supervisor
+        {
+            let spie = if mstatus & MSTATUS_SIE != 0 { MSTATUS_SPIE } else { 0 };
+
+            self.csrs[CSR_MSTATUS] = (mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP))
+                                     | spie
+                                     | (previous << MSTATUS_SPP_SHIFT);
+
+            self.csrs[CSR_SEPC] = self.pc as u64;
+            self.csrs[CSR_SCAUSE] = cause;
+            self.csrs[CSR_STVAL] = tval;
+
+            self.privilege = PrivilegeLvel::Supervisor;
+            self.csrs[CSR_STVEC]
+        }
+        else
+        {
+            let mpie = if mstatus & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+
+            self.csrs[CSR_MSTATUS] = (mstatus & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP))
+                                     | mpie
+                                     | (previous << MSTATUS_MPP_SHIFT);
+
+            self.csrs[CSR_MEPC] = self.pc as u64;
+            self.csrs[CSR_MCAUSE] = cause;
+            self.csrs[CSR_MTVAL] = tval;
+
+            self.privilege = PrivilegeLvel::Machine;
+            self.csrs[CSR_MTVEC]
+        }
+    }
+
+
+    // mret unwinds the machine-mode stack in mstatus: the interrupt enable is restored, the hart
+    // drops back to the privilege held in MPP, and MPP is left at the least privileged level.  As
+    // with a trap, the load reservation is given up.
+    fn machine_return(&mut self)
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+        let privilege = PrivilegeLvel::from_bits(mstatus >> MSTATUS_MPP_SHIFT);
+        let mie = if mstatus & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+
+        let mut mstatus = (mstatus & !(MSTATUS_MIE | MSTATUS_MPP)) | mie | MSTATUS_MPIE;
+
+        if privilege != PrivilegeLvel::Machine
+        {
+            mstatus &= !MSTATUS_MPRV;
+        }
+
+        self.csrs[CSR_MSTATUS] = mstatus;
+        self.log_csr(CSR_MSTATUS);
+
+        self.privilege = privilege;
+        self.reservation = None;
+        self.pc = self.csrs[CSR_MEPC] as usize;
+    }
+
+
+    // sret does the same with the supervisor-mode fields.
+    fn supervisor_return(&mut self)
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+        let privilege = PrivilegeLvel::from_bits((mstatus & MSTATUS_SPP) >> MSTATUS_SPP_SHIFT);
+        let sie = if mstatus & MSTATUS_SPIE != 0 { MSTATUS_SIE } else { 0 };
+
+        self.csrs[CSR_MSTATUS] = (mstatus & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV))
+                                 | sie
+                                 | MSTATUS_SPIE;
+        self.log_csr(CSR_MSTATUS);
+
+        self.privilege = privilege;
+        self.reservation = None;
+        self.pc = self.csrs[CSR_SEPC] as usize;
     }
 
 
-    pub fn fetch(&self) -> Instruction
+    // Control transfers must land on an instruction boundary.
+    fn jump(&mut self, target: usize) -> Result<(), Exception>
     {
-        // TODO: self.pc must be 32-bit aligned addresses, instruction-address-misaligned exception.
-        Instruction::new(self.read_u32(self.pc))
+        if !target.is_multiple_of(IALIGN as usize / 8)
+        {
+            return Err(Exception::InstructionAddressMisaligned(target as u64));
+        }
+
+        self.pc = target;
+        Ok(())
     }
 
 
-    pub fn execute(&mut self, instruction: &Instruction)
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), Exception>
     {
+        let illegal = Exception::IllegalInstruction(instruction.encoding);
+
+        // Floating-point instructions are illegal while the floating-point unit is off.
+        if is_float_opcode(instruction.opcode)
+        {
+            if !self.float_enabled()
+            {
+                return Err(illegal);
+            }
+
+            self.mark_float_dirty();
+        }
+
         match ( instruction.func7, instruction.func3, instruction.opcode )
         {
             // RV32D Standard Extension
 
             // fld  i-type
-            ( F3_FLD, _, OP_FLW ) =>
+            ( _, F3_FLD, OP_FLW ) =>
                 {
+                    let address = self.address_from_it(instruction);
+                    self.fregs[instruction.rd] = self.read_u64(address)?;
                 },
 
             // fsd  s-type
-            ( F3_FSD, _, OP_FSW ) =>
+            ( _, F3_FSD, OP_FSW ) =>
                 {
+                    let address = self.address_from_st(instruction);
+                    self.write_u64(address, self.fregs[instruction.rs2])?;
                 },
 
             // fmadd.d  r4-type
             ( func7, _, OP_FMADD_S___ ) if (func7 & RS3_MASK) == F7_FMADD_D =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f64_values_from_registers_r4(instruction);
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F64, rs1, rs2, rs3))?;
+
+                    self.fregs[instruction.rd] = result;
                 },
 
             // fmsub.d  r4-type
             ( func7, _, OP_FMSUB_S___ ) if (func7 & RS3_MASK) == F7_FMSUB_D =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f64_values_from_registers_r4(instruction);
+                    let rs3 = rs3 ^ F64_SIGN_BIT;
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F64, rs1, rs2, rs3))?;
+
+                    self.fregs[instruction.rd] = result;
                 },
 
             // fnmsub.d  r4-type
             ( func7, _, OP_FNMSUB_S___ ) if (func7 & RS3_MASK) == F7_FNMSUB_D =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f64_values_from_registers_r4(instruction);
+                    let rs1 = rs1 ^ F64_SIGN_BIT;
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F64, rs1, rs2, rs3))?;
+
+                    self.fregs[instruction.rd] = result;
                 },
 
             // fnmadd.d  r4-type
             ( func7, _, OP_FNMADD_S___ ) if (func7 & RS3_MASK) == F7_FNMADD_D =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f64_values_from_registers_r4(instruction);
+                    let ( rs1, rs3 ) = ( rs1 ^ F64_SIGN_BIT, rs3 ^ F64_SIGN_BIT );
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F64, rs1, rs2, rs3))?;
+
+                    self.fregs[instruction.rd] = result;
                 },
 
             // fadd.d  r-type
             ( F7_FADD_D, _, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.add(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fsub.d  r-type
             ( F7_FSUB_D, _, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.sub(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fmul.d  r-type
             ( F7_FMUL_D, _, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.mul(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fdiv.d  r-type
             ( F7_FDIV_D, _, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.div(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fsqrt.d  r-type
             ( F7_FSQRT_D, _, OP_RV_F___ ) if instruction.rs2 == RS2_F7_FSQRT_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.sqrt(&F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fsgnj.d  r-type
             ( F7_FSGNJ_D___, F3_FSGNJ_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = (rs1 & !F64_SIGN_BIT) | (rs2 & F64_SIGN_BIT);
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fsgnjn.d  r-type
             ( F7_FSGNJ_D___, F3_FSGNJN_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = (rs1 & !F64_SIGN_BIT) | (!rs2 & F64_SIGN_BIT);
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fsgnjx.d  r-type
             ( F7_FSGNJ_D___, F3_FSGNJX_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = rs1 ^ (rs2 & F64_SIGN_BIT);
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fmin.d  r-type
             ( F7_FSMM_D___, F3_FMIN_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.min(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fmax.d  r-type
             ( F7_FSMM_D___, F3_FMAX_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.max(&F64, rs1, rs2))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fcvt.s.d  r-type
             ( F7_FCVT_S_D___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_S_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.convert(&F64, &F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
+                },
 
             // fcvt.d.s  r-type
             ( F7_FCVT_D_S___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_D_S =>
-            {
-            },
+                {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.convert(&F32, &F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // feq.d  r-type
             ( F7_F_QLT_D___, F3_FEQ_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.eq(&F64, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
+                },
 
             // flt.d  r-type
             ( F7_F_QLT_D___, F3_FLT_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.lt(&F64, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
+                },
 
             // fle.d  r-type
             ( F7_F_QLT_D___, F3_FLE_D, OP_RV_F___ ) =>
-            {
-            },
+                {
+                    let ( rs1, rs2 ) = self.f64_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.le(&F64, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
+                },
 
             // fclass.d  r-type
             ( F7_FCLASS_D___, F3_FCLASS_D___, OP_RV_F___ ) if instruction.rs2 == RS2_FCLASS_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    self.write_gp_reg(instruction.rd, classify(&F64, rs1));
+                },
 
             // fcvt.w.d  r-type
             ( F7_FCVT1___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_W_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.float_to_i32(&F64, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as i64 as u64);
+                },
 
             // fcvt.wu.d  r-type
             ( F7_FCVT1___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_WU_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.float_to_u32(&F64, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as i32 as i64 as u64);
+                },
 
             // fcvt.d.w  r-type
             ( F7_FCVT2___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_D_W =>
-            {
-            },
+                {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as i32 as i64;
+                    let result = self.float_op(instruction, |fp| fp.i64_to_float(&F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fcvt.d.wu  r-type
             ( F7_FCVT2___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_D_WU =>
-            {
-            },
+                {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as u32 as u64;
+                    let result = self.float_op(instruction, |fp| fp.u64_to_float(&F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
 
             // RV64D Standard Extension (in addition to RV32D)
 
             // fcvt.l.d  r-type
             ( F7_FCVT1___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_L_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.float_to_i64(&F64, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as u64);
+                },
 
             // fcvt.lu.d  r-type
             ( F7_FCVT1___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_LU_D =>
-            {
-            },
+                {
+                    let rs1 = self.fregs[instruction.rs1];
+                    let result = self.float_op(instruction, |fp| fp.float_to_u64(&F64, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result);
+                },
 
             // fmv.x.d  r-type
             ( F7_FMV_X_D___, F3_FMV_X_D___, OP_RV_F___ ) if instruction.rs2 == RS2_FMV_X_D =>
-            {
-            },
+                {
+                    self.write_gp_reg(instruction.rd, self.fregs[instruction.rs1]);
+                },
 
             // fcvt.d.l  r-type
             ( F7_FCVT2___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_D_L =>
-            {
-            },
+                {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as i64;
+                    let result = self.float_op(instruction, |fp| fp.i64_to_float(&F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fcvt.d.lu  r-type
             ( F7_FCVT2___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_D_LU =>
-            {
-            },
+                {
+                    let rs1 = self.read_gp_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.u64_to_float(&F64, rs1))?;
+
+                    self.fregs[instruction.rd] = result;
+                },
 
             // fmv.d.x  r-type
             ( F7_FMV_D_X___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FMV_D_X =>
-            {
-            },
+                {
+                    self.fregs[instruction.rd] = self.read_gp_reg(instruction.rs1);
+                },
 
 
 
             // RV32F Standard Extension
 
             // flw  i-type
-            ( _, _, OP_FLW ) =>
+            ( _, F3_FLW, OP_FLW ) =>
                 {
+                    let address = self.address_from_it(instruction);
+                    let value = self.read_u32(address)? as u64;
+
+                    self.write_f32_reg(instruction.rd, value);
                 },
 
             // fsw  s-type
-            ( _, _, OP_FSW ) =>
+            ( _, F3_FSW, OP_FSW ) =>
                 {
+                    let address = self.address_from_st(instruction);
+                    self.write_u32(address, self.fregs[instruction.rs2] as u32)?;
                 },
 
             // fmadd.s  r4-type
             ( func7, _, OP_FMADD_S___ ) if (func7 & RS3_MASK) == F7_FMADD_S =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f32_values_from_registers_r4(instruction);
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F32, rs1, rs2, rs3))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fmsub.s  r4-type
             ( func7, _, OP_FMSUB_S___ ) if (func7 & RS3_MASK) == F7_FMSUB_S =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f32_values_from_registers_r4(instruction);
+                    let rs3 = rs3 ^ F32_SIGN_BIT;
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F32, rs1, rs2, rs3))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fnmsub.s  r4-type
-            ( _, _, OP_FNMSUB_S___ ) if (instruction.func7 & RS3_MASK) == F7_FNMSUB_S =>
+            ( func7, _, OP_FNMSUB_S___ ) if (func7 & RS3_MASK) == F7_FNMSUB_S =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f32_values_from_registers_r4(instruction);
+                    let rs1 = rs1 ^ F32_SIGN_BIT;
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F32, rs1, rs2, rs3))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fnmadd.s  r4-type
-            ( _, _, OP_FNMADD_S___ ) if (instruction.func7 & RS3_MASK) == F7_FNMADD_S =>
+            ( func7, _, OP_FNMADD_S___ ) if (func7 & RS3_MASK) == F7_FNMADD_S =>
                 {
+                    let ( rs1, rs2, rs3 ) = self.f32_values_from_registers_r4(instruction);
+                    let ( rs1, rs3 ) = ( rs1 ^ F32_SIGN_BIT, rs3 ^ F32_SIGN_BIT );
+                    let result = self.float_op(instruction, |fp| fp.mul_add(&F32, rs1, rs2, rs3))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fadd.s  r-type
             ( F7_FADD_S, _, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.add(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fsub.s  r-type
             ( F7_FSUB_S, _, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.sub(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fmul.s  r-type
             ( F7_FMUL_S, _, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.mul(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fdiv.s  r-type
             ( F7_FDIV_S, _, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.div(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fsqrt.s  r-type
             ( F7_FSQRT_S, _, OP_RV_F___ ) if instruction.rs2 == RS2_F7_FSQRT_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.sqrt(&F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fsgnj.s  r-type
             ( F7_FSGNJ___, F3_FSGNJ_S, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = (rs1 & !F32_SIGN_BIT) | (rs2 & F32_SIGN_BIT);
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fsgnjn.s  r-type
             ( F7_FSGNJ___, F3_FSGNJN_S, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = (rs1 & !F32_SIGN_BIT) | (!rs2 & F32_SIGN_BIT);
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fsgnjx.s  r-type
             ( F7_FSGNJ___, F3_FSGNJX_S, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = rs1 ^ (rs2 & F32_SIGN_BIT);
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fmin.s  r-type
             ( F7_FSMM___, F3_FMIN_S, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.min(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fmax.s  r-type
             ( F7_FSMM___, F3_FMAX_S, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.max(&F32, rs1, rs2))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fcvt.w.s  r-type
-            ( F7_FCVT_W___, F3_FCVT_W_S, OP_RV_F___ ) =>
+            ( F7_FCVT_W___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_W_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.float_to_i32(&F32, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as i64 as u64);
                 },
 
             // fcvt.wu.s  r-type
-            ( F7_FCVT_W___, F3_FCVT_WU_S, OP_RV_F___ ) =>
+            ( F7_FCVT_W___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_WU_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.float_to_u32(&F32, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as i32 as i64 as u64);
                 },
 
             // fmv.x.w  r-type
             ( F7_FMV_X_W, F3_32_FMV___, OP_RV_F___ ) if instruction.rs2 == RS2_FMV_X_W =>
                 {
+                    let result = self.fregs[instruction.rs1] as i32;
+                    self.write_gp_reg(instruction.rd, result as i64 as u64);
                 },
 
             // feq.s  r-type
-            ( F7_FLE_S, F3_32_FMV___, OP_RV_F___ ) =>
+            ( F7_FEQ_S, F3_32_FE___, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.eq(&F32, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
                 },
 
             // flt.s  r-type
-            ( F7_FMV_W_X, F3_32_FMV___, OP_RV_F___ ) if instruction.rs2 == RS2_FMV_W_X =>
+            ( F7_FLT_S, F3_32_FLT___, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.lt(&F32, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
                 },
 
             // fle.s  r-type
-            ( F7_FEQ_S, F3_32_FE___, OP_RV_F___ ) =>
+            ( F7_FLE_S, F3_32_FMV___, OP_RV_F___ ) =>
                 {
+                    let ( rs1, rs2 ) = self.f32_values_from_registers(instruction);
+                    let result = self.float_op(instruction, |fp| fp.le(&F32, rs1, rs2))?;
+
+                    self.write_gp_reg(instruction.rd, if result { 1 } else { 0 });
                 },
 
             // fclass.s  r-type
-            ( F7_FLT_S, F3_32_FLT___, OP_RV_F___ ) =>
+            ( F7_FCLASS_S, F3_32_FC___, OP_RV_F___ ) if instruction.rs2 == RS2_FCLASS_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    self.write_gp_reg(instruction.rd, classify(&F32, rs1));
                 },
 
             // fcvt.s.w  r-type
-            ( F7_FCLASS_S, F3_32_FC___, OP_RV_F___ ) if instruction.rs2 == RS2_FCLASS_S =>
+            ( F7_FCVT_S___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_S_W =>
                 {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as i32 as i64;
+                    let result = self.float_op(instruction, |fp| fp.i64_to_float(&F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fcvt.s.wu  r-type
-            ( F7_FCVT_S___, F3_FCVT_S_W, OP_RV_F___ ) =>
+            ( F7_FCVT_S___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_S_WU =>
                 {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as u32 as u64;
+                    let result = self.float_op(instruction, |fp| fp.u64_to_float(&F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fmv.w.x  r-type
-            ( F7_FCVT_S___, F3_FCVT_S_WU, OP_RV_F___ ) =>
+            ( F7_FMV_W_X, F3_32_FMV___, OP_RV_F___ ) if instruction.rs2 == RS2_FMV_W_X =>
                 {
+                    self.write_f32_reg(instruction.rd, self.read_gp_reg(instruction.rs1));
                 },
 
             // RV64F Standard Extension (in addition to RV32F)
@@ -557,21 +1433,37 @@ impl Cpu
             // fcvt.l.s  * r-type
             ( F7_FCVT_W___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_L_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.float_to_i64(&F32, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result as u64);
                 },
 
             // fcvt.lu.s  * r-type
             ( F7_FCVT_W___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_LU_S =>
                 {
+                    let rs1 = self.read_f32_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.float_to_u64(&F32, rs1))?;
+
+                    self.write_gp_reg(instruction.rd, result);
                 },
 
             // fcvt.s.l  * r-type
             ( F7_FCVT_S___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_S_L =>
                 {
+                    let rs1 = self.read_gp_reg(instruction.rs1) as i64;
+                    let result = self.float_op(instruction, |fp| fp.i64_to_float(&F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
             // fcvt.s.lu  * r-type
             ( F7_FCVT_S___, _, OP_RV_F___ ) if instruction.rs2 == RS2_FCVT_S_LU =>
                 {
+                    let rs1 = self.read_gp_reg(instruction.rs1);
+                    let result = self.float_op(instruction, |fp| fp.u64_to_float(&F32, rs1))?;
+
+                    self.write_f32_reg(instruction.rd, result);
                 },
 
 
@@ -584,56 +1476,89 @@ impl Cpu
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if    (func7 & EXT_A_F7_MASK == F7_LR_W___)
                                                      && (instruction.rs2 == RS2_LR_W) =>
                 {
+                    let address = self.atomic_address(instruction,
+                                                      4,
+                                                      Exception::LoadAddressMisaligned)?;
+                    let value = self.read_u32(address)? as i32 as i64 as u64;
+
+                    self.reserve(address);
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // sc.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_SC_W =>
                 {
+                    let address = self.atomic_address(instruction,
+                                                      4,
+                                                      Exception::StoreAddressMisaligned)?;
+                    let rs2 = self.read_gp_reg(instruction.rs2);
+
+                    // The reservation is always given up, whether or not the store goes ahead.
+                    let reserved = self.is_reserved(address);
+                    self.reservation = None;
+
+                    if reserved
+                    {
+                        self.write_u32(address, rs2 as u32)?;
+                    }
+
+                    self.write_gp_reg(instruction.rd, if reserved { 0 } else { 1 });
                 },
 
             // amoswap.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOSWAP_W =>
                 {
+                    self.atomic_word(instruction, |_, rs2| rs2)?;
                 },
 
             // amoadd.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOADD_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value.wrapping_add(rs2))?;
                 },
 
             // amoxor.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOXOR_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value ^ rs2)?;
                 },
 
             // amoand.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOAND_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value & rs2)?;
                 },
 
             // amoor.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOOR_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value | rs2)?;
                 },
 
             // amomin.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMIN_W =>
                 {
+                    self.atomic_word(instruction,
+                                    |value, rs2| (value as i32).min(rs2 as i32) as u32)?;
                 },
 
             // amomax.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMAX_W =>
                 {
+                    self.atomic_word(instruction,
+                                    |value, rs2| (value as i32).max(rs2 as i32) as u32)?;
                 },
 
             // amominu.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMINU_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value.min(rs2))?;
                 },
 
             // amomaxu.w  r-type
             ( func7, F3_EXT_A32___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMAXU_W =>
                 {
+                    self.atomic_word(instruction, |value, rs2| value.max(rs2))?;
                 },
 
             // RV64A Standard Extension (in addition to RV32A)
@@ -642,56 +1567,89 @@ impl Cpu
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if    (func7 & EXT_A_F7_MASK == F7_LR_D___)
                                                      && (instruction.rs2 == RS2_LR_D) =>
                 {
+                    let address = self.atomic_address(instruction,
+                                                      8,
+                                                      Exception::LoadAddressMisaligned)?;
+                    let value = self.read_u64(address)?;
+
+                    self.reserve(address);
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // sc.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_SC_D =>
                 {
+                    let address = self.atomic_address(instruction,
+                                                      8,
+                                                      Exception::StoreAddressMisaligned)?;
+                    let rs2 = self.read_gp_reg(instruction.rs2);
+
+                    // The reservation is always given up, whether or not the store goes ahead.
+                    let reserved = self.is_reserved(address);
+                    self.reservation = None;
+
+                    if reserved
+                    {
+                        self.write_u64(address, rs2)?;
+                    }
+
+                    self.write_gp_reg(instruction.rd, if reserved { 0 } else { 1 });
                 },
 
             // amoswap.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOSWAP_D =>
                 {
+                    self.atomic_double(instruction, |_, rs2| rs2)?;
                 },
 
             // amoadd.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOADD_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value.wrapping_add(rs2))?;
                 },
 
             // amoxor.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOXOR_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value ^ rs2)?;
                 },
 
             // amoand.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOAND_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value & rs2)?;
                 },
 
             // amoor.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOOR_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value | rs2)?;
                 },
 
             // amomin.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMIN_D =>
                 {
+                    self.atomic_double(instruction,
+                                      |value, rs2| (value as i64).min(rs2 as i64) as u64)?;
                 },
 
             // amomax.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMAX_D =>
                 {
+                    self.atomic_double(instruction,
+                                      |value, rs2| (value as i64).max(rs2 as i64) as u64)?;
                 },
 
             // amominu.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMINU_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value.min(rs2))?;
                 },
 
             // amomaxu.d  r-type
             ( func7, F3_EXT_A64___, OP_EXT_A___ ) if  func7 & EXT_A_F7_MASK == F7_AMOMAXU_D =>
                 {
+                    self.atomic_double(instruction, |value, rs2| value.max(rs2))?;
                 },
 
 
@@ -887,21 +1845,25 @@ impl Cpu
             ( _, F3_LWU, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u32(address) as u32 as u64);
+                    let value = self.read_u32(address)? as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // ld  i-type
             ( _, F3_LD, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u64(address));
+                    let value = self.read_u64(address)?;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // sd  s-type
             ( _, F3_SD, OP_ST___ ) =>
                 {
                     let address = self.address_from_st(instruction);
-                    self.write_u64(address, self.read_gp_reg(instruction.rs2));
+                    self.write_u64(address, self.read_gp_reg(instruction.rs2))?;
                 },
 
             // addiw  i-type
@@ -936,7 +1898,7 @@ impl Cpu
             ( F7_SRLIW, F3_SHR___, OP_MO3___ ) =>
                 {
                     let shift = instruction.rs2;
-                    let rs1 = self.read_gp_reg(instruction.rs1);
+                    let rs1 = self.read_gp_reg(instruction.rs1) as u32;
                     let result = (rs1 >> shift) as i32;
 
                     self.write_gp_reg(instruction.rd, result as i64 as u64);
@@ -946,8 +1908,8 @@ impl Cpu
             ( F7_SRAIW, F3_SHR___, OP_MO3___ ) =>
                 {
                     let shift = instruction.rs2;
-                    let rs1 = self.read_gp_reg(instruction.rs1) as i64;
-                    let result = (rs1 >> shift) as i32;
+                    let rs1 = self.read_gp_reg(instruction.rs1) as i32;
+                    let result = rs1 >> shift;
 
                     self.write_gp_reg(instruction.rd, result as i64 as u64);
                 },
@@ -1021,21 +1983,20 @@ impl Cpu
             // jal  j-type
             ( _, _, OP_JAL ) =>
                 {
-                    self.write_gp_reg(instruction.rd, self.pc as u64);
-                    self.pc = self.address_from_jt(instruction);
+                    let return_address = self.pc as u64;
 
-                    // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                    self.jump(self.address_from_jt(instruction))?;
+                    self.write_gp_reg(instruction.rd, return_address);
                 },
 
             // jalr  i-type
             ( _, _, OP_JALR ) =>
                 {
                     let address = self.address_from_it(instruction);
+                    let return_address = self.pc as u64;
 
-                    self.write_gp_reg(instruction.rd, self.pc as u64);
-                    self.pc = address & (!1);
-
-                    // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                    self.jump(address & (!1))?;
+                    self.write_gp_reg(instruction.rd, return_address);
                 },
 
             // beq  b-type
@@ -1045,8 +2006,7 @@ impl Cpu
 
                     if rs1 == rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1057,8 +2017,7 @@ impl Cpu
 
                     if rs1 != rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1069,8 +2028,7 @@ impl Cpu
 
                     if rs1 < rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1081,8 +2039,7 @@ impl Cpu
 
                     if rs1 >= rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1093,8 +2050,7 @@ impl Cpu
 
                     if rs1 < rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1105,8 +2061,7 @@ impl Cpu
 
                     if rs1 >= rs2
                     {
-                        self.pc = self.address_from_bt(self.pc - 4, instruction);
-                        // TODO: Generate a mis-aligned exception if not on a 4-byte boundry.
+                        self.jump(self.address_from_bt(instruction))?;
                     }
                 },
 
@@ -1114,56 +2069,66 @@ impl Cpu
             ( _, F3_LB, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u8(address) as i8 as i64 as u64);
+                    let value = self.read_u8(address)? as i8 as i64 as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // lh  i-type
             ( _, F3_LH, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u16(address) as i16 as i64 as u64);
+                    let value = self.read_u16(address)? as i16 as i64 as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // lw  i-type
             ( _, F3_LW, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u32(address) as i32 as i64 as u64);
+                    let value = self.read_u32(address)? as i32 as i64 as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // lbu  i-type
             ( _, F3_LBU, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u8(address) as u64);
+                    let value = self.read_u8(address)? as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // lhu  i-type
             ( _, F3_LHU, OP_LD___ ) =>
                 {
                     let address = self.address_from_it(instruction);
-                    self.write_gp_reg(instruction.rd, self.read_u16(address) as u64);
+                    let value = self.read_u16(address)? as u64;
+
+                    self.write_gp_reg(instruction.rd, value);
                 },
 
             // sb  s-type
             ( _, F3_SB, OP_ST___ ) =>
                 {
                     let address = self.address_from_st(instruction);
-                    self.write_u8(address, self.read_gp_reg(instruction.rs2) as u8);
+                    self.write_u8(address, self.read_gp_reg(instruction.rs2) as u8)?;
                 },
 
             // sh  s-type
             ( _, F3_SH, OP_ST___ ) =>
                 {
                     let address = self.address_from_st(instruction);
-                    self.write_u16(address, self.read_gp_reg(instruction.rs2) as u16);
+                    self.write_u16(address, self.read_gp_reg(instruction.rs2) as u16)?;
                 },
 
             // sw  s-type
             ( _, F3_SW, OP_ST___ ) =>
                 {
                     let address = self.address_from_st(instruction);
-                    self.write_u32(address, self.read_gp_reg(instruction.rs2) as u32);
+                    self.write_u32(address, self.read_gp_reg(instruction.rs2) as u32)?;
                 },
 
             // addi  i-type
@@ -1212,8 +2177,8 @@ impl Cpu
             // ori  i-type
             ( _, F3_ORI, OP_MO1___ ) =>
                 {
-                    let immediate = instruction.it_immediate() as u64;
-                    let rs1 = self.read_gp_reg(instruction.rs1) as u64;
+                    let immediate = instruction.it_immediate();
+                    let rs1 = self.read_gp_reg(instruction.rs1);
 
                     self.write_gp_reg(instruction.rd, rs1 | immediate);
                 },
@@ -1221,34 +2186,34 @@ impl Cpu
             // andi  i-type
             ( _, F3_ANDI, OP_MO1___ ) =>
                 {
-                    let immediate = instruction.it_immediate() as u64;
-                    let rs1 = self.read_gp_reg(instruction.rs1) as u64;
+                    let immediate = instruction.it_immediate();
+                    let rs1 = self.read_gp_reg(instruction.rs1);
 
                     self.write_gp_reg(instruction.rd, rs1 & immediate);
                 },
 
             // slli  r-type
-            ( F7_SLLI, F3_SL___, OP_MO1___ ) =>
+            ( func7, F3_SL___, OP_MO1___ ) if func7 & SHAMT_F7_MASK == F7_SLLI =>
                 {
-                    let shift = instruction.rs2;
-                    let rs1 = self.read_gp_reg(instruction.rs1) as u64;
+                    let shift = instruction.shamt();
+                    let rs1 = self.read_gp_reg(instruction.rs1);
 
                     self.write_gp_reg(instruction.rd, rs1 << shift);
                 },
 
             // srli  r-type
-            ( F7_SRLI, F3_SR___, OP_MO1___ ) =>
+            ( func7, F3_SR___, OP_MO1___ ) if func7 & SHAMT_F7_MASK == F7_SRLI =>
                 {
-                    let shift = instruction.rs2;
-                    let rs1 = self.read_gp_reg(instruction.rs1) as u64;
+                    let shift = instruction.shamt();
+                    let rs1 = self.read_gp_reg(instruction.rs1);
 
                     self.write_gp_reg(instruction.rd, rs1 >> shift);
                 },
 
             // srai  r-type
-            ( F7_SRAI, F3_SR___, OP_MO1___ ) =>
+            ( func7, F3_SR___, OP_MO1___ ) if func7 & SHAMT_F7_MASK == F7_SRAI =>
                 {
-                    let shift = instruction.rs2;
+                    let shift = instruction.shamt();
                     let rs1 = self.read_gp_reg(instruction.rs1) as i64;
 
                     self.write_gp_reg(instruction.rd, (rs1 >> shift) as u64);
@@ -1349,21 +2314,315 @@ impl Cpu
                 {
                 },
 
+            // fence.i  * i-type
+            ( _, F3_FENCE_I, OP_MISC_MEM___ ) =>
+                {
+                    // Instructions are always fetched straight from memory, so stores are already
+                    // visible to them.
+                },
+
             // ecall  * i-type
             ( _, F3_SYS_PRIV___, OP_SYSTEM___ ) if instruction.func12 == F12_ECALL =>
                 {
+                    return Err(match self.privilege
+                        {
+                            PrivilegeLvel::User       => Exception::EnvironmentCallFromUMode,
+                            PrivilegeLvel::Supervisor => Exception::EnvironmentCallFromSMode,
+                            PrivilegeLvel::Machine    => Exception::EnvironmentCallFromMMode
+                        });
                 },
 
             // ebreak  * i-type
             ( _, F3_SYS_PRIV___, OP_SYSTEM___ ) if instruction.func12 == F12_EBREAK =>
                 {
+                    return Err(Exception::Breakpoint(self.instruction_address(instruction) as u64));
+                },
+
+            // mret  * i-type
+            ( _, F3_SYS_PRIV___, OP_SYSTEM___ ) if instruction.func12 == F12_MRET =>
+                {
+                    if self.privilege != PrivilegeLvel::Machine
+                    {
+                        return Err(illegal);
+                    }
+
+                    self.machine_return();
+                },
+
+            // sret  * i-type
+            ( _, F3_SYS_PRIV___, OP_SYSTEM___ ) if instruction.func12 == F12_SRET =>
+                {
+                    let trapped =    self.privilege == PrivilegeLvel::Supervisor
+                                  && self.csrs[CSR_MSTATUS] & MSTATUS_TSR != 0;
+
+                    if self.privilege == PrivilegeLvel::User || trapped
+                    {
+                        return Err(illegal);
+                    }
+
+                    self.supervisor_return();
+                },
+
+            // sfence.vma  r-type
+            ( F7_SFENCE_VMA, F3_SYS_PRIV___, OP_SYSTEM___ ) =>
+                {
+                    let trapped =    self.privilege == PrivilegeLvel::Supervisor
+                                  && self.csrs[CSR_MSTATUS] & MSTATUS_TVM != 0;
+
+                    if self.privilege == PrivilegeLvel::User || trapped
+                    {
+                        return Err(illegal);
+                    }
+
+                    // Entries aren't tagged with their address space, so an asid doesn't narrow the
+                    // flush down.
+                    if instruction.rs1 == 0
+                    {
+                        self.tlb.flush();
+                    }
+                    else
+                    {
+                        let levels = self.translation_levels();
+                        self.tlb.flush_page(self.read_gp_reg(instruction.rs1) as usize, levels);
+                    }
+                },
+
+            // wfi  * i-type
+            ( _, F3_SYS_PRIV___, OP_SYSTEM___ ) if instruction.func12 == F12_WFI =>
+                {
+                    let trapped =    self.privilege == PrivilegeLvel::Supervisor
+                                  && self.csrs[CSR_MSTATUS] & MSTATUS_TW != 0;
+
+                    if self.privilege == PrivilegeLvel::User || trapped
+                    {
+                        return Err(illegal);
+                    }
+
+                    // The hart only waits while no interrupt is pending, whether it's enabled
+                    // globally or not.  Skip ahead to the timer, an external interrupt that would
+                    // have come first is just taken a little late.  Firmware outside the guest
+                    // drives the supervisor timer from the same timer.
+                    let waiting = self.csrs[CSR_MIP] & self.csrs[CSR_MIE] == 0;
+
+                    if waiting && self.csrs[CSR_MIE] & (MIP_MTIP | MIP_STIP) != 0
+                    {
+                        if let Some(clint) = &self.clint
+                        {
+                            clint.borrow_mut().idle();
+                        }
+                    }
+                },
+
+
+            // Zicsr Standard Extension
+
+            // csrrw  i-type
+            ( _, F3_CSRRW, OP_SYSTEM___ ) =>
+                {
+                    let value = self.read_gp_reg(instruction.rs1);
+                    self.csr_write_op(instruction, |_| value).ok_or(illegal)?;
+                },
+
+            // csrrs  i-type
+            ( _, F3_CSRRS, OP_SYSTEM___ ) =>
+                {
+                    let value = self.read_gp_reg(instruction.rs1);
+                    self.csr_set_op(instruction, |csr| csr | value).ok_or(illegal)?;
+                },
+
+            // csrrc  i-type
+            ( _, F3_CSRRC, OP_SYSTEM___ ) =>
+                {
+                    let value = self.read_gp_reg(instruction.rs1);
+                    self.csr_set_op(instruction, |csr| csr & !value).ok_or(illegal)?;
+                },
+
+            // csrrwi  i-type
+            ( _, F3_CSRRWI, OP_SYSTEM___ ) =>
+                {
+                    let value = instruction.rs1 as u64;
+                    self.csr_write_op(instruction, |_| value).ok_or(illegal)?;
+                },
+
+            // csrrsi  i-type
+            ( _, F3_CSRRSI, OP_SYSTEM___ ) =>
+                {
+                    let value = instruction.rs1 as u64;
+                    self.csr_set_op(instruction, |csr| csr | value).ok_or(illegal)?;
+                },
+
+            // csrrci  i-type
+            ( _, F3_CSRRCI, OP_SYSTEM___ ) =>
+                {
+                    let value = instruction.rs1 as u64;
+                    self.csr_set_op(instruction, |csr| csr & !value).ok_or(illegal)?;
                 },
 
 
             _ =>
                 {
-                    dbg!("Opcode unimplemnented.");
+                    return Err(illegal);
                 }
         }
+
+        // Every instruction takes a single cycle.
+        self.csrs[CSR_MCYCLE] = self.csrs[CSR_MCYCLE].wrapping_add(1);
+        self.csrs[CSR_MINSTRET] = self.csrs[CSR_MINSTRET].wrapping_add(1);
+
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    const RAM_BASE: usize = 0x_8000_0000;
+    const DATA:     usize = RAM_BASE + 0x_1000;
+
+    const REG_A0: usize = 10;
+    const REG_A1: usize = 11;
+    const REG_A2: usize = 12;
+    const REG_A3: usize = 13;
+
+    const LR_W:      u32 = 0x_100525af;  // lr.w       a1, (a0)
+    const SC_W:      u32 = 0x_18d5262f;  // sc.w       a2, a3, (a0)
+    const ECALL:     u32 = 0x_00000073;
+    const MRET:      u32 = 0x_30200073;
+    const SB_INSIDE: u32 = 0x_000501a3;  // sb         zero, 3(a0)
+    const SB_AFTER:  u32 = 0x_00050423;  // sb         zero, 8(a0)
+    const SB_TOP:    u32 = 0x_fe000fa3;  // sb         zero, -1(zero)
+
+
+    // A machine mode hart about to run the code at the bottom of RAM, with a0 pointing at some
+    // data further up.
+    fn hart(code: &[ u32 ]) -> Cpu
+    {
+        let mut bus = Bus::new();
+
+        bus.add_ram(RAM_BASE as u64, 0x_1_0000).unwrap();
+
+        let mut cpu = Cpu::new(bus);
+
+        for ( index, word ) in code.iter().enumerate()
+        {
+            assert!(cpu.write_physical(RAM_BASE + 4 * index, &word.to_le_bytes()));
+        }
+
+        cpu.pc = RAM_BASE;
+        cpu.write_gp_reg(REG_A0, DATA as u64);
+        cpu
+    }
+
+
+    // Run the given number of instructions, taking any traps they raise.
+    fn run(cpu: &mut Cpu, count: usize)
+    {
+        for _ in 0..count
+        {
+            if let Err(exception) = cpu.step()
+            {
+                cpu.trap(exception);
+            }
+        }
+    }
+
+
+    fn read_data(cpu: &mut Cpu) -> u64
+    {
+        let mut bytes = [ 0; 8 ];
+
+        assert!(cpu.read_physical(DATA, &mut bytes));
+        u64::from_le_bytes(bytes)
+    }
+
+
+    // What comes between an lr.w and an sc.w to the same address, and whether the sc.w succeeds.
+    // Traps and returns from them land on the sc.w.
+    #[test]
+    fn reservations()
+    {
+        let table: [ ( &[ u32 ], bool ); 7 ] =
+            [
+                ( &[],                       true ),
+                ( &[ SB_AFTER ],             true ),
+                ( &[ SB_INSIDE ],            false ),
+                ( &[ SB_AFTER, SB_INSIDE ],  false ),
+                ( &[ ECALL ],                false ),
+                ( &[ MRET ],                 false ),
+
+                // The store faults without disturbing the reservation, but the trap gives it up.
+                ( &[ SB_TOP ],               false )
+            ];
+
+        for &( between, succeeds ) in &table
+        {
+            let code: Vec<u32> = [ &[ LR_W ], between, &[ SC_W ] ].concat();
+            let sc = (RAM_BASE + 4 * (code.len() - 1)) as u64;
+
+            let mut cpu = hart(&code);
+
+            cpu.csrs[CSR_MTVEC] = sc;
+            cpu.csrs[CSR_MEPC] = sc;
+            cpu.csrs[CSR_MSTATUS] |= MSTATUS_MPP;
+            cpu.write_gp_reg(REG_A3, 0x_5555_5555);
+
+            run(&mut cpu, code.len());
+
+            let stored = read_data(&mut cpu) as u32 == 0x_5555_5555;
+
+            assert_eq!(cpu.pc, sc as usize + 4, "{:08x?}", between);
+            assert_eq!(cpu.read_gp_reg(REG_A2), !succeeds as u64, "{:08x?}", between);
+            assert_eq!(stored, succeeds, "{:08x?}", between);
+        }
+    }
+
+
+    // The word AMOs operate on the low 32 bits of rs2 and the word in memory, and sign-extend the
+    // word they load into rd.
+    #[test]
+    fn word_amos()
+    {
+        let table =
+            [
+                // amoadd.w
+                ( 0x_00c525af, 0x_8000_0000, 1,
+                  0x_ffff_ffff_8000_0000, 0x_8000_0001 ),
+
+                // amoswap.w
+                ( 0x_08c525af, 0x_ffff_fffe, 5,
+                  0x_ffff_ffff_ffff_fffe, 5 ),
+
+                // amomaxu.w
+                ( 0x_e0c525af, 0x_8000_0000, 0x_ffff_ffff_0000_0001,
+                  0x_ffff_ffff_8000_0000, 0x_8000_0000 ),
+
+                // amomin.w
+                ( 0x_80c525af, 1, 0x_ffff_ffff,
+                  1, 0x_ffff_ffff ),
+
+                // amoxor.w
+                ( 0x_20c525af, 0x_8000_0000, 0x_ffff_ffff,
+                  0x_ffff_ffff_8000_0000, 0x_7fff_ffff )
+            ];
+
+        for &( encoding, memory, rs2, loaded, stored ) in &table
+        {
+            let mut cpu = hart(&[ encoding ]);
+
+            // The word above is left alone.
+            let initial: u64 = 0x_1234_5678_0000_0000 | memory;
+
+            assert!(cpu.write_physical(DATA, &initial.to_le_bytes()));
+            cpu.write_gp_reg(REG_A2, rs2);
+
+            assert_eq!(cpu.step(), Ok(()));
+            assert_eq!(cpu.read_gp_reg(REG_A1), loaded, "{:08x}", encoding);
+            assert_eq!(read_data(&mut cpu), 0x_1234_5678_0000_0000 | stored, "{:08x}", encoding);
+        }
     }
 }
@@ -0,0 +1,666 @@
+
+use super::cpu::{ Cpu, PrivilegeLvel };
+
+
+// Control and Status Register addresses.
+
+
+// Unprivileged Floating-Point CSRs
+pub const CSR_FFLAGS: usize = 0x_001;
+pub const CSR_FRM:    usize = 0x_002;
+pub const CSR_FCSR:   usize = 0x_003;
+
+// Unprivileged Counter/Timers
+pub const CSR_CYCLE:         usize = 0x_c00;
+pub const CSR_TIME:          usize = 0x_c01;
+pub const CSR_INSTRET:       usize = 0x_c02;
+pub const CSR_HPMCOUNTER3:   usize = 0x_c03;
+pub const CSR_HPMCOUNTER31:  usize = 0x_c1f;
+
+// Supervisor Trap Setup
+pub const CSR_SSTATUS:       usize = 0x_100;
+pub const CSR_SIE:           usize = 0x_104;
+pub const CSR_STVEC:         usize = 0x_105;
+pub const CSR_SCOUNTEREN:    usize = 0x_106;
+
+// Supervisor Trap Handling
+pub const CSR_SSCRATCH:      usize = 0x_140;
+pub const CSR_SEPC:          usize = 0x_141;
+pub const CSR_SCAUSE:        usize = 0x_142;
+pub const CSR_STVAL:         usize = 0x_143;
+pub const CSR_SIP:           usize = 0x_144;
+
+// Supervisor Protection and Translation
+pub const CSR_SATP:          usize = 0x_180;
+
+// Machine Information Registers
+pub const CSR_MVENDORID:     usize = 0x_f11;
+pub const CSR_MARCHID:       usize = 0x_f12;
+pub const CSR_MIMPID:        usize = 0x_f13;
+pub const CSR_MHARTID:       usize = 0x_f14;
+pub const CSR_MCONFIGPTR:    usize = 0x_f15;
+
+// Machine Trap Setup
+pub const CSR_MSTATUS:       usize = 0x_300;
+pub const CSR_MISA:          usize = 0x_301;
+pub const CSR_MEDELEG:       usize = 0x_302;
+pub const CSR_MIDELEG:       usize = 0x_303;
+pub const CSR_MIE:           usize = 0x_304;
+pub const CSR_MTVEC:         usize = 0x_305;
+pub const CSR_MCOUNTEREN:    usize = 0x_306;
+
+// Machine Trap Handling
+pub const CSR_MSCRATCH:      usize = 0x_340;
+pub const CSR_MEPC:          usize = 0x_341;
+pub const CSR_MCAUSE:        usize = 0x_342;
+pub const CSR_MTVAL:         usize = 0x_343;
+pub const CSR_MIP:           usize = 0x_344;
+
+// Machine Memory Protection
+pub const CSR_PMPCFG0:       usize = 0x_3a0;
+pub const CSR_PMPCFG15:      usize = 0x_3af;
+pub const CSR_PMPADDR0:      usize = 0x_3b0;
+pub const CSR_PMPADDR63:     usize = 0x_3ef;
+
+// Machine Counter/Timers
+pub const CSR_MCYCLE:        usize = 0x_b00;
+pub const CSR_MINSTRET:      usize = 0x_b02;
+pub const CSR_MHPMCOUNTER3:  usize = 0x_b03;
+pub const CSR_MHPMCOUNTER31: usize = 0x_b1f;
+
+// Machine Counter Setup
+pub const CSR_MHPMEVENT3:    usize = 0x_323;
+pub const CSR_MHPMEVENT31:   usize = 0x_33f;
+
+
+// fcsr holds the rounding mode above the accrued exception flags.
+pub const FCSR_FRM_SHIFT:   u64 = 5;
+pub const FCSR_FFLAGS_MASK: u64 = 0b_11111;
+pub const FCSR_FRM_MASK:    u64 = 0b_111;
+
+
+// mstatus fields, sstatus is a restricted view of the same register.
+pub const MSTATUS_SIE:      u64 = 1 << 1;
+pub const MSTATUS_MIE:      u64 = 1 << 3;
+pub const MSTATUS_SPIE:     u64 = 1 << 5;
+pub const MSTATUS_MPIE:     u64 = 1 << 7;
+pub const MSTATUS_SPP:      u64 = 1 << 8;
+pub const MSTATUS_MPP:      u64 = 0b_11 << 11;
+pub const MSTATUS_FS:       u64 = 0b_11 << 13;
+pub const MSTATUS_MPRV:     u64 = 1 << 17;
+pub const MSTATUS_SUM:      u64 = 1 << 18;
+pub const MSTATUS_MXR:      u64 = 1 << 19;
+pub const MSTATUS_TVM:      u64 = 1 << 20;
+pub const MSTATUS_TW:       u64 = 1 << 21;
+pub const MSTATUS_TSR:      u64 = 1 << 22;
+pub const MSTATUS_UXL:      u64 = 0b_11 << 32;
+pub const MSTATUS_SD:       u64 = 1 << 63;
+
+pub const MSTATUS_SPP_SHIFT: u64 = 8;
+pub const MSTATUS_MPP_SHIFT: u64 = 11;
+pub const MSTATUS_UXL_SHIFT: u64 = 32;
+pub const MSTATUS_SXL_SHIFT: u64 = 34;
+
+// The XLEN encoding used by misa, and the UXL and SXL fields.
+pub const XL_64: u64 = 0b_10;
+
+pub const MSTATUS_FS_OFF:     u64 = 0b_00 << 13;
+pub const MSTATUS_FS_INITIAL: u64 = 0b_01 << 13;
+pub const MSTATUS_FS_DIRTY:   u64 = 0b_11 << 13;
+
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE
+                              | MSTATUS_SPP | MSTATUS_MPP | MSTATUS_FS
+                              | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR
+                              | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+
+const SSTATUS_WRITABLE: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS
+                              | MSTATUS_SUM | MSTATUS_MXR;
+
+const SSTATUS_VISIBLE: u64 = SSTATUS_WRITABLE | MSTATUS_UXL | MSTATUS_SD;
+
+
+// mie and mip bits, sie and sip are views of them restricted to the delegated interrupts.
+pub const MIP_SSIP: u64 = 1 << 1;
+pub const MIP_MSIP: u64 = 1 << 3;
+pub const MIP_STIP: u64 = 1 << 5;
+pub const MIP_MTIP: u64 = 1 << 7;
+pub const MIP_SEIP: u64 = 1 << 9;
+pub const MIP_MEIP: u64 = 1 << 11;
+
+const SUPERVISOR_INTERRUPTS: u64 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+const MACHINE_INTERRUPTS:    u64 = MIP_MSIP | MIP_MTIP | MIP_MEIP;
+
+const MIE_WRITABLE: u64 = SUPERVISOR_INTERRUPTS | MACHINE_INTERRUPTS;
+
+// Only the supervisor-level interrupts can be delegated, or raised by software through mip.
+const MIDELEG_WRITABLE: u64 = SUPERVISOR_INTERRUPTS;
+const MIP_WRITABLE:     u64 = SUPERVISOR_INTERRUPTS;
+const SIP_WRITABLE:     u64 = MIP_SSIP;
+
+// Every exception other than an environment call from machine mode can be delegated.
+const MEDELEG_WRITABLE: u64 = 0b_10110011_11111111;
+
+
+// mtvec holds the trap vector base above its mode.
+pub const MTVEC_MODE_MASK: u64 = 0b_11;
+pub const MTVEC_VECTORED:  u64 = 0b_01;
+
+
+// misa reports a 64-bit hart along with one bit for each implemented extension letter.
+pub const MISA_MXL_64: u64 = XL_64 << 62;
+
+pub const fn misa_extension(letter: u8) -> u64
+{
+    1 << (letter - b'A')
+}
+
+pub const MISA: u64 = MISA_MXL_64
+                      | misa_extension(b'A')
+                      | misa_extension(b'C')
+                      | misa_extension(b'D')
+                      | misa_extension(b'F')
+                      | misa_extension(b'I')
+                      | misa_extension(b'M')
+                      | misa_extension(b'S')
+                      | misa_extension(b'U');
+
+
+// The names of the CSRs that are implemented, as used by assemblers and debuggers.
+pub const CSR_NAMES: [ ( usize, &str ); 35 ] = [ ( CSR_FFLAGS,     "fflags" ),
+                                                 ( CSR_FRM,        "frm" ),
+                                                 ( CSR_FCSR,       "fcsr" ),
+                                                 ( CSR_CYCLE,      "cycle" ),
+                                                 ( CSR_TIME,       "time" ),
+                                                 ( CSR_INSTRET,    "instret" ),
+                                                 ( CSR_SSTATUS,    "sstatus" ),
+                                                 ( CSR_SIE,        "sie" ),
+                                                 ( CSR_STVEC,      "stvec" ),
+                                                 ( CSR_SCOUNTEREN, "scounteren" ),
+                                                 ( CSR_SSCRATCH,   "sscratch" ),
+                                                 ( CSR_SEPC,       "sepc" ),
+                                                 ( CSR_SCAUSE,     "scause" ),
+                                                 ( CSR_STVAL,      "stval" ),
+                                                 ( CSR_SIP,        "sip" ),
+                                                 ( CSR_SATP,       "satp" ),
+                                                 ( CSR_MVENDORID,  "mvendorid" ),
+                                                 ( CSR_MARCHID,    "marchid" ),
+                                                 ( CSR_MIMPID,     "mimpid" ),
+                                                 ( CSR_MHARTID,    "mhartid" ),
+                                                 ( CSR_MCONFIGPTR, "mconfigptr" ),
+                                                 ( CSR_MSTATUS,    "mstatus" ),
+                                                 ( CSR_MISA,       "misa" ),
+                                                 ( CSR_MEDELEG,    "medeleg" ),
+                                                 ( CSR_MIDELEG,    "mideleg" ),
+                                                 ( CSR_MIE,        "mie" ),
+                                                 ( CSR_MTVEC,      "mtvec" ),
+                                                 ( CSR_MCOUNTEREN, "mcounteren" ),
+                                                 ( CSR_MSCRATCH,   "mscratch" ),
+                                                 ( CSR_MEPC,       "mepc" ),
+                                                 ( CSR_MCAUSE,     "mcause" ),
+                                                 ( CSR_MTVAL,      "mtval" ),
+                                                 ( CSR_MIP,        "mip" ),
+                                                 ( CSR_MCYCLE,     "mcycle" ),
+                                                 ( CSR_MINSTRET,   "minstret" ) ];
+
+
+pub fn csr_name(address: usize) -> Option<&'static str>
+{
+    CSR_NAMES.iter().find(|( number, _ )| *number == address).map(|( _, name )| *name)
+}
+
+
+// A CSR whose address has both of bits 11:10 set is read-only.
+pub fn is_read_only_csr(address: usize) -> bool
+{
+    (address >> 10) & 0b_11 == 0b_11
+}
+
+
+// Bits 9:8 of a CSR's address hold the lowest privilege level allowed to access it.
+pub fn csr_privilege(address: usize) -> usize
+{
+    (address >> 8) & 0b_11
+}
+
+
+
+// Access to the CSRs as seen by the csr instructions, any access that real hardware would reject
+// with an illegal-instruction exception returns None.
+impl Cpu
+{
+    pub fn read_csr(&self, address: usize) -> Option<u64>
+    {
+        if !self.csr_accessible(address)
+        {
+            return None;
+        }
+
+        match address
+        {
+            CSR_FFLAGS | CSR_FRM | CSR_FCSR =>
+                Some(self.csrs[address]),
+
+            // Every instruction retires in a single cycle.
+            CSR_CYCLE | CSR_MCYCLE =>
+                Some(self.csrs[CSR_MCYCLE]),
+
+            CSR_INSTRET | CSR_MINSTRET =>
+                Some(self.csrs[CSR_MINSTRET]),
+
+            // Without a CLINT there's no timer, and reading the time is left to a trap handler.
+            CSR_TIME =>
+                self.clint.as_ref().map(|clint| clint.borrow().mtime()),
+
+            CSR_MSTATUS =>
+                Some(self.read_mstatus()),
+
+            CSR_SSTATUS =>
+                Some(self.read_mstatus() & SSTATUS_VISIBLE),
+
+            CSR_SIE =>
+                Some(self.csrs[CSR_MIE] & self.csrs[CSR_MIDELEG]),
+
+            CSR_SIP =>
+                Some(self.csrs[CSR_MIP] & self.csrs[CSR_MIDELEG]),
+
+            CSR_STVEC | CSR_SCOUNTEREN | CSR_SATP |
+            CSR_SSCRATCH | CSR_SEPC | CSR_SCAUSE | CSR_STVAL |
+            CSR_MVENDORID | CSR_MARCHID | CSR_MIMPID | CSR_MHARTID | CSR_MCONFIGPTR |
+            CSR_MISA | CSR_MEDELEG | CSR_MIDELEG | CSR_MIE | CSR_MTVEC | CSR_MCOUNTEREN | CSR_MIP |
+            CSR_MSCRATCH | CSR_MEPC | CSR_MCAUSE | CSR_MTVAL =>
+                Some(self.csrs[address]),
+
+            // There are no performance monitors or physical memory protection entries, their
+            // registers are hardwired to zero.
+            CSR_HPMCOUNTER3..=CSR_HPMCOUNTER31 |
+            CSR_MHPMCOUNTER3..=CSR_MHPMCOUNTER31 |
+            CSR_MHPMEVENT3..=CSR_MHPMEVENT31 |
+            CSR_PMPCFG0..=CSR_PMPCFG15 |
+            CSR_PMPADDR0..=CSR_PMPADDR63 =>
+                Some(0),
+
+            _ =>
+                None
+        }
+    }
+
+
+    pub fn write_csr(&mut self, address: usize, value: u64) -> Option<()>
+    {
+        if is_read_only_csr(address) || !self.csr_accessible(address)
+        {
+            return None;
+        }
+
+        match address
+        {
+            // fflags and frm are both views of fcsr, so all three are kept in step.
+            CSR_FFLAGS =>
+                {
+                    let frm = self.csrs[CSR_FRM];
+                    self.write_fcsr((frm << FCSR_FRM_SHIFT) | (value & FCSR_FFLAGS_MASK));
+                },
+
+            CSR_FRM =>
+                {
+                    let fflags = self.csrs[CSR_FFLAGS];
+                    self.write_fcsr(((value & FCSR_FRM_MASK) << FCSR_FRM_SHIFT) | fflags);
+                },
+
+            CSR_FCSR =>
+                self.write_fcsr(value),
+
+            // The written value is what the next instruction sees, so account for the writing
+            // instruction's own retirement.
+            CSR_MCYCLE | CSR_MINSTRET =>
+                self.csrs[address] = value.wrapping_sub(1),
+
+            // MPP can't hold the reserved privilege level, such a write leaves it unchanged.
+            CSR_MSTATUS =>
+                {
+                    let mut writable = MSTATUS_WRITABLE;
+
+                    if (value & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT == 0b_10
+                    {
+                        writable &= !MSTATUS_MPP;
+                    }
+
+                    self.write_masked(CSR_MSTATUS, value, writable);
+                },
+
+            CSR_SSTATUS =>
+                self.write_masked(CSR_MSTATUS, value, SSTATUS_WRITABLE),
+
+            // The extensions can't be switched off.
+            CSR_MISA =>
+                {
+                },
+
+            CSR_MEDELEG =>
+                self.write_masked(CSR_MEDELEG, value, MEDELEG_WRITABLE),
+
+            CSR_MIDELEG =>
+                self.write_masked(CSR_MIDELEG, value, MIDELEG_WRITABLE),
+
+            CSR_MIE =>
+                self.write_masked(CSR_MIE, value, MIE_WRITABLE),
+
+            CSR_SIE =>
+                self.write_masked(CSR_MIE, value, MIE_WRITABLE & self.csrs[CSR_MIDELEG]),
+
+            // The machine-level pending bits are set by the hardware.
+            CSR_MIP =>
+                self.write_masked(CSR_MIP, value, MIP_WRITABLE),
+
+            CSR_SIP =>
+                self.write_masked(CSR_MIP, value, SIP_WRITABLE & self.csrs[CSR_MIDELEG]),
+
+            CSR_SATP =>
+                self.write_satp(value),
+
+            // Only the direct and vectored modes exist.
+            CSR_MTVEC | CSR_STVEC =>
+                self.csrs[address] = value & !0b_10,
+
+            // Instructions are 16-bit aligned with the C extension.
+            CSR_MEPC | CSR_SEPC =>
+                self.csrs[address] = value & !0b_1,
+
+            CSR_MCOUNTEREN | CSR_SCOUNTEREN =>
+                self.csrs[address] = value & 0x_ffffffff,
+
+            CSR_MSCRATCH | CSR_MCAUSE | CSR_MTVAL |
+            CSR_SSCRATCH | CSR_SCAUSE | CSR_STVAL =>
+                self.csrs[address] = value,
+
+            CSR_MHPMCOUNTER3..=CSR_MHPMCOUNTER31 |
+            CSR_MHPMEVENT3..=CSR_MHPMEVENT31 |
+            CSR_PMPCFG0..=CSR_PMPCFG15 |
+            CSR_PMPADDR0..=CSR_PMPADDR63 =>
+                {
+                },
+
+            _ =>
+                return None
+        }
+
+        Some(())
+    }
+
+
+    // A debugger has machine mode's access to the CSRs whatever the hart is running, and sees the
+    // floating-point CSRs even with the unit switched off.
+    pub fn read_csr_debug(&mut self, address: usize) -> Option<u64>
+    {
+        if matches!(address, CSR_FFLAGS | CSR_FRM | CSR_FCSR)
+        {
+            return Some(self.csrs[address]);
+        }
+
+        let privilege = std::mem::replace(&mut self.privilege, PrivilegeLvel::Machine);
+        let value = self.read_csr(address);
+
+        self.privilege = privilege;
+        value
+    }
+
+
+    pub fn write_csr_debug(&mut self, address: usize, value: u64) -> Option<()>
+    {
+        let privilege = std::mem::replace(&mut self.privilege, PrivilegeLvel::Machine);
+        let result = self.write_csr(address, value);
+
+        // A debugger's write isn't made by an instruction, so there's no retirement to account for.
+        if result.is_some() && matches!(address, CSR_MCYCLE | CSR_MINSTRET)
+        {
+            self.csrs[address] = value;
+        }
+
+        self.privilege = privilege;
+        result
+    }
+
+
+    // The floating-point CSRs are only accessible while the floating-point unit is enabled, and
+    // below machine mode the counters have to be enabled by each more privileged level.
+    fn csr_accessible(&self, address: usize) -> bool
+    {
+        if csr_privilege(address) > self.privilege as usize
+        {
+            return false;
+        }
+
+        match address
+        {
+            CSR_FFLAGS | CSR_FRM | CSR_FCSR =>
+                self.float_enabled(),
+
+            // TVM traps supervisor mode's address translation management.
+            CSR_SATP =>
+                   self.privilege != PrivilegeLvel::Supervisor
+                || self.csrs[CSR_MSTATUS] & MSTATUS_TVM == 0,
+
+            CSR_CYCLE..=CSR_HPMCOUNTER31 =>
+                {
+                    let bit = 1 << (address - CSR_CYCLE);
+                    let enabled = |counteren: usize| self.csrs[counteren] & bit != 0;
+
+                    match self.privilege
+                    {
+                        PrivilegeLvel::Machine    => true,
+                        PrivilegeLvel::Supervisor => enabled(CSR_MCOUNTEREN),
+                        PrivilegeLvel::User       => enabled(CSR_MCOUNTEREN)
+                                                     && enabled(CSR_SCOUNTEREN)
+                    }
+                },
+
+            _ =>
+                true
+        }
+    }
+
+
+    // SD summarizes whether any extension state is dirty.
+    fn read_mstatus(&self) -> u64
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+
+        if mstatus & MSTATUS_FS == MSTATUS_FS_DIRTY
+        {
+            mstatus | MSTATUS_SD
+        }
+        else
+        {
+            mstatus
+        }
+    }
+
+
+    fn write_masked(&mut self, address: usize, value: u64, mask: u64)
+    {
+        self.csrs[address] = (self.csrs[address] & !mask) | (value & mask);
+    }
+
+
+    pub fn float_enabled(&self) -> bool
+    {
+        self.csrs[CSR_MSTATUS] & MSTATUS_FS != MSTATUS_FS_OFF
+    }
+
+
+    // Any change to floating-point state marks it as dirty in mstatus.
+    pub fn mark_float_dirty(&mut self)
+    {
+        self.csrs[CSR_MSTATUS] |= MSTATUS_FS_DIRTY;
+    }
+
+
+    fn write_fcsr(&mut self, value: u64)
+    {
+        self.csrs[CSR_FFLAGS] = value & FCSR_FFLAGS_MASK;
+        self.csrs[CSR_FRM] = (value >> FCSR_FRM_SHIFT) & FCSR_FRM_MASK;
+        self.csrs[CSR_FCSR] = (self.csrs[CSR_FRM] << FCSR_FRM_SHIFT) | self.csrs[CSR_FFLAGS];
+
+        self.mark_float_dirty();
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::bus::Bus;
+
+
+    // A hart without a CLINT, with the floating-point unit on.
+    fn hart() -> Cpu
+    {
+        let mut cpu = Cpu::new(Bus::new());
+
+        cpu.csrs[CSR_MSTATUS] |= MSTATUS_FS_INITIAL;
+        cpu
+    }
+
+
+    // Writes from machine mode, and what reading back the register gives.  None for a write that
+    // raises an illegal-instruction exception.
+    #[test]
+    fn registers()
+    {
+        let mut cpu = hart();
+
+        let table =
+            [
+                ( CSR_MEDELEG,      u64::MAX,        Some(MEDELEG_WRITABLE) ),
+                ( CSR_MIDELEG,      u64::MAX,        Some(SUPERVISOR_INTERRUPTS) ),
+                ( CSR_MIE,          u64::MAX,        Some(MIE_WRITABLE) ),
+                ( CSR_MIP,          u64::MAX,        Some(SUPERVISOR_INTERRUPTS) ),
+                ( CSR_MISA,         0,               Some(MISA) ),
+                ( CSR_MTVEC,        0x_8000_0003,    Some(0x_8000_0001) ),
+                ( CSR_MEPC,         0x_8000_0003,    Some(0x_8000_0002) ),
+                ( CSR_MCOUNTEREN,   u64::MAX,        Some(0x_ffff_ffff) ),
+                ( CSR_FCSR,         0x_ff,           Some(0x_ff) ),
+                ( CSR_FFLAGS,       0,               Some(0) ),
+                ( CSR_FRM,          9,               Some(1) ),
+                ( CSR_PMPADDR0,     u64::MAX,        Some(0) ),
+                ( CSR_MHPMCOUNTER3, u64::MAX,        Some(0) ),
+                ( CSR_MHPMEVENT31,  u64::MAX,        Some(0) ),
+
+                // The unprivileged counters and the machine information registers are read-only.
+                ( CSR_CYCLE,        0,               None ),
+                ( CSR_TIME,         0,               None ),
+                ( CSR_HPMCOUNTER31, 0,               None ),
+                ( CSR_MHARTID,      0,               None ),
+                ( CSR_MVENDORID,    0,               None ),
+
+                // Nothing is there.
+                ( 0x_7c0,           0,               None ),
+                ( 0x_5c0,           0,               None )
+            ];
+
+        for &( address, value, expected ) in &table
+        {
+            assert_eq!(cpu.write_csr(address, value).is_some(), expected.is_some(),
+                       "{:#x}", address);
+
+            if expected.is_some()
+            {
+                assert_eq!(cpu.read_csr(address), expected, "{:#x}", address);
+            }
+        }
+
+        // The views of fcsr follow it.
+        assert_eq!(cpu.read_csr(CSR_FCSR), Some(1 << FCSR_FRM_SHIFT));
+    }
+
+
+    // mstatus keeps its read-only fields, and MPP can't be set to the reserved level.
+    #[test]
+    fn mstatus()
+    {
+        let mut cpu = hart();
+        let initial = cpu.read_csr(CSR_MSTATUS).unwrap();
+
+        assert_eq!(cpu.write_csr(CSR_MSTATUS, u64::MAX), Some(()));
+
+        let mstatus = cpu.read_csr(CSR_MSTATUS).unwrap();
+
+        assert_eq!(mstatus & MSTATUS_WRITABLE, MSTATUS_WRITABLE);
+        assert_eq!(mstatus & MSTATUS_UXL, initial & MSTATUS_UXL);
+        assert_eq!(mstatus & MSTATUS_SD, MSTATUS_SD);
+
+        assert_eq!(cpu.write_csr(CSR_MSTATUS, 0b_10 << MSTATUS_MPP_SHIFT), Some(()));
+        assert_eq!(cpu.read_csr(CSR_MSTATUS).unwrap() & MSTATUS_MPP, MSTATUS_MPP);
+
+        // sstatus only reaches its own fields.
+        assert_eq!(cpu.write_csr(CSR_MSTATUS, u64::MAX), Some(()));
+        assert_eq!(cpu.write_csr(CSR_SSTATUS, 0), Some(()));
+        assert_eq!(cpu.read_csr(CSR_MSTATUS).unwrap() & MSTATUS_WRITABLE,
+                   MSTATUS_WRITABLE & !SSTATUS_WRITABLE);
+
+        // sie reaches the delegated interrupts only.
+        cpu.csrs[CSR_MIDELEG] = MIP_STIP;
+
+        assert_eq!(cpu.write_csr(CSR_MIE, 0), Some(()));
+        assert_eq!(cpu.write_csr(CSR_SIE, u64::MAX), Some(()));
+        assert_eq!(cpu.read_csr(CSR_MIE), Some(MIP_STIP));
+        assert_eq!(cpu.read_csr(CSR_SIE), Some(MIP_STIP));
+    }
+
+
+    // Whether each privilege level can read and write a CSR.  Machine mode has enabled cycle for
+    // the levels below it, but supervisor mode hasn't passed it on.
+    #[test]
+    fn access()
+    {
+        use PrivilegeLvel::*;
+
+        let table =
+            [
+                ( User,       CSR_FCSR,       true,  true ),
+                ( User,       CSR_CYCLE,      false, false ),
+                ( User,       CSR_SSTATUS,    false, false ),
+                ( User,       CSR_MSTATUS,    false, false ),
+                ( Supervisor, CSR_CYCLE,      true,  false ),
+                ( Supervisor, CSR_INSTRET,    false, false ),
+                ( Supervisor, CSR_SSTATUS,    true,  true ),
+                ( Supervisor, CSR_SATP,       true,  true ),
+                ( Supervisor, CSR_MSTATUS,    false, false ),
+                ( Supervisor, CSR_MSCRATCH,   false, false ),
+                ( Supervisor, CSR_MHARTID,    false, false ),
+                ( Machine,    CSR_INSTRET,    true,  false ),
+                ( Machine,    CSR_MHARTID,    true,  false ),
+
+                // Without a CLINT, reading the time is left to a trap handler.
+                ( Machine,    CSR_TIME,       false, false )
+            ];
+
+        for &( privilege, address, readable, writable ) in &table
+        {
+            let mut cpu = hart();
+
+            cpu.csrs[CSR_MCOUNTEREN] = 1;
+            cpu.privilege = privilege;
+
+            assert_eq!(cpu.read_csr(address).is_some(), readable, "{:?} {:#x}", privilege, address);
+            assert_eq!(cpu.write_csr(address, 0).is_some(), writable,
+                       "{:?} {:#x}", privilege, address);
+        }
+
+        // TVM takes satp away from supervisor mode, and with the unit off the floating-point
+        // CSRs are gone.
+        let mut cpu = hart();
+
+        cpu.csrs[CSR_MSTATUS] |= MSTATUS_TVM;
+        cpu.privilege = Supervisor;
+
+        assert_eq!(cpu.read_csr(CSR_SATP), None);
+
+        cpu.csrs[CSR_MSTATUS] &= !MSTATUS_FS;
+
+        assert_eq!(cpu.read_csr(CSR_FFLAGS), None);
+        assert_eq!(cpu.write_csr(CSR_FRM, 0), None);
+    }
+}
@@ -0,0 +1,956 @@
+
+// Disassembly of decoded instructions, in the style of GNU objdump: ABI register names, the usual
+// pseudo-instructions in place of the instructions they stand for, and operands separated by bare
+// commas.  Compressed instructions come out as the instructions they expand to, as they do there.
+
+
+use std::fmt;
+use super::{ opcodes::*, instruction::Instruction, csr::*, float::* };
+
+
+pub const REGISTER_NAMES: [ &str; 32 ] = [ "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+                                           "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+                                           "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+                                           "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6" ];
+
+pub const FP_REGISTER_NAMES: [ &str; 32 ] = [ "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6",
+                                              "ft7", "fs0", "fs1", "fa0", "fa1", "fa2", "fa3",
+                                              "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4",
+                                              "fs5", "fs6", "fs7", "fs8", "fs9", "fs10", "fs11",
+                                              "ft8", "ft9", "ft10", "ft11" ];
+
+// Indexed by the rm field.  The dynamic mode is the default, and isn't shown.
+const ROUNDING_MODES: [ &str; 8 ] = [ "rne", "rtz", "rdn", "rup", "rmm", "0x5", "0x6", "dyn" ];
+
+// The floating-point format in the low bits of func7, or of rs3's field for the fused operations.
+const FMT_S: u32 = 0b_00;
+const FMT_D: u32 = 0b_01;
+
+// The rest of func7 gives the operation.
+const FP_ADD:          u32 = F7_FADD_S >> 2;
+const FP_SUB:          u32 = F7_FSUB_S >> 2;
+const FP_MUL:          u32 = F7_FMUL_S >> 2;
+const FP_DIV:          u32 = F7_FDIV_S >> 2;
+const FP_SQRT:         u32 = F7_FSQRT_S >> 2;
+const FP_SGNJ:         u32 = F7_FSGNJ___ >> 2;
+const FP_MIN_MAX:      u32 = F7_FSMM___ >> 2;
+const FP_CVT_FP:       u32 = F7_FCVT_S_D___ >> 2;
+const FP_COMPARE:      u32 = F7_FEQ_S >> 2;
+const FP_CVT_TO_INT:   u32 = F7_FCVT_W___ >> 2;
+const FP_CVT_FROM_INT: u32 = F7_FCVT_S___ >> 2;
+const FP_MV_TO_INT:    u32 = F7_FMV_X_W >> 2;
+const FP_MV_FROM_INT:  u32 = F7_FMV_W_X >> 2;
+
+// fence's predecessor and successor sets, from the high bit down, and its fence mode.
+const FENCE_SET_NAMES: [ char; 4 ] = [ 'i', 'o', 'r', 'w' ];
+const FENCE_ALL:       u32 = 0b_1111;
+const FENCE_RW:        u32 = 0b_0011;
+const FENCE_FM_TSO:    u32 = 0b_1000;
+
+// The aq and rl bits at the bottom of an atomic instruction's func7.
+const AMO_AQ: u32 = 0b_10;
+const AMO_RL: u32 = 0b_01;
+
+
+
+fn x(index: usize) -> &'static str
+{
+    REGISTER_NAMES[index]
+}
+
+
+fn f(index: usize) -> &'static str
+{
+    FP_REGISTER_NAMES[index]
+}
+
+
+fn line(mnemonic: &str, operands: String) -> String
+{
+    if operands.is_empty()
+    {
+        mnemonic.to_string()
+    }
+    else
+    {
+        format!("{}\t{}", mnemonic, operands)
+    }
+}
+
+
+fn csr(address: u32) -> String
+{
+    csr_name(address as usize).map_or_else(|| format!("0x{:x}", address), str::to_string)
+}
+
+
+fn fence_set(bits: u32) -> String
+{
+    FENCE_SET_NAMES.iter()
+                   .enumerate()
+                   .filter(|( index, _ )| bits & (0b_1000 >> index) != 0)
+                   .map(|( _, name )| *name)
+                   .collect()
+}
+
+
+fn with_rounding(operands: String, rm: u32) -> String
+{
+    if rm == RM_DYN
+    {
+        operands
+    }
+    else
+    {
+        format!("{},{}", operands, ROUNDING_MODES[rm as usize])
+    }
+}
+
+
+fn format_suffix(format: u32) -> Option<&'static str>
+{
+    match format
+    {
+        FMT_S => Some("s"),
+        FMT_D => Some("d"),
+        _     => None
+    }
+}
+
+
+
+impl Instruction
+{
+    // The instruction as objdump would show it at the given address, branch and jump targets
+    // included.
+    pub fn disassemble(&self, address: usize) -> String
+    {
+        self.format(&|offset| format!("{:x}", (address as u64).wrapping_add(offset)))
+    }
+
+
+    fn format(&self, target: &dyn Fn(u64) -> String) -> String
+    {
+        // lui and auipc show their immediate as it's encoded, without the low 12 bits.
+        let upper = format!("{},0x{:x}", x(self.rd), self.raw_instruction >> 12);
+
+        let text = match self.opcode
+            {
+                OP_LUI         => Some(line("lui", upper)),
+                OP_AUIPC       => Some(line("auipc", upper)),
+                OP_JAL         => Some(self.format_jal(target)),
+                OP_JALR        => self.format_jalr(),
+                OP_BR___       => self.format_branch(target),
+                OP_LD___       => self.format_load(),
+                OP_ST___       => self.format_store(),
+                OP_MO1___      => self.format_op_immediate(),
+                OP_MO2___      => self.format_op(),
+                OP_MO3___      => self.format_op_immediate_word(),
+                OP_MO4___      => self.format_op_word(),
+                OP_MISC_MEM___ => self.format_misc_mem(),
+                OP_SYSTEM___   => self.format_system(),
+                OP_EXT_A___    => self.format_atomic(),
+                OP_FLW         => self.format_float_load(),
+                OP_FSW         => self.format_float_store(),
+                OP_FMADD_S___  |
+                OP_FMSUB_S___  |
+                OP_FNMSUB_S___ |
+                OP_FNMADD_S___ => self.format_fused(),
+                OP_RV_F___     => self.format_float(),
+                _              => None
+            };
+
+        // Anything unrecognised is given as it was fetched.
+        text.unwrap_or_else(|| format!(".{}byte\t0x{:x}", self.length, self.encoding))
+    }
+
+
+    fn format_jal(&self, target: &dyn Fn(u64) -> String) -> String
+    {
+        let destination = target(self.jt_immediate());
+
+        match self.rd
+        {
+            0 => line("j", destination),
+            1 => line("jal", destination),
+            _ => line("jal", format!("{},{}", x(self.rd), destination))
+        }
+    }
+
+
+    fn format_jalr(&self) -> Option<String>
+    {
+        if self.func3 != 0
+        {
+            return None;
+        }
+
+        let offset = self.it_immediate() as i64;
+
+        Some(match ( self.rd, self.rs1, offset )
+            {
+                ( 0, 1, 0 ) => "ret".to_string(),
+                ( 0, _, 0 ) => line("jr", x(self.rs1).to_string()),
+                ( 0, _, _ ) => line("jr", format!("{}({})", offset, x(self.rs1))),
+                ( 1, _, 0 ) => line("jalr", x(self.rs1).to_string()),
+                _           => line("jalr", format!("{},{}({})", x(self.rd), offset, x(self.rs1)))
+            })
+    }
+
+
+    // Comparisons with zero have their own names.
+    fn format_branch(&self, target: &dyn Fn(u64) -> String) -> Option<String>
+    {
+        let destination = target(self.bt_immediate());
+
+        let mnemonic = match self.func3
+            {
+                F3_BEQ  => "beq",
+                F3_BNE  => "bne",
+                F3_BLT  => "blt",
+                F3_BGE  => "bge",
+                F3_BLTU => "bltu",
+                F3_BGEU => "bgeu",
+                _       => return None
+            };
+
+        let alias = match ( self.func3, self.rs1, self.rs2 )
+            {
+                ( F3_BEQ, _, 0 ) => Some(( "beqz", self.rs1 )),
+                ( F3_BNE, _, 0 ) => Some(( "bnez", self.rs1 )),
+                ( F3_BLT, _, 0 ) => Some(( "bltz", self.rs1 )),
+                ( F3_BGE, _, 0 ) => Some(( "bgez", self.rs1 )),
+                ( F3_BLT, 0, _ ) => Some(( "bgtz", self.rs2 )),
+                ( F3_BGE, 0, _ ) => Some(( "blez", self.rs2 )),
+                _                => None
+            };
+
+        Some(match alias
+            {
+                Some(( alias, rs )) => line(alias, format!("{},{}", x(rs), destination)),
+                None                =>
+                    line(mnemonic, format!("{},{},{}", x(self.rs1), x(self.rs2), destination))
+            })
+    }
+
+
+    fn format_load(&self) -> Option<String>
+    {
+        let mnemonic = match self.func3
+            {
+                F3_LB  => "lb",
+                F3_LH  => "lh",
+                F3_LW  => "lw",
+                F3_LD  => "ld",
+                F3_LBU => "lbu",
+                F3_LHU => "lhu",
+                F3_LWU => "lwu",
+                _      => return None
+            };
+
+        let offset = self.it_immediate() as i64;
+
+        Some(line(mnemonic, format!("{},{}({})", x(self.rd), offset, x(self.rs1))))
+    }
+
+
+    fn format_store(&self) -> Option<String>
+    {
+        let mnemonic = match self.func3
+            {
+                F3_SB => "sb",
+                F3_SH => "sh",
+                F3_SW => "sw",
+                F3_SD => "sd",
+                _     => return None
+            };
+
+        let offset = self.st_immediate() as i64;
+
+        Some(line(mnemonic, format!("{},{}({})", x(self.rs2), offset, x(self.rs1))))
+    }
+
+
+    fn format_op_immediate(&self) -> Option<String>
+    {
+        let ( rd, rs1 ) = ( x(self.rd), x(self.rs1) );
+        let immediate = self.it_immediate() as i64;
+
+        let mnemonic = match ( self.func3, self.func7 & SHAMT_F7_MASK )
+            {
+                ( F3_ADDI, _ ) if self.rd == 0 && self.rs1 == 0 && immediate == 0 =>
+                    return Some("nop".to_string()),
+
+                ( F3_ADDI, _ ) if self.rs1 == 0 =>
+                    return Some(line("li", format!("{},{}", rd, immediate))),
+
+                ( F3_ADDI, _ ) if immediate == 0 =>
+                    return Some(line("mv", format!("{},{}", rd, rs1))),
+
+                ( F3_SLTIU, _ ) if immediate == 1 =>
+                    return Some(line("seqz", format!("{},{}", rd, rs1))),
+
+                ( F3_XORI, _ ) if immediate == -1 =>
+                    return Some(line("not", format!("{},{}", rd, rs1))),
+
+                ( F3_SL___, F7_SLLI ) => "slli",
+                ( F3_SR___, F7_SRLI ) => "srli",
+                ( F3_SR___, F7_SRAI ) => "srai",
+
+                ( F3_SL___, _ ) | ( F3_SR___, _ ) =>
+                    return None,
+
+                ( F3_ADDI,  _ ) => "addi",
+                ( F3_SLTI,  _ ) => "slti",
+                ( F3_SLTIU, _ ) => "sltiu",
+                ( F3_XORI,  _ ) => "xori",
+                ( F3_ORI,   _ ) => "ori",
+                ( F3_ANDI,  _ ) => "andi",
+                _               => return None
+            };
+
+        // Shift amounts are shown in hex.
+        let operands = match self.func3
+            {
+                F3_SL___ | F3_SR___ => format!("{},{},0x{:x}", rd, rs1, self.shamt()),
+                _                   => format!("{},{},{}", rd, rs1, immediate)
+            };
+
+        Some(line(mnemonic, operands))
+    }
+
+
+    fn format_op(&self) -> Option<String>
+    {
+        let ( rd, rs1, rs2 ) = ( x(self.rd), x(self.rs1), x(self.rs2) );
+
+        let mnemonic = match ( self.func7, self.func3 )
+            {
+                ( F7_ADD, F3_AS___ ) if self.rs1 == 0 =>
+                    return Some(line("mv", format!("{},{}", rd, rs2))),
+
+                ( F7_SUB, F3_AS___ ) if self.rs1 == 0 =>
+                    return Some(line("neg", format!("{},{}", rd, rs2))),
+
+                ( F7_SLT, F3_SLT ) if self.rs2 == 0 =>
+                    return Some(line("sltz", format!("{},{}", rd, rs1))),
+
+                ( F7_SLT, F3_SLT ) if self.rs1 == 0 =>
+                    return Some(line("sgtz", format!("{},{}", rd, rs2))),
+
+                ( F7_SLTU, F3_SLTU ) if self.rs1 == 0 =>
+                    return Some(line("snez", format!("{},{}", rd, rs2))),
+
+                ( F7_ADD,  F3_AS___ )  => "add",
+                ( F7_SUB,  F3_AS___ )  => "sub",
+                ( F7_SLL,  F3_SLL )    => "sll",
+                ( F7_SLT,  F3_SLT )    => "slt",
+                ( F7_SLTU, F3_SLTU )   => "sltu",
+                ( F7_XOR,  F3_XOR )    => "xor",
+                ( F7_SRL,  F3_SR2___ ) => "srl",
+                ( F7_SRA,  F3_SR2___ ) => "sra",
+                ( F7_OR,   F3_OR )     => "or",
+                ( F7_AND,  F3_AND )    => "and",
+
+                ( F7_M_EXT, F3_32_MUL___ )    => "mul",
+                ( F7_M_EXT, F3_32_MULH___ )   => "mulh",
+                ( F7_M_EXT, F3_32_MULHSU___ ) => "mulhsu",
+                ( F7_M_EXT, F3_32_MULHU___ )  => "mulhu",
+                ( F7_M_EXT, F3_32_DIV___ )    => "div",
+                ( F7_M_EXT, F3_32_DIVU___ )   => "divu",
+                ( F7_M_EXT, F3_32_REM___ )    => "rem",
+                ( F7_M_EXT, F3_32_REMU___ )   => "remu",
+
+                _ =>
+                    return None
+            };
+
+        Some(line(mnemonic, format!("{},{},{}", rd, rs1, rs2)))
+    }
+
+
+    // The word shifts only have five bits of shift amount, held in the rs2 field.
+    fn format_op_immediate_word(&self) -> Option<String>
+    {
+        let ( rd, rs1 ) = ( x(self.rd), x(self.rs1) );
+        let immediate = self.it_immediate() as i64;
+
+        let mnemonic = match ( self.func3, self.func7 )
+            {
+                ( F3_ADDIW, _ ) if immediate == 0 =>
+                    return Some(line("sext.w", format!("{},{}", rd, rs1))),
+
+                ( F3_ADDIW, _ ) =>
+                    return Some(line("addiw", format!("{},{},{}", rd, rs1, immediate))),
+
+                ( F3_SHL___, F7_SLLIW ) => "slliw",
+                ( F3_SHR___, F7_SRLIW ) => "srliw",
+                ( F3_SHR___, F7_SRAIW ) => "sraiw",
+                _                       => return None
+            };
+
+        Some(line(mnemonic, format!("{},{},0x{:x}", rd, rs1, self.rs2)))
+    }
+
+
+    fn format_op_word(&self) -> Option<String>
+    {
+        let ( rd, rs1, rs2 ) = ( x(self.rd), x(self.rs1), x(self.rs2) );
+
+        let mnemonic = match ( self.func7, self.func3 )
+            {
+                ( F7_SUBW, F3_ASW___ ) if self.rs1 == 0 =>
+                    return Some(line("negw", format!("{},{}", rd, rs2))),
+
+                ( F7_ADDW, F3_ASW___ )  => "addw",
+                ( F7_SUBW, F3_ASW___ )  => "subw",
+                ( F7_SLLW, F3_SLLW___ ) => "sllw",
+                ( F7_SRLW, F3_SHR___ )  => "srlw",
+                ( F7_SRAW, F3_SHR___ )  => "sraw",
+
+                ( F7_M_EXT, F3_64_MULW___ )  => "mulw",
+                ( F7_M_EXT, F3_64_DIVW___ )  => "divw",
+                ( F7_M_EXT, F3_64_DIVUW___ ) => "divuw",
+                ( F7_M_EXT, F3_64_REMW___ )  => "remw",
+                ( F7_M_EXT, F3_64_REMUW___ ) => "remuw",
+
+                _ =>
+                    return None
+            };
+
+        Some(line(mnemonic, format!("{},{},{}", rd, rs1, rs2)))
+    }
+
+
+    // A fence that orders everything is shown bare.
+    fn format_misc_mem(&self) -> Option<String>
+    {
+        match self.func3
+        {
+            F3_FENCE =>
+                {
+                    let mode = self.raw_instruction >> 28;
+                    let predecessor = (self.raw_instruction >> 24) & 0b_1111;
+                    let successor = (self.raw_instruction >> 20) & 0b_1111;
+
+                    Some(match ( mode, predecessor, successor )
+                        {
+                            ( FENCE_FM_TSO, FENCE_RW, FENCE_RW ) =>
+                                "fence.tso".to_string(),
+
+                            ( _, FENCE_ALL, FENCE_ALL ) =>
+                                "fence".to_string(),
+
+                            _ =>
+                                line("fence",
+                                     format!("{},{}", fence_set(predecessor), fence_set(successor)))
+                        })
+                },
+
+            F3_FENCE_I =>
+                Some("fence.i".to_string()),
+
+            _ =>
+                None
+        }
+    }
+
+
+    fn format_system(&self) -> Option<String>
+    {
+        if self.func3 == F3_SYS_PRIV___
+        {
+            return self.format_privileged();
+        }
+
+        let ( rd, rs1 ) = ( x(self.rd), x(self.rs1) );
+        let address = self.func12;
+        let name = csr(address);
+
+        // The immediate forms hold their value in the rs1 field.
+        let immediate = self.rs1;
+
+        let read_alias = match address as usize
+            {
+                CSR_CYCLE   => Some("rdcycle"),
+                CSR_TIME    => Some("rdtime"),
+                CSR_INSTRET => Some("rdinstret"),
+                CSR_FFLAGS  => Some("frflags"),
+                CSR_FRM     => Some("frrm"),
+                CSR_FCSR    => Some("frcsr"),
+                _           => None
+            };
+
+        let write_alias = match address as usize
+            {
+                CSR_FFLAGS => Some("fsflags"),
+                CSR_FRM    => Some("fsrm"),
+                CSR_FCSR   => Some("fscsr"),
+                _          => None
+            };
+
+        Some(match ( self.func3, self.rd, self.rs1 )
+            {
+                ( F3_CSRRS, _, 0 ) =>
+                    match read_alias
+                    {
+                        Some(alias) => line(alias, rd.to_string()),
+                        None        => line("csrr", format!("{},{}", rd, name))
+                    },
+
+                ( F3_CSRRW, 0, _ ) =>
+                    match write_alias
+                    {
+                        Some(alias) => line(alias, rs1.to_string()),
+                        None        => line("csrw", format!("{},{}", name, rs1))
+                    },
+
+                ( F3_CSRRW, _, _ ) =>
+                    match write_alias
+                    {
+                        Some(alias) => line(alias, format!("{},{}", rd, rs1)),
+                        None        => line("csrrw", format!("{},{},{}", rd, name, rs1))
+                    },
+
+                ( F3_CSRRWI, 0, _ ) =>
+                    match write_alias
+                    {
+                        Some(alias) => line(&format!("{}i", alias), immediate.to_string()),
+                        None        => line("csrwi", format!("{},{}", name, immediate))
+                    },
+
+                ( F3_CSRRS,  0, _ ) => line("csrs", format!("{},{}", name, rs1)),
+                ( F3_CSRRC,  0, _ ) => line("csrc", format!("{},{}", name, rs1)),
+                ( F3_CSRRSI, 0, _ ) => line("csrsi", format!("{},{}", name, immediate)),
+                ( F3_CSRRCI, 0, _ ) => line("csrci", format!("{},{}", name, immediate)),
+
+                ( F3_CSRRS,  _, _ ) => line("csrrs", format!("{},{},{}", rd, name, rs1)),
+                ( F3_CSRRC,  _, _ ) => line("csrrc", format!("{},{},{}", rd, name, rs1)),
+                ( F3_CSRRWI, _, _ ) => line("csrrwi", format!("{},{},{}", rd, name, immediate)),
+                ( F3_CSRRSI, _, _ ) => line("csrrsi", format!("{},{},{}", rd, name, immediate)),
+                ( F3_CSRRCI, _, _ ) => line("csrrci", format!("{},{},{}", rd, name, immediate)),
+
+                _ =>
+                    return None
+            })
+    }
+
+
+    fn format_privileged(&self) -> Option<String>
+    {
+        if self.rd != 0
+        {
+            return None;
+        }
+
+        if self.func7 == F7_SFENCE_VMA
+        {
+            return Some(match ( self.rs1, self.rs2 )
+                {
+                    ( 0, 0 ) => "sfence.vma".to_string(),
+                    ( _, 0 ) => line("sfence.vma", x(self.rs1).to_string()),
+                    _        => line("sfence.vma", format!("{},{}", x(self.rs1), x(self.rs2)))
+                });
+        }
+
+        if self.rs1 != 0
+        {
+            return None;
+        }
+
+        let mnemonic = match self.func12
+            {
+                F12_ECALL  => "ecall",
+                F12_EBREAK => "ebreak",
+                F12_MRET   => "mret",
+                F12_SRET   => "sret",
+                F12_WFI    => "wfi",
+                _          => return None
+            };
+
+        Some(mnemonic.to_string())
+    }
+
+
+    // Acquire and release show as suffixes.
+    fn format_atomic(&self) -> Option<String>
+    {
+        let size = match self.func3
+            {
+                F3_EXT_A32___ => "w",
+                F3_EXT_A64___ => "d",
+                _             => return None
+            };
+
+        // The word and double-word operations share their func7s.
+        let operation = match self.func7 & EXT_A_F7_MASK
+            {
+                F7_LR_W___   => "lr",
+                F7_SC_W      => "sc",
+                F7_AMOSWAP_W => "amoswap",
+                F7_AMOADD_W  => "amoadd",
+                F7_AMOXOR_W  => "amoxor",
+                F7_AMOAND_W  => "amoand",
+                F7_AMOOR_W   => "amoor",
+                F7_AMOMIN_W  => "amomin",
+                F7_AMOMAX_W  => "amomax",
+                F7_AMOMINU_W => "amominu",
+                F7_AMOMAXU_W => "amomaxu",
+                _            => return None
+            };
+
+        let ordering = match ( self.func7 & AMO_AQ != 0, self.func7 & AMO_RL != 0 )
+            {
+                ( true,  true )  => ".aqrl",
+                ( true,  false ) => ".aq",
+                ( false, true )  => ".rl",
+                ( false, false ) => ""
+            };
+
+        let mnemonic = format!("{}.{}{}", operation, size, ordering);
+        let ( rd, rs1, rs2 ) = ( x(self.rd), x(self.rs1), x(self.rs2) );
+
+        if self.func7 & EXT_A_F7_MASK == F7_LR_W___
+        {
+            return match self.rs2
+                {
+                    RS2_LR_W => Some(line(&mnemonic, format!("{},({})", rd, rs1))),
+                    _        => None
+                };
+        }
+
+        Some(line(&mnemonic, format!("{},{},({})", rd, rs2, rs1)))
+    }
+
+
+    fn format_float_load(&self) -> Option<String>
+    {
+        let mnemonic = match self.func3
+            {
+                F3_FLW => "flw",
+                F3_FLD => "fld",
+                _      => return None
+            };
+
+        let offset = self.it_immediate() as i64;
+
+        Some(line(mnemonic, format!("{},{}({})", f(self.rd), offset, x(self.rs1))))
+    }
+
+
+    fn format_float_store(&self) -> Option<String>
+    {
+        let mnemonic = match self.func3
+            {
+                F3_FSW => "fsw",
+                F3_FSD => "fsd",
+                _      => return None
+            };
+
+        let offset = self.st_immediate() as i64;
+
+        Some(line(mnemonic, format!("{},{}({})", f(self.rs2), offset, x(self.rs1))))
+    }
+
+
+    fn format_fused(&self) -> Option<String>
+    {
+        let suffix = format_suffix(self.func7 & RS3_MASK)?;
+
+        let operation = match self.opcode
+            {
+                OP_FMADD_S___  => "fmadd",
+                OP_FMSUB_S___  => "fmsub",
+                OP_FNMSUB_S___ => "fnmsub",
+                _              => "fnmadd"
+            };
+
+        let operands = format!("{},{},{},{}", f(self.rd), f(self.rs1), f(self.rs2), f(self.rs3));
+
+        Some(line(&format!("{}.{}", operation, suffix), with_rounding(operands, self.func3)))
+    }
+
+
+    fn format_float(&self) -> Option<String>
+    {
+        let suffix = format_suffix(self.func7 & RS3_MASK)?;
+        let operation = self.func7 >> 2;
+        let rm = self.func3;
+
+        let ( frd, frs1, frs2 ) = ( f(self.rd), f(self.rs1), f(self.rs2) );
+        let ( rd, rs1 ) = ( x(self.rd), x(self.rs1) );
+
+        // The integer sizes of the conversions, by rs2.
+        let integer = match self.rs2
+            {
+                RS2_FCVT_W_S  => "w",
+                RS2_FCVT_WU_S => "wu",
+                RS2_FCVT_L_S  => "l",
+                RS2_FCVT_LU_S => "lu",
+                _             => ""
+            };
+
+        let ( mnemonic, operands ) = match ( operation, rm )
+            {
+                ( FP_ADD, _ ) | ( FP_SUB, _ ) | ( FP_MUL, _ ) | ( FP_DIV, _ ) =>
+                    {
+                        let name = match operation
+                            {
+                                FP_ADD => "fadd",
+                                FP_SUB => "fsub",
+                                FP_MUL => "fmul",
+                                _      => "fdiv"
+                            };
+
+                        ( name, with_rounding(format!("{},{},{}", frd, frs1, frs2), rm) )
+                    },
+
+                ( FP_SQRT, _ ) if self.rs2 == RS2_F7_FSQRT_S =>
+                    ( "fsqrt", with_rounding(format!("{},{}", frd, frs1), rm) ),
+
+                // Sign injection from a register into itself is a move, a negation or an absolute.
+                ( FP_SGNJ, _ ) if self.rs1 == self.rs2 =>
+                    {
+                        let name = match rm
+                            {
+                                F3_FSGNJ_S  => "fmv",
+                                F3_FSGNJN_S => "fneg",
+                                F3_FSGNJX_S => "fabs",
+                                _           => return None
+                            };
+
+                        ( name, format!("{},{}", frd, frs1) )
+                    },
+
+                ( FP_SGNJ, F3_FSGNJ_S )  => ( "fsgnj", format!("{},{},{}", frd, frs1, frs2) ),
+                ( FP_SGNJ, F3_FSGNJN_S ) => ( "fsgnjn", format!("{},{},{}", frd, frs1, frs2) ),
+                ( FP_SGNJ, F3_FSGNJX_S ) => ( "fsgnjx", format!("{},{},{}", frd, frs1, frs2) ),
+                ( FP_MIN_MAX, F3_FMIN_S ) => ( "fmin", format!("{},{},{}", frd, frs1, frs2) ),
+                ( FP_MIN_MAX, F3_FMAX_S ) => ( "fmax", format!("{},{},{}", frd, frs1, frs2) ),
+
+                ( FP_COMPARE, F3_FEQ_D ) => ( "feq", format!("{},{},{}", rd, frs1, frs2) ),
+                ( FP_COMPARE, F3_FLT_D ) => ( "flt", format!("{},{},{}", rd, frs1, frs2) ),
+                ( FP_COMPARE, F3_FLE_D ) => ( "fle", format!("{},{},{}", rd, frs1, frs2) ),
+
+                // Between single and double precision, widening is exact so has no rounding mode.
+                ( FP_CVT_FP, _ ) =>
+                    {
+                        let text = match ( self.func7, self.rs2 )
+                            {
+                                ( F7_FCVT_S_D___, RS2_FCVT_S_D ) =>
+                                    line("fcvt.s.d", with_rounding(format!("{},{}", frd, frs1),
+                                                                   rm)),
+
+                                ( F7_FCVT_D_S___, RS2_FCVT_D_S ) =>
+                                    line("fcvt.d.s", format!("{},{}", frd, frs1)),
+
+                                _ =>
+                                    return None
+                            };
+
+                        return Some(text);
+                    },
+
+                ( FP_CVT_TO_INT, _ ) if !integer.is_empty() =>
+                    {
+                        let mnemonic = format!("fcvt.{}.{}", integer, suffix);
+                        let operands = format!("{},{}", rd, frs1);
+
+                        return Some(line(&mnemonic, with_rounding(operands, rm)));
+                    },
+
+                // Converting a word into a double is exact too.
+                ( FP_CVT_FROM_INT, _ ) if !integer.is_empty() =>
+                    {
+                        let mnemonic = format!("fcvt.{}.{}", suffix, integer);
+                        let operands = format!("{},{}", frd, rs1);
+
+                        let exact =    suffix == "d"
+                                    && matches!(self.rs2, RS2_FCVT_D_W | RS2_FCVT_D_WU);
+
+                        return Some(if exact
+                            {
+                                line(&mnemonic, operands)
+                            }
+                            else
+                            {
+                                line(&mnemonic, with_rounding(operands, rm))
+                            });
+                    },
+
+                ( FP_MV_TO_INT, F3_32_FMV___ ) if self.rs2 == RS2_FMV_X_W =>
+                    {
+                        let mnemonic = if suffix == "s" { "fmv.x.w" } else { "fmv.x.d" };
+                        return Some(line(mnemonic, format!("{},{}", rd, frs1)));
+                    },
+
+                ( FP_MV_TO_INT, F3_32_FC___ ) if self.rs2 == RS2_FCLASS_S =>
+                    ( "fclass", format!("{},{}", rd, frs1) ),
+
+                ( FP_MV_FROM_INT, F3_32_FMV___ ) if self.rs2 == RS2_FMV_W_X =>
+                    {
+                        let mnemonic = if suffix == "s" { "fmv.w.x" } else { "fmv.d.x" };
+                        return Some(line(mnemonic, format!("{},{}", frd, rs1)));
+                    },
+
+                _ =>
+                    return None
+            };
+
+        Some(line(&format!("{}.{}", mnemonic, suffix), operands))
+    }
+}
+
+
+// Without an address to go on, branch and jump targets are shown relative to the instruction, the
+// way an assembler would take them.
+impl fmt::Display for Instruction
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result
+    {
+        let text = self.format(&|offset|
+            {
+                match offset as i64
+                {
+                    offset if offset < 0 => format!(".{}", offset),
+                    offset               => format!(".+{}", offset)
+                }
+            });
+
+        formatter.write_str(&text)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    const ADDRESS: usize = 0x_8000_0000;
+
+
+    // Encodings against what objdump shows for them at ADDRESS.
+    #[test]
+    fn disassembly()
+    {
+        let table =
+            [
+                ( 0x_12345537, "lui\ta0,0x12345" ),
+                ( 0x_00000297, "auipc\tt0,0x0" ),
+                ( 0x_ffb00513, "li\ta0,-5" ),
+                ( 0x_00058513, "mv\ta0,a1" ),
+                ( 0x_00000013, "nop" ),
+                ( 0x_ff010113, "addi\tsp,sp,-16" ),
+                ( 0x_fff54513, "not\ta0,a0" ),
+                ( 0x_0015b513, "seqz\ta0,a1" ),
+                ( 0x_02051513, "slli\ta0,a0,0x20" ),
+                ( 0x_43f65593, "srai\ta1,a2,0x3f" ),
+                ( 0x_40b00533, "neg\ta0,a1" ),
+                ( 0x_00b03533, "snez\ta0,a1" ),
+                ( 0x_0005a533, "sltz\ta0,a1" ),
+                ( 0x_00c58533, "add\ta0,a1,a2" ),
+                ( 0x_027322b3, "mulhsu\tt0,t1,t2" ),
+                ( 0x_0005851b, "sext.w\ta0,a1" ),
+                ( 0x_fff5851b, "addiw\ta0,a1,-1" ),
+                ( 0x_41f5551b, "sraiw\ta0,a0,0x1f" ),
+                ( 0x_40b0053b, "negw\ta0,a1" ),
+                ( 0x_0349d93b, "divuw\ts2,s3,s4" ),
+                ( 0x_00813503, "ld\ta0,8(sp)" ),
+                ( 0x_fe143c23, "sd\tra,-8(s0)" ),
+                ( 0x_7ff7c283, "lbu\tt0,2047(a5)" ),
+                ( 0x_00050863, "beqz\ta0,80000010" ),
+                ( 0x_feb51ee3, "bne\ta0,a1,7ffffffc" ),
+                ( 0x_00a04463, "bgtz\ta0,80000008" ),
+                ( 0x_80d67063, "bgeu\ta2,a3,7ffff000" ),
+                ( 0x_100000ef, "jal\t80000100" ),
+                ( 0x_ff9ff06f, "j\t7ffffff8" ),
+                ( 0x_0040036f, "jal\tt1,80000004" ),
+                ( 0x_00008067, "ret" ),
+                ( 0x_00028067, "jr\tt0" ),
+                ( 0x_000780e7, "jalr\ta5" ),
+                ( 0x_ffc58567, "jalr\ta0,-4(a1)" ),
+                ( 0x_00000073, "ecall" ),
+                ( 0x_00100073, "ebreak" ),
+                ( 0x_30200073, "mret" ),
+                ( 0x_10200073, "sret" ),
+                ( 0x_10500073, "wfi" ),
+                ( 0x_12000073, "sfence.vma" ),
+                ( 0x_12050073, "sfence.vma\ta0" ),
+                ( 0x_12b50073, "sfence.vma\ta0,a1" ),
+                ( 0x_30002573, "csrr\ta0,mstatus" ),
+                ( 0x_18051073, "csrw\tsatp,a0" ),
+                ( 0x_340292f3, "csrrw\tt0,mscratch,t0" ),
+                ( 0x_30046073, "csrsi\tmstatus,8" ),
+                ( 0x_10017573, "csrrci\ta0,sstatus,2" ),
+                ( 0x_7c002573, "csrr\ta0,0x7c0" ),
+                ( 0x_c0002573, "rdcycle\ta0" ),
+                ( 0x_00302573, "frcsr\ta0" ),
+                ( 0x_0020d073, "fsrmi\t1" ),
+                ( 0x_0ff0000f, "fence" ),
+                ( 0x_0330000f, "fence\trw,rw" ),
+                ( 0x_8330000f, "fence.tso" ),
+                ( 0x_0000100f, "fence.i" ),
+                ( 0x_1405b52f, "lr.d.aq\ta0,(a1)" ),
+                ( 0x_18d7262f, "sc.w\ta2,a3,(a4)" ),
+                ( 0x_0eb6252f, "amoswap.w.aqrl\ta0,a1,(a2)" ),
+                ( 0x_0251302f, "amoadd.d.rl\tzero,t0,(sp)" ),
+                ( 0x_00412507, "flw\tfa0,4(sp)" ),
+                ( 0x_fe843827, "fsd\tfs0,-16(s0)" ),
+                ( 0x_02c59553, "fadd.d\tfa0,fa1,fa2,rtz" ),
+                ( 0x_00c5f553, "fadd.s\tfa0,fa1,fa2" ),
+                ( 0x_10208053, "fmul.s\tft0,ft1,ft2,rne" ),
+                ( 0x_68c5f543, "fmadd.s\tfa0,fa1,fa2,fa3" ),
+                ( 0x_6ac5c54b, "fnmsub.d\tfa0,fa1,fa2,fa3,rmm" ),
+                ( 0x_5a00f053, "fsqrt.d\tft0,ft1" ),
+                ( 0x_22b58553, "fmv.d\tfa0,fa1" ),
+                ( 0x_20b59553, "fneg.s\tfa0,fa1" ),
+                ( 0x_22b5a553, "fabs.d\tfa0,fa1" ),
+                ( 0x_20c5a553, "fsgnjx.s\tfa0,fa1,fa2" ),
+                ( 0x_2ac58553, "fmin.d\tfa0,fa1,fa2" ),
+                ( 0x_c2051553, "fcvt.w.d\ta0,fa0,rtz" ),
+                ( 0x_c0357553, "fcvt.lu.s\ta0,fa0" ),
+                ( 0x_42058553, "fcvt.d.s\tfa0,fa1" ),
+                ( 0x_4015f553, "fcvt.s.d\tfa0,fa1" ),
+                ( 0x_d2050553, "fcvt.d.w\tfa0,a0" ),
+                ( 0x_d0253553, "fcvt.s.l\tfa0,a0,rup" ),
+                ( 0x_a0b52553, "feq.s\ta0,fa0,fa1" ),
+                ( 0x_a2b50553, "fle.d\ta0,fa0,fa1" ),
+                ( 0x_e2050553, "fmv.x.d\ta0,fa0" ),
+                ( 0x_f0050553, "fmv.w.x\tfa0,a0" ),
+                ( 0x_e2051553, "fclass.d\ta0,fa0" )
+
+            ];
+
+        for &( encoding, expected ) in &table
+        {
+            assert_eq!(Instruction::new(encoding).disassemble(ADDRESS), expected,
+                       "{:08x}", encoding);
+        }
+    }
+
+
+    // Compressed instructions show as what they expand to, anything unrecognised as it was
+    // fetched.
+    #[test]
+    fn encodings()
+    {
+        assert_eq!(Instruction::compressed(0x_4505, 0x_00100513).disassemble(ADDRESS), "li\ta0,1");
+        assert_eq!(Instruction::compressed(0x_8082, 0x_00008067).disassemble(ADDRESS), "ret");
+
+        assert_eq!(Instruction::new(0x_ffff_ffff).disassemble(ADDRESS), ".4byte\t0xffffffff");
+        assert_eq!(Instruction::new(0x_0000_707f).disassemble(ADDRESS), ".4byte\t0x707f");
+    }
+
+
+    // Without an address, targets are relative.
+    #[test]
+    fn relative_targets()
+    {
+        assert_eq!(Instruction::new(0x_00050863).to_string(), "beqz\ta0,.+16");
+        assert_eq!(Instruction::new(0x_feb51ee3).to_string(), "bne\ta0,a1,.-4");
+        assert_eq!(Instruction::new(0x_ff9ff06f).to_string(), "j\t.-8");
+    }
+}
@@ -0,0 +1,76 @@
+
+// Helpers shared by the floating-point extensions.  The floating-point registers are FLEN (64)
+// bits wide, so single-precision values are kept NaN-boxed: the upper 32 bits are all ones.  Any
+// register that isn't properly boxed reads back as the canonical NaN.
+//
+// Floating-point values are handled as raw bit patterns, the arithmetic itself is done by the
+// software implementation in softfloat.rs.
+
+
+use super::opcodes::*;
+
+
+// Rounding modes, as encoded in an instruction's rm field and in frm.
+pub const RM_RNE: u32 = 0b_000;
+pub const RM_RTZ: u32 = 0b_001;
+pub const RM_RDN: u32 = 0b_010;
+pub const RM_RUP: u32 = 0b_011;
+pub const RM_RMM: u32 = 0b_100;
+pub const RM_DYN: u32 = 0b_111;
+
+
+// Exception flags, as accrued in fflags.
+pub const FLAG_NX: u32 = 0b_00001;  // Inexact
+pub const FLAG_UF: u32 = 0b_00010;  // Underflow
+pub const FLAG_OF: u32 = 0b_00100;  // Overflow
+pub const FLAG_DZ: u32 = 0b_01000;  // Divide by zero
+pub const FLAG_NV: u32 = 0b_10000;  // Invalid operation
+
+
+// fclass result bits.
+pub const FCLASS_NEG_INFINITY:  u64 = 1 << 0;
+pub const FCLASS_NEG_NORMAL:    u64 = 1 << 1;
+pub const FCLASS_NEG_SUBNORMAL: u64 = 1 << 2;
+pub const FCLASS_NEG_ZERO:      u64 = 1 << 3;
+pub const FCLASS_POS_ZERO:      u64 = 1 << 4;
+pub const FCLASS_POS_SUBNORMAL: u64 = 1 << 5;
+pub const FCLASS_POS_NORMAL:    u64 = 1 << 6;
+pub const FCLASS_POS_INFINITY:  u64 = 1 << 7;
+pub const FCLASS_SIGNALING_NAN: u64 = 1 << 8;
+pub const FCLASS_QUIET_NAN:     u64 = 1 << 9;
+
+
+pub const F32_CANONICAL_NAN: u64 = 0x_7fc00000;
+pub const F32_SIGN_BIT:      u64 = 0x_80000000;
+pub const F64_SIGN_BIT:      u64 = 0x_80000000_00000000;
+
+const F32_NAN_BOX: u64 = 0x_ffffffff_00000000;
+
+
+
+pub fn box_f32(value: u64) -> u64
+{
+    F32_NAN_BOX | (value & 0x_ffffffff)
+}
+
+
+pub fn unbox_f32(value: u64) -> u64
+{
+    if (value & F32_NAN_BOX) == F32_NAN_BOX
+    {
+        value & 0x_ffffffff
+    }
+    else
+    {
+        F32_CANONICAL_NAN
+    }
+}
+
+
+// The opcodes shared by the F and D extensions, all of which need the floating-point unit enabled.
+pub fn is_float_opcode(opcode: u32) -> bool
+{
+    matches!(opcode, OP_FLW | OP_FSW |
+                     OP_FMADD_S___ | OP_FMSUB_S___ | OP_FNMSUB_S___ | OP_FNMADD_S___ |
+                     OP_RV_F___)
+}
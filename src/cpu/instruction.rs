@@ -42,13 +42,19 @@ fn decode_immediate_signed(source: u32, fields: &[ FieldMask ], immediate_size:
 #[derive(Debug, Copy, Clone)]
 pub struct Instruction
 {
-    raw_instruction: u32,
+    pub raw_instruction: u32,
+
+    // The bits as fetched, and their length in bytes.  These only differ from the instruction
+    // being executed for compressed instructions, which are expanded before decoding.
+    pub encoding: u32,
+    pub length: usize,
 
     pub opcode: u32,
 
     pub rd: usize,
     pub rs1: usize,
     pub rs2: usize,
+    pub rs3: usize,
 
     pub func3: u32,
     pub func7: u32,
@@ -65,12 +71,42 @@ impl Instruction
         let rd  =  ((raw_instruction & 0b_00000000_00000000_00001111_10000000) >> 7) as usize;
         let rs1 =  ((raw_instruction & 0b_00000000_00001111_10000000_00000000) >> 15) as usize;
         let rs2 =  ((raw_instruction & 0b_00000001_11110000_00000000_00000000) >> 20) as usize;
+        let rs3 =  ((raw_instruction & 0b_11111000_00000000_00000000_00000000) >> 27) as usize;
 
         let func3  = (raw_instruction & 0b_00000000_00000000_01110000_00000000) >> 12;
         let func7  = (raw_instruction & 0b_11111110_00000000_00000000_00000000) >> 25;
-        let func12 = (raw_instruction & 0b_11111111_11110000_00000000_00000000) >> 21;
+        let func12 = (raw_instruction & 0b_11111111_11110000_00000000_00000000) >> 20;
+
+        Self { raw_instruction,
+               encoding: raw_instruction,
+               length: 4,
+               opcode,
+               rd,
+               rs1,
+               rs2,
+               rs3,
+               func3,
+               func7,
+               func12 }
+    }
+
+
+    // A compressed instruction along with the full instruction it expands into.
+    pub const fn compressed(encoding: u16, expanded: u32) -> Self
+    {
+        let mut instruction = Self::new(expanded);
 
-        Self { raw_instruction, opcode, rd, rs1, rs2, func3, func7, func12 }
+        instruction.encoding = encoding as u32;
+        instruction.length = 2;
+
+        instruction
+    }
+
+
+    // The shift amount of the RV64 shift-immediate instructions, bits 25:20.
+    pub fn shamt(&self) -> u32
+    {
+        (self.raw_instruction >> 20) & 0b_111111
     }
 
 
@@ -97,7 +133,7 @@ impl Instruction
                       FieldMask { mask: 0b_00000000_00000000_00001111_00000000, shift:  7  },
                       FieldMask { mask: 0b_00000000_00000000_00000000_10000000, shift: -4  } ];
 
-        decode_immediate_signed(self.raw_instruction, &masks, 20)
+        decode_immediate_signed(self.raw_instruction, &masks, 13)
     }
 
     pub fn ut_immediate(&self) -> u64
@@ -109,11 +145,11 @@ impl Instruction
 
     pub fn jt_immediate(&self) -> u64
     {
-        let masks = [ FieldMask { mask: 0b_10000000_00000000_00000000_00000000, shift: 10 },
-                      FieldMask { mask: 0b_01111111_11100000_00000000_00000000, shift: 21 },
+        let masks = [ FieldMask { mask: 0b_10000000_00000000_00000000_00000000, shift: 11 },
+                      FieldMask { mask: 0b_01111111_11100000_00000000_00000000, shift: 20 },
                       FieldMask { mask: 0b_00000000_00010000_00000000_00000000, shift: 9  },
                       FieldMask { mask: 0b_00000000_00001111_11110000_00000000, shift: 0  } ];
 
-        decode_immediate_signed(self.raw_instruction, &masks, 20)
+        decode_immediate_signed(self.raw_instruction, &masks, 21)
     }
 }
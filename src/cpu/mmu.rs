@@ -0,0 +1,610 @@
+
+use super::{ cpu::{ Cpu, PrivilegeLvel }, csr::*, trap::Exception };
+
+
+// Virtual memory, as controlled by satp.  Sv39, Sv48 and Sv57 translation is supported, with the
+// accessed and dirty bits managed by the hardware.  Translations are cached in a small direct
+// mapped TLB which is only flushed by sfence.vma or a write to satp.
+
+
+pub const PAGE_SIZE:  usize = 4096;
+pub const PAGE_SHIFT: u64 = 12;
+
+
+// satp holds the translation mode above the address space id and the root page table's number.
+pub const SATP_MODE_SHIFT: u64 = 60;
+pub const SATP_ASID_MASK:  u64 = 0x_ffff << 44;
+pub const SATP_PPN_MASK:   u64 = (1 << 44) - 1;
+
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+pub const SATP_MODE_SV48: u64 = 9;
+pub const SATP_MODE_SV57: u64 = 10;
+
+
+// Page table entry fields.
+pub const PTE_V: u64 = 1 << 0;
+pub const PTE_R: u64 = 1 << 1;
+pub const PTE_W: u64 = 1 << 2;
+pub const PTE_X: u64 = 1 << 3;
+pub const PTE_U: u64 = 1 << 4;
+pub const PTE_A: u64 = 1 << 6;
+pub const PTE_D: u64 = 1 << 7;
+
+pub const PTE_PPN_SHIFT: u64 = 10;
+pub const PTE_PPN_MASK:  u64 = (1 << 44) - 1;
+
+// Bits 63:54 belong to extensions that aren't implemented, so they must be clear.
+pub const PTE_RESERVED: u64 = 0b_11111111_11 << 54;
+
+// Each level of the page table translates this many bits of the virtual page number.
+const VPN_BITS: u64 = 9;
+const VPN_MASK: u64 = (1 << VPN_BITS) - 1;
+
+pub const TLB_SIZE: usize = 256;
+
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AccessType
+{
+    Instruction,
+    Load,
+    Store
+}
+
+
+impl AccessType
+{
+    pub fn page_fault(self, address: usize) -> Exception
+    {
+        match self
+        {
+            AccessType::Instruction => Exception::InstructionPageFault(address as u64),
+            AccessType::Load        => Exception::LoadPageFault(address as u64),
+            AccessType::Store       => Exception::StorePageFault(address as u64)
+        }
+    }
+
+
+    pub fn access_fault(self, address: usize) -> Exception
+    {
+        match self
+        {
+            AccessType::Instruction => Exception::InstructionAccessFault(address as u64),
+            AccessType::Load        => Exception::LoadAccessFault(address as u64),
+            AccessType::Store       => Exception::StoreAccessFault(address as u64)
+        }
+    }
+}
+
+
+
+// A cached translation of a single 4KiB virtual page, superpages are cached a page at a time.  The
+// level of the leaf is kept so that flushing any page of a superpage flushes all of it.
+#[derive(Debug, Copy, Clone)]
+struct TlbEntry
+{
+    vpn: u64,
+    ppn: u64,
+    pte: u64,
+    level: u64
+}
+
+
+pub struct Tlb
+{
+    entries: [ Option<TlbEntry>; TLB_SIZE ]
+}
+
+
+impl Tlb
+{
+    pub fn new() -> Self
+    {
+        Self { entries: [ None; TLB_SIZE ] }
+    }
+
+
+    pub fn flush(&mut self)
+    {
+        self.entries = [ None; TLB_SIZE ];
+    }
+
+
+    // Flush every entry for the page holding the address, translated with the given number of
+    // levels.  Without translation there's no page to narrow the flush down to.
+    pub fn flush_page(&mut self, address: usize, levels: u64)
+    {
+        if levels == 0
+        {
+            self.flush();
+            return;
+        }
+
+        let vpn = virtual_page_number(address, levels);
+
+        for slot in self.entries.iter_mut()
+        {
+            if slot.is_some_and(|entry| entry.vpn >> (VPN_BITS * entry.level)
+                                        == vpn >> (VPN_BITS * entry.level))
+            {
+                *slot = None;
+            }
+        }
+    }
+
+
+    fn lookup(&self, vpn: u64) -> Option<TlbEntry>
+    {
+        self.entries[vpn as usize % TLB_SIZE].filter(|entry| entry.vpn == vpn)
+    }
+
+
+    fn insert(&mut self, entry: TlbEntry)
+    {
+        self.entries[entry.vpn as usize % TLB_SIZE] = Some(entry);
+    }
+}
+
+
+impl Default for Tlb
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+// The virtual page number is taken from the bits of the address that are translated, without the
+// sign extension above them.
+fn virtual_page_number(address: usize, levels: u64) -> u64
+{
+    let va_bits = PAGE_SHIFT + VPN_BITS * levels;
+
+    (address as u64 & ((1 << va_bits) - 1)) >> PAGE_SHIFT
+}
+
+
+
+impl Cpu
+{
+    // Translate a virtual address into a physical one for the given kind of access.
+    pub fn translate(&mut self, address: usize, access: AccessType) -> Result<usize, Exception>
+    {
+        let privilege = self.effective_privilege(access);
+        let levels = self.translation_levels();
+
+        if privilege == PrivilegeLvel::Machine || levels == 0
+        {
+            return Ok(address);
+        }
+
+        // The bits above the virtual address have to be copies of its top bit.
+        let va_bits = PAGE_SHIFT + VPN_BITS * levels;
+        let upper = (address as i64) >> (va_bits - 1);
+
+        if upper != 0 && upper != -1
+        {
+            return Err(access.page_fault(address));
+        }
+
+        let vpn = virtual_page_number(address, levels);
+
+        // A cached entry can only be used for a store once its dirty bit has been set.
+        let entry = match self.tlb.lookup(vpn)
+            {
+                Some(entry) if access != AccessType::Store || entry.pte & PTE_D != 0 =>
+                    {
+                        if !self.page_permitted(entry.pte, privilege, access)
+                        {
+                            return Err(access.page_fault(address));
+                        }
+
+                        entry
+                    },
+
+                _ =>
+                    {
+                        let entry = self.walk_page_table(address, vpn, levels, privilege, access)?;
+
+                        self.tlb.insert(entry);
+                        entry
+                    }
+            };
+
+        Ok(((entry.ppn << PAGE_SHIFT) | (address as u64 & (PAGE_SIZE as u64 - 1))) as usize)
+    }
+
+
+    // A debugger sees memory through the hart's address space, but without the permission checks,
+    // and without touching the TLB or the accessed and dirty bits.
+    pub fn debug_translate(&mut self, address: usize) -> Option<usize>
+    {
+        let levels = self.translation_levels();
+
+        if self.privilege == PrivilegeLvel::Machine || levels == 0
+        {
+            return Some(address);
+        }
+
+        let vpn = virtual_page_number(address, levels);
+        let mut table = (self.csrs[CSR_SATP] & SATP_PPN_MASK) << PAGE_SHIFT;
+
+        for level in (0..levels).rev()
+        {
+            let index = (vpn >> (VPN_BITS * level)) & VPN_MASK;
+            let mut bytes = [ 0; 8 ];
+
+            if !self.read_physical((table + index * 8) as usize, &mut bytes)
+            {
+                return None;
+            }
+
+            let pte = u64::from_le_bytes(bytes);
+            let ppn = (pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK;
+
+            if pte & PTE_V == 0
+            {
+                return None;
+            }
+
+            if pte & (PTE_R | PTE_X) == 0
+            {
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+
+            // The low bits of a superpage's address come from the virtual address.
+            let offset_mask = (1 << (PAGE_SHIFT + VPN_BITS * level)) - 1;
+
+            return Some((((ppn << PAGE_SHIFT) & !offset_mask) | (address as u64 & offset_mask))
+                        as usize);
+        }
+
+        None
+    }
+
+
+    // satp only accepts the modes that are implemented, writes of any other mode are ignored.
+    pub fn write_satp(&mut self, value: u64)
+    {
+        match value >> SATP_MODE_SHIFT
+        {
+            SATP_MODE_BARE | SATP_MODE_SV39 | SATP_MODE_SV48 | SATP_MODE_SV57 =>
+                {
+                    self.csrs[CSR_SATP] = value & ((0b_1111 << SATP_MODE_SHIFT)
+                                                   | SATP_ASID_MASK
+                                                   | SATP_PPN_MASK);
+                    self.tlb.flush();
+                },
+
+            _ =>
+                {
+                }
+        }
+    }
+
+
+    // With MPRV set, loads and stores from machine mode are translated and checked as though they
+    // were made at the privilege level held in MPP.
+    fn effective_privilege(&self, access: AccessType) -> PrivilegeLvel
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+
+        if    access != AccessType::Instruction
+           && self.privilege == PrivilegeLvel::Machine
+           && mstatus & MSTATUS_MPRV != 0
+        {
+            PrivilegeLvel::from_bits(mstatus >> MSTATUS_MPP_SHIFT)
+        }
+        else
+        {
+            self.privilege
+        }
+    }
+
+
+    pub fn translation_levels(&self) -> u64
+    {
+        match self.csrs[CSR_SATP] >> SATP_MODE_SHIFT
+        {
+            SATP_MODE_SV39 => 3,
+            SATP_MODE_SV48 => 4,
+            SATP_MODE_SV57 => 5,
+            _              => 0
+        }
+    }
+
+
+    // Walk the page table from the root down to the leaf mapping the page.  The accessed bit, and
+    // for stores the dirty bit, are set in the leaf once the access is known to be permitted.
+    fn walk_page_table(&mut self,
+                       address: usize,
+                       vpn: u64,
+                       levels: u64,
+                       privilege: PrivilegeLvel,
+                       access: AccessType) -> Result<TlbEntry, Exception>
+    {
+        let mut table = (self.csrs[CSR_SATP] & SATP_PPN_MASK) << PAGE_SHIFT;
+
+        for level in (0..levels).rev()
+        {
+            let index = (vpn >> (VPN_BITS * level)) & VPN_MASK;
+            let pte_address = (table + index * 8) as usize;
+
+            let mut bytes = [ 0; 8 ];
+
+            if !self.read_physical(pte_address, &mut bytes)
+            {
+                return Err(access.access_fault(address));
+            }
+
+            let pte = u64::from_le_bytes(bytes);
+            let ppn = (pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK;
+
+            if    pte & PTE_V == 0
+               || (pte & PTE_R == 0 && pte & PTE_W != 0)
+               || pte & PTE_RESERVED != 0
+            {
+                return Err(access.page_fault(address));
+            }
+
+            // Without any of R, W or X this points to the next level of the table.
+            if pte & (PTE_R | PTE_X) == 0
+            {
+                if pte & (PTE_U | PTE_A | PTE_D) != 0
+                {
+                    return Err(access.page_fault(address));
+                }
+
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+
+            // Superpages have to be aligned to their size.
+            let superpage_mask = (1 << (VPN_BITS * level)) - 1;
+
+            if !self.page_permitted(pte, privilege, access) || ppn & superpage_mask != 0
+            {
+                return Err(access.page_fault(address));
+            }
+
+            let mut updated = pte | PTE_A;
+
+            if access == AccessType::Store
+            {
+                updated |= PTE_D;
+            }
+
+            if updated != pte && !self.write_physical(pte_address, &updated.to_le_bytes())
+            {
+                return Err(access.access_fault(address));
+            }
+
+            return Ok(TlbEntry { vpn, ppn: ppn | (vpn & superpage_mask), pte: updated, level });
+        }
+
+        Err(access.page_fault(address))
+    }
+
+
+    // Check a leaf's permissions.  Supervisor mode can only touch user pages when SUM is set, and
+    // never executes them.  MXR makes executable pages readable.
+    fn page_permitted(&self, pte: u64, privilege: PrivilegeLvel, access: AccessType) -> bool
+    {
+        let mstatus = self.csrs[CSR_MSTATUS];
+
+        let allowed = match access
+            {
+                AccessType::Instruction => pte & PTE_X != 0,
+                AccessType::Load        =>    pte & PTE_R != 0
+                                           || (pte & PTE_X != 0 && mstatus & MSTATUS_MXR != 0),
+                AccessType::Store       => pte & PTE_W != 0
+            };
+
+        let user_page = pte & PTE_U != 0;
+
+        let privileged = match privilege
+            {
+                PrivilegeLvel::User       => user_page,
+                PrivilegeLvel::Supervisor =>    !user_page
+                                             || (   access != AccessType::Instruction
+                                                 && mstatus & MSTATUS_SUM != 0),
+                PrivilegeLvel::Machine    => true
+            };
+
+        allowed && privileged
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::bus::Bus;
+
+
+    const RAM_BASE: u64 = 0x_8000_0000;
+    const RAM_SIZE: u64 = 0x_10_0000;
+
+    // The page tables, a page each at the bottom of RAM.
+    const ROOT:    u64 = RAM_BASE;
+    const LOW_L1:  u64 = RAM_BASE + 0x_1000;
+    const LOW_L0:  u64 = RAM_BASE + 0x_2000;
+    const HIGH_L1: u64 = RAM_BASE + 0x_3000;
+
+    const LEAF: u64 = PTE_V | PTE_R | PTE_W | PTE_X | PTE_A | PTE_D;
+
+
+    fn pte(address: u64, flags: u64) -> u64
+    {
+        ((address >> PAGE_SHIFT) << PTE_PPN_SHIFT) | flags
+    }
+
+
+    fn set_pte(cpu: &mut Cpu, table: u64, index: u64, pte: u64)
+    {
+        assert!(cpu.write_physical((table + index * 8) as usize, &pte.to_le_bytes()));
+    }
+
+
+    fn get_pte(cpu: &mut Cpu, table: u64, index: u64) -> u64
+    {
+        let mut bytes = [ 0; 8 ];
+
+        assert!(cpu.read_physical((table + index * 8) as usize, &mut bytes));
+        u64::from_le_bytes(bytes)
+    }
+
+
+    // A supervisor mode hart under Sv39 with:
+    //
+    //   0x_8000_0000 a gigapage mapped onto itself,
+    //   0x_c000_0000 a gigapage that isn't aligned to its size,
+    //   0x_1000      a read/write page, not yet accessed, at 0x_8000_5000,
+    //   0x_2000      a read only page at 0x_8000_6000,
+    //   0x_3000      a user page at 0x_8000_7000,
+    //   0x_ffff_ffff_ffe0_0000 a megapage at 0x_8020_0000.
+    fn sv39() -> Cpu
+    {
+        let mut bus = Bus::new();
+
+        bus.add_ram(RAM_BASE, RAM_SIZE).unwrap();
+
+        let mut cpu = Cpu::new(bus);
+
+        set_pte(&mut cpu, ROOT, 0, pte(LOW_L1, PTE_V));
+        set_pte(&mut cpu, ROOT, 2, pte(0x_8000_0000, LEAF));
+        set_pte(&mut cpu, ROOT, 3, pte(0x_8000_1000, LEAF));
+        set_pte(&mut cpu, ROOT, 511, pte(HIGH_L1, PTE_V));
+
+        set_pte(&mut cpu, LOW_L1, 0, pte(LOW_L0, PTE_V));
+        set_pte(&mut cpu, LOW_L0, 1, pte(0x_8000_5000, PTE_V | PTE_R | PTE_W));
+        set_pte(&mut cpu, LOW_L0, 2, pte(0x_8000_6000, PTE_V | PTE_R | PTE_A));
+        set_pte(&mut cpu, LOW_L0, 3, pte(0x_8000_7000, LEAF | PTE_U));
+
+        set_pte(&mut cpu, HIGH_L1, 511, pte(0x_8020_0000, LEAF));
+
+        cpu.write_satp((SATP_MODE_SV39 << SATP_MODE_SHIFT) | (ROOT >> PAGE_SHIFT));
+        cpu.privilege = PrivilegeLvel::Supervisor;
+        cpu
+    }
+
+
+    #[test]
+    fn translations()
+    {
+        let mut cpu = sv39();
+
+        let table =
+            [
+                ( 0x_8012_3456,            AccessType::Instruction, Ok(0x_8012_3456) ),
+                ( 0x_1234,                 AccessType::Load,        Ok(0x_8000_5234) ),
+                ( 0x_1ff8,                 AccessType::Store,       Ok(0x_8000_5ff8) ),
+                ( 0x_2010,                 AccessType::Load,        Ok(0x_8000_6010) ),
+                ( 0x_ffff_ffff_ffe0_1008,  AccessType::Store,       Ok(0x_8020_1008) ),
+                ( 0x_ffff_ffff_ffff_fffc,  AccessType::Load,        Ok(0x_803f_fffc) ),
+
+                // Not writable.
+                ( 0x_2010,                 AccessType::Store,
+                  Err(Exception::StorePageFault(0x_2010)) ),
+
+                // Supervisor mode never executes user pages, and without SUM doesn't touch them.
+                ( 0x_3000,                 AccessType::Instruction,
+                  Err(Exception::InstructionPageFault(0x_3000)) ),
+                ( 0x_3000,                 AccessType::Load,
+                  Err(Exception::LoadPageFault(0x_3000)) ),
+
+                // Nothing mapped.
+                ( 0x_4000,                 AccessType::Load,
+                  Err(Exception::LoadPageFault(0x_4000)) ),
+
+                // A misaligned superpage.
+                ( 0x_c000_0000,            AccessType::Load,
+                  Err(Exception::LoadPageFault(0x_c000_0000)) ),
+
+                // Above the 39 bits of virtual address without being sign extended.
+                ( 0x_40_0000_1000,         AccessType::Load,
+                  Err(Exception::LoadPageFault(0x_40_0000_1000)) ),
+                ( 0x_ff7f_ffff_ffe0_0000,  AccessType::Load,
+                  Err(Exception::LoadPageFault(0x_ff7f_ffff_ffe0_0000)) )
+            ];
+
+        for &( address, access, expected ) in &table
+        {
+            assert_eq!(cpu.translate(address, access), expected, "{:#x} {:?}", address, access);
+        }
+    }
+
+
+    // The accessed bit is set by any access, the dirty bit only by a store, which can't be
+    // satisfied from a TLB entry cached before the page was dirty.
+    #[test]
+    fn accessed_and_dirty()
+    {
+        let mut cpu = sv39();
+
+        assert_eq!(cpu.translate(0x_1000, AccessType::Load), Ok(0x_8000_5000));
+        assert_eq!(get_pte(&mut cpu, LOW_L0, 1) & (PTE_A | PTE_D), PTE_A);
+
+        assert_eq!(cpu.translate(0x_1000, AccessType::Store), Ok(0x_8000_5000));
+        assert_eq!(get_pte(&mut cpu, LOW_L0, 1) & (PTE_A | PTE_D), PTE_A | PTE_D);
+    }
+
+
+    #[test]
+    fn privilege()
+    {
+        let mut cpu = sv39();
+
+        cpu.csrs[CSR_MSTATUS] |= MSTATUS_SUM;
+        assert_eq!(cpu.translate(0x_3000, AccessType::Load), Ok(0x_8000_7000));
+
+        cpu.privilege = PrivilegeLvel::User;
+        assert_eq!(cpu.translate(0x_3000, AccessType::Instruction), Ok(0x_8000_7000));
+        assert_eq!(cpu.translate(0x_1000, AccessType::Load),
+                   Err(Exception::LoadPageFault(0x_1000)));
+
+        // Machine mode isn't translated.
+        cpu.privilege = PrivilegeLvel::Machine;
+        assert_eq!(cpu.translate(0x_1000, AccessType::Load), Ok(0x_1000));
+    }
+
+
+    // Flushing any page of a superpage drops the whole of it from the TLB, whichever of its pages
+    // were cached, while flushing an unrelated page leaves it be.
+    #[test]
+    fn flush_superpage()
+    {
+        let mut cpu = sv39();
+
+        assert_eq!(cpu.translate(0x_ffff_ffff_ffe0_1000, AccessType::Load), Ok(0x_8020_1000));
+
+        set_pte(&mut cpu, HIGH_L1, 511, pte(0x_8040_0000, LEAF));
+
+        cpu.tlb.flush_page(0x_1000, 3);
+        assert_eq!(cpu.translate(0x_ffff_ffff_ffe0_1000, AccessType::Load), Ok(0x_8020_1000));
+
+        cpu.tlb.flush_page(0x_ffff_ffff_fff0_0000, 3);
+        assert_eq!(cpu.translate(0x_ffff_ffff_ffe0_1000, AccessType::Load), Ok(0x_8040_1000));
+    }
+
+
+    #[test]
+    fn debug_translation()
+    {
+        let mut cpu = sv39();
+
+        assert_eq!(cpu.debug_translate(0x_3008), Some(0x_8000_7008));
+        assert_eq!(cpu.debug_translate(0x_ffff_ffff_ffe0_1008), Some(0x_8020_1008));
+        assert_eq!(cpu.debug_translate(0x_4000), None);
+
+        // Without touching the accessed bit.
+        assert_eq!(cpu.debug_translate(0x_1000), Some(0x_8000_5000));
+        assert_eq!(get_pte(&mut cpu, LOW_L0, 1) & PTE_A, 0);
+    }
+}
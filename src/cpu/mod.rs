@@ -1,9 +1,26 @@
 
 mod opcodes;
 mod instruction;
+mod compressed;
+mod csr;
+mod float;
+mod softfloat;
+mod trap;
+mod mmu;
+mod disassembler;
+mod commit_log;
+#[allow(clippy::module_inception)]
 mod cpu;
 
 
 pub use opcodes::*;
 pub use instruction::*;
+pub use compressed::*;
+pub use csr::*;
+pub use float::*;
+pub use softfloat::*;
+pub use trap::*;
+pub use mmu::*;
+pub use disassembler::*;
+pub use commit_log::*;
 pub use cpu::*;
@@ -34,6 +34,8 @@ pub const OP_MO1___:          u32 = 0b_0010011;
     pub const F3_SR___:       u32 = 0b_101;
         pub const F7_SRLI:    u32 = 0b_0000000;
         pub const F7_SRAI:    u32 = 0b_0100000;
+        // On RV64 the low bit of func7 holds bit 5 of the shift amount.
+        pub const SHAMT_F7_MASK: u32 = 0b_1111110;
 pub const OP_MO2___:          u32 = 0b_0110011;
     pub const F3_AS___:       u32 = 0b_000;
         pub const F7_ADD:     u32 = 0b_0000000;
@@ -63,6 +65,9 @@ pub const OP_SYSTEM___:       u32 = 0b_1110011;
 
 // "Zifencei" Instruction-Fetch Fence, Version 2.0
 
+// OP_MISC_MEM___
+    pub const F3_FENCE_I:     u32 = 0b_001;
+
 
 // RV32E Base Integer Instruction Set, Version 1.9
 
@@ -238,7 +243,9 @@ pub const RS3_MASK: u32 = 0b_0000011;
 
 // RV32F Standard Extension
 pub const OP_FLW:                     u32   = 0b_0000111;
+    pub const F3_FLW:                 u32   = 0b_010;
 pub const OP_FSW:                     u32   = 0b_0100111;
+    pub const F3_FSW:                 u32   = 0b_010;
 pub const OP_FMADD_S___:              u32   = 0b_1000011;
     pub const F7_FMADD_S:             u32   = 0b_00;
 pub const OP_FMSUB_S___:              u32   = 0b_1000111;
@@ -262,8 +269,8 @@ pub const OP_RV_F___:                 u32   = 0b_1010011;
             pub const F3_FMIN_S:      u32   = 0b_000;
             pub const F3_FMAX_S:      u32   = 0b_001;
         pub const F7_FCVT_W___:       u32   = 0b_1100000;
-            pub const F3_FCVT_W_S:    u32   = 0b_000;
-            pub const F3_FCVT_WU_S:   u32   = 0b_001;
+            pub const RS2_FCVT_W_S:   usize = 0b_00000;
+            pub const RS2_FCVT_WU_S:  usize = 0b_00001;
     pub const F3_32_FMV___:           u32   = 0b_000;
         pub const F7_FMV_X_W:         u32   = 0b_1110000;
             pub const RS2_FMV_X_W:    usize = 0b_00000;
@@ -277,9 +284,9 @@ pub const OP_RV_F___:                 u32   = 0b_1010011;
     pub const F3_32_FC___:            u32   = 0b_001;
         pub const F7_FCLASS_S:        u32   = 0b_1110000;
             pub const RS2_FCLASS_S:   usize = 0b_00000;
-        pub const F7_FCVT_S___:       u32   = 0b_1101000;  // rs2 = 00000
-            pub const F3_FCVT_S_W:    u32   = 0b_000;
-            pub const F3_FCVT_S_WU:   u32   = 0b_001;
+        pub const F7_FCVT_S___:       u32   = 0b_1101000;
+            pub const RS2_FCVT_S_W:   usize = 0b_00000;
+            pub const RS2_FCVT_S_WU:  usize = 0b_00001;
 
 // RV64F Standard Extension (in addition to RV32F)
 // OP_RV_F___
@@ -382,7 +389,7 @@ RV64D Standard Extension (in addition to RV32D)
     // F7_FCVT1___
         pub const RS2_FCVT_L_D:       usize = 0b_00010;
         pub const RS2_FCVT_LU_D:      usize = 0b_00011;
-    pub const F3_FMV_X_D___:          u32   = 0b_010;
+    pub const F3_FMV_X_D___:          u32   = 0b_000;
         pub const F7_FMV_X_D___:      u32   = 0b_1110001;
             pub const RS2_FMV_X_D:    usize = 0b_00000;
     // F7_FCVT2___
@@ -403,6 +410,46 @@ RV64D Standard Extension (in addition to RV32D)
 
 // "C" Standard Extension for Compressed Instructions, Version 2.0
 
+// Compressed instructions are identified by the quadrant in their low two bits, anything in
+// quadrant 3 is a 32-bit instruction.  Within a quadrant the funct3 is in bits 15:13.
+pub const C_QUADRANT_MASK:    u16 = 0b_11;
+pub const C_Q0___:            u16 = 0b_00;
+    pub const C_F3_ADDI4SPN:  u16 = 0b_000;
+    pub const C_F3_FLD:       u16 = 0b_001;
+    pub const C_F3_LW:        u16 = 0b_010;
+    pub const C_F3_LD:        u16 = 0b_011;
+    pub const C_F3_FSD:       u16 = 0b_101;
+    pub const C_F3_SW:        u16 = 0b_110;
+    pub const C_F3_SD:        u16 = 0b_111;
+pub const C_Q1___:            u16 = 0b_01;
+    pub const C_F3_ADDI:      u16 = 0b_000;
+    pub const C_F3_ADDIW:     u16 = 0b_001;
+    pub const C_F3_LI:        u16 = 0b_010;
+    pub const C_F3_LUI___:    u16 = 0b_011;
+    pub const C_F3_ALU___:    u16 = 0b_100;
+        pub const C_F2_SRLI:  u16 = 0b_00;
+        pub const C_F2_SRAI:  u16 = 0b_01;
+        pub const C_F2_ANDI:  u16 = 0b_10;
+        pub const C_F2_REG___: u16 = 0b_11;
+            pub const C_SUB:  u16 = 0b_0_00;
+            pub const C_XOR:  u16 = 0b_0_01;
+            pub const C_OR:   u16 = 0b_0_10;
+            pub const C_AND:  u16 = 0b_0_11;
+            pub const C_SUBW: u16 = 0b_1_00;
+            pub const C_ADDW: u16 = 0b_1_01;
+    pub const C_F3_J:         u16 = 0b_101;
+    pub const C_F3_BEQZ:      u16 = 0b_110;
+    pub const C_F3_BNEZ:      u16 = 0b_111;
+pub const C_Q2___:            u16 = 0b_10;
+    pub const C_F3_SLLI:      u16 = 0b_000;
+    pub const C_F3_FLDSP:     u16 = 0b_001;
+    pub const C_F3_LWSP:      u16 = 0b_010;
+    pub const C_F3_LDSP:      u16 = 0b_011;
+    pub const C_F3_JR_MV___:  u16 = 0b_100;
+    pub const C_F3_FSDSP:     u16 = 0b_101;
+    pub const C_F3_SWSP:      u16 = 0b_110;
+    pub const C_F3_SDSP:      u16 = 0b_111;
+
 
 // "B" Standard Extension for Bit Manipulation, Version 0.0
 
@@ -434,8 +481,16 @@ RV64D Standard Extension (in addition to RV32D)
 
 // Machine-Level ISA, Version 1.12
 
+// OP_SYSTEM___ F3_SYS_PRIV___
+        pub const F12_MRET:   u32 = 0b_001100000010;
+        pub const F12_WFI:    u32 = 0b_000100000101;
+
 
 // Supervisor-Level ISA, Version 1.12
 
+// OP_SYSTEM___ F3_SYS_PRIV___
+        pub const F12_SRET:   u32 = 0b_000100000010;
+        pub const F7_SFENCE_VMA: u32 = 0b_0001001;
+
 
 // Hypervisor Extension, Version 0.6.1
@@ -0,0 +1,1006 @@
+
+use super::float::*;
+
+
+// A software implementation of IEEE-754 binary floating-point arithmetic.  The host's floating
+// point unit only rounds to nearest-even, so every floating-point instruction is evaluated here
+// instead, where the rounding mode can be chosen per operation and the exception flags are
+// computed the same way RISC-V hardware computes them, (including detecting tininess after
+// rounding.)
+//
+// Values are passed around as raw bit patterns in a u64, the Format describes how to interpret
+// them.  Significands are carried in u128s during calculations, with enough headroom for the full
+// product of two doubles.
+
+
+pub struct Format
+{
+    pub exponent_bits: u32,
+    pub fraction_bits: u32
+}
+
+
+pub const F32: Format = Format { exponent_bits: 8,  fraction_bits: 23 };
+pub const F64: Format = Format { exponent_bits: 11, fraction_bits: 52 };
+
+
+impl Format
+{
+    fn bias(&self) -> i32
+    {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+
+    fn max_exponent(&self) -> u64
+    {
+        (1 << self.exponent_bits) - 1
+    }
+
+
+    fn fraction_mask(&self) -> u64
+    {
+        (1 << self.fraction_bits) - 1
+    }
+
+
+    fn quiet_bit(&self) -> u64
+    {
+        1 << (self.fraction_bits - 1)
+    }
+
+
+    pub fn sign_bit(&self) -> u64
+    {
+        1 << (self.exponent_bits + self.fraction_bits)
+    }
+
+
+    pub fn canonical_nan(&self) -> u64
+    {
+        (self.max_exponent() << self.fraction_bits) | self.quiet_bit()
+    }
+
+
+    fn zero(&self, sign: bool) -> u64
+    {
+        if sign { self.sign_bit() } else { 0 }
+    }
+
+
+    fn infinity(&self, sign: bool) -> u64
+    {
+        self.zero(sign) | (self.max_exponent() << self.fraction_bits)
+    }
+
+
+    fn largest(&self, sign: bool) -> u64
+    {
+        self.infinity(sign) - 1
+    }
+
+
+    fn is_sign_negative(&self, value: u64) -> bool
+    {
+        (value & self.sign_bit()) != 0
+    }
+
+
+    pub fn is_nan(&self, value: u64) -> bool
+    {
+        let magnitude = value & !self.sign_bit();
+        magnitude > (self.max_exponent() << self.fraction_bits)
+    }
+
+
+    pub fn is_signaling_nan(&self, value: u64) -> bool
+    {
+        self.is_nan(value) && (value & self.quiet_bit()) == 0
+    }
+
+
+    fn unpack(&self, value: u64) -> Unpacked
+    {
+        let sign = self.is_sign_negative(value);
+        let exponent = (value >> self.fraction_bits) & self.max_exponent();
+        let fraction = value & self.fraction_mask();
+
+        if exponent == self.max_exponent()
+        {
+            if fraction == 0 { Unpacked::Infinity(sign) } else { Unpacked::NaN }
+        }
+        else if exponent == 0
+        {
+            if fraction == 0
+            {
+                Unpacked::Zero(sign)
+            }
+            else
+            {
+                let exponent = 1 - self.bias() - self.fraction_bits as i32;
+                Unpacked::Finite(sign, exponent, fraction as u128)
+            }
+        }
+        else
+        {
+            Unpacked::Finite(sign,
+                             exponent as i32 - self.bias() - self.fraction_bits as i32,
+                             (fraction | (1 << self.fraction_bits)) as u128)
+        }
+    }
+
+
+    // Key for ordering non-NaN values, both zeros compare equal.
+    fn order_key(&self, value: u64) -> i64
+    {
+        let magnitude = (value & !self.sign_bit()) as i64;
+        if self.is_sign_negative(value) { -magnitude } else { magnitude }
+    }
+}
+
+
+// A finite value is sign, exponent and significand with the value being significand * 2^exponent.
+enum Unpacked
+{
+    NaN,
+    Infinity(bool),
+    Zero(bool),
+    Finite(bool, i32, u128)
+}
+
+
+// The position finite significands are normalized to before addition, leaving room for a carry.
+const NORMALIZED_MSB: u32 = 125;
+
+
+fn most_significant_bit(value: u128) -> i32
+{
+    127 - value.leading_zeros() as i32
+}
+
+
+fn normalize(exponent: i32, significand: u128) -> ( i32, u128 )
+{
+    let shift = NORMALIZED_MSB as i32 - most_significant_bit(significand);
+    ( exponent - shift, significand << shift )
+}
+
+
+// Shift right, or-ing any bits shifted out into the lowest bit so that they are still seen when
+// rounding.
+fn shift_right_jam(value: u128, shift: i32) -> u128
+{
+    if shift <= 0
+    {
+        value
+    }
+    else if shift >= 128
+    {
+        (value != 0) as u128
+    }
+    else
+    {
+        (value >> shift) | ((value & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+
+fn integer_sqrt(value: u128) -> u128
+{
+    let mut remainder = value;
+    let mut root = 0;
+    let mut bit = 1u128 << 126;
+
+    while bit > value
+    {
+        bit >>= 2;
+    }
+
+    while bit != 0
+    {
+        if remainder >= root + bit
+        {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        }
+        else
+        {
+            root >>= 1;
+        }
+
+        bit >>= 2;
+    }
+
+    root
+}
+
+
+pub struct SoftFloat
+{
+    pub rounding_mode: u32,
+    pub flags: u32
+}
+
+
+impl SoftFloat
+{
+    pub fn new(rounding_mode: u32) -> Self
+    {
+        Self { rounding_mode, flags: 0 }
+    }
+
+
+    // Shift a significand right, rounding the result according to the current rounding mode.
+    // Returns the rounded value and whether or not it was inexact.
+    fn shift_right_round(&self, sign: bool, significand: u128, shift: i32) -> ( u128, bool )
+    {
+        if shift <= 0
+        {
+            return ( significand << -shift, false );
+        }
+
+        let ( kept, remainder, half ) = if shift >= 128
+            {
+                // Everything is shifted out, and what's left is always below the halfway point.
+                ( 0, (significand != 0) as u128, 2 )
+            }
+            else
+            {
+                ( significand >> shift, significand & ((1 << shift) - 1), 1 << (shift - 1) )
+            };
+
+        let increment = match self.rounding_mode
+            {
+                RM_RTZ => false,
+                RM_RDN => sign && remainder != 0,
+                RM_RUP => !sign && remainder != 0,
+                RM_RMM => remainder >= half,
+                _      => remainder > half || (remainder == half && (kept & 1) != 0)
+            };
+
+        ( kept + increment as u128, remainder != 0 )
+    }
+
+
+    fn overflow(&mut self, format: &Format, sign: bool) -> u64
+    {
+        self.flags |= FLAG_OF | FLAG_NX;
+
+        let to_infinity = match self.rounding_mode
+            {
+                RM_RTZ => false,
+                RM_RDN => sign,
+                RM_RUP => !sign,
+                _      => true
+            };
+
+        if to_infinity { format.infinity(sign) } else { format.largest(sign) }
+    }
+
+
+    // Round an arbitrary precision value, significand * 2^exponent, to the given format and
+    // encode it.
+    fn round_pack(&mut self, format: &Format, sign: bool, exponent: i32, significand: u128) -> u64
+    {
+        if significand == 0
+        {
+            return format.zero(sign);
+        }
+
+        let precision = format.fraction_bits as i32 + 1;
+        let min_exponent = 1 - format.bias();
+
+        // The exponent of the value's leading bit.
+        let leading_exponent = exponent + most_significant_bit(significand);
+
+        // Tininess is detected after rounding, that is a value just below the smallest normal that
+        // would round up to it given an unbounded exponent range is not considered tiny.
+        let tiny = if leading_exponent < min_exponent - 1
+            {
+                true
+            }
+            else if leading_exponent == min_exponent - 1
+            {
+                let shift = leading_exponent - (precision - 1) - exponent;
+                let ( rounded, _ ) = self.shift_right_round(sign, significand, shift);
+
+                (rounded >> precision) == 0
+            }
+            else
+            {
+                false
+            };
+
+        // Subnormal values are all quantized to the same, smallest exponent.
+        let quantum = leading_exponent.max(min_exponent) - (precision - 1);
+        let ( rounded, inexact ) = self.shift_right_round(sign, significand, quantum - exponent);
+
+        if inexact
+        {
+            self.flags |= FLAG_NX;
+
+            if tiny
+            {
+                self.flags |= FLAG_UF;
+            }
+        }
+
+        // Normal values are encoded with the exponent less one, so that the implicit bit carries
+        // into the exponent field.  That also takes care of rounding up into the next binade, and
+        // of subnormals rounding up to the smallest normal.
+        let encoded = if leading_exponent < min_exponent
+            {
+                rounded as u64
+            }
+            else
+            {
+                (((leading_exponent + format.bias() - 1) as u64) << format.fraction_bits)
+                    + rounded as u64
+            };
+
+        if (encoded >> format.fraction_bits) >= format.max_exponent()
+        {
+            self.overflow(format, sign)
+        }
+        else
+        {
+            format.zero(sign) | encoded
+        }
+    }
+
+
+    fn invalid(&mut self, format: &Format) -> u64
+    {
+        self.flags |= FLAG_NV;
+        format.canonical_nan()
+    }
+
+
+    // Any NaN input produces the canonical NaN, signaling NaNs also raise invalid.
+    fn propagate_nan(&mut self, format: &Format, inputs: &[ u64 ]) -> u64
+    {
+        if inputs.iter().any(|&value| format.is_signaling_nan(value))
+        {
+            self.flags |= FLAG_NV;
+        }
+
+        format.canonical_nan()
+    }
+
+
+    fn add_finite(&mut self,
+                  format: &Format,
+                  ( sign_a, exponent_a, significand_a ): ( bool, i32, u128 ),
+                  ( sign_b, exponent_b, significand_b ): ( bool, i32, u128 )) -> u64
+    {
+        let ( exponent_a, significand_a ) = normalize(exponent_a, significand_a);
+        let ( exponent_b, significand_b ) = normalize(exponent_b, significand_b);
+
+        let ( ( sign_a, exponent, significand_a ), ( sign_b, significand_b ) ) =
+            if exponent_a >= exponent_b
+            {
+                ( ( sign_a, exponent_a, significand_a ),
+                  ( sign_b, shift_right_jam(significand_b, exponent_a - exponent_b) ) )
+            }
+            else
+            {
+                ( ( sign_b, exponent_b, significand_b ),
+                  ( sign_a, shift_right_jam(significand_a, exponent_b - exponent_a) ) )
+            };
+
+        if sign_a == sign_b
+        {
+            self.round_pack(format, sign_a, exponent, significand_a + significand_b)
+        }
+        else if significand_a > significand_b
+        {
+            self.round_pack(format, sign_a, exponent, significand_a - significand_b)
+        }
+        else if significand_b > significand_a
+        {
+            self.round_pack(format, sign_b, exponent, significand_b - significand_a)
+        }
+        else
+        {
+            format.zero(self.rounding_mode == RM_RDN)
+        }
+    }
+
+
+    pub fn add(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        match ( format.unpack(a), format.unpack(b) )
+        {
+            ( Unpacked::NaN, _ ) | ( _, Unpacked::NaN ) => self.propagate_nan(format, &[ a, b ]),
+
+            ( Unpacked::Infinity(sign_a), Unpacked::Infinity(sign_b) ) if sign_a != sign_b =>
+                self.invalid(format),
+
+            ( Unpacked::Infinity(sign), _ ) | ( _, Unpacked::Infinity(sign) ) =>
+                format.infinity(sign),
+
+            ( Unpacked::Zero(sign_a), Unpacked::Zero(sign_b) ) =>
+                if sign_a == sign_b
+                {
+                    format.zero(sign_a)
+                }
+                else
+                {
+                    format.zero(self.rounding_mode == RM_RDN)
+                },
+
+            ( Unpacked::Zero(_), _ ) => b,
+            ( _, Unpacked::Zero(_) ) => a,
+
+            ( Unpacked::Finite(sign_a, exponent_a, significand_a),
+              Unpacked::Finite(sign_b, exponent_b, significand_b) ) =>
+                self.add_finite(format,
+                                ( sign_a, exponent_a, significand_a ),
+                                ( sign_b, exponent_b, significand_b ))
+        }
+    }
+
+
+    pub fn sub(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        self.add(format, a, b ^ format.sign_bit())
+    }
+
+
+    pub fn mul(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        match ( format.unpack(a), format.unpack(b) )
+        {
+            ( Unpacked::NaN, _ ) | ( _, Unpacked::NaN ) => self.propagate_nan(format, &[ a, b ]),
+
+            ( Unpacked::Infinity(_), Unpacked::Zero(_) ) |
+            ( Unpacked::Zero(_), Unpacked::Infinity(_) ) => self.invalid(format),
+
+            ( Unpacked::Infinity(sign_a), Unpacked::Infinity(sign_b) ) |
+            ( Unpacked::Infinity(sign_a), Unpacked::Finite(sign_b, _, _) ) |
+            ( Unpacked::Finite(sign_a, _, _), Unpacked::Infinity(sign_b) ) =>
+                format.infinity(sign_a != sign_b),
+
+            ( Unpacked::Zero(sign_a), Unpacked::Zero(sign_b) ) |
+            ( Unpacked::Zero(sign_a), Unpacked::Finite(sign_b, _, _) ) |
+            ( Unpacked::Finite(sign_a, _, _), Unpacked::Zero(sign_b) ) =>
+                format.zero(sign_a != sign_b),
+
+            ( Unpacked::Finite(sign_a, exponent_a, significand_a),
+              Unpacked::Finite(sign_b, exponent_b, significand_b) ) =>
+                self.round_pack(format,
+                                sign_a != sign_b,
+                                exponent_a + exponent_b,
+                                significand_a * significand_b)
+        }
+    }
+
+
+    // Computes (a * b) + c with a single rounding.
+    pub fn mul_add(&mut self, format: &Format, a: u64, b: u64, c: u64) -> u64
+    {
+        let product_sign = format.is_sign_negative(a) != format.is_sign_negative(b);
+
+        match ( format.unpack(a), format.unpack(b), format.unpack(c) )
+        {
+            // Infinity times zero is invalid even if the addend is a quiet NaN.
+            ( Unpacked::Infinity(_), Unpacked::Zero(_), _ ) |
+            ( Unpacked::Zero(_), Unpacked::Infinity(_), _ ) => self.invalid(format),
+
+            ( Unpacked::NaN, _, _ ) | ( _, Unpacked::NaN, _ ) | ( _, _, Unpacked::NaN ) =>
+                self.propagate_nan(format, &[ a, b, c ]),
+
+            ( Unpacked::Infinity(_), _, Unpacked::Infinity(sign_c) ) |
+            ( _, Unpacked::Infinity(_), Unpacked::Infinity(sign_c) ) =>
+                if sign_c != product_sign
+                {
+                    self.invalid(format)
+                }
+                else
+                {
+                    format.infinity(sign_c)
+                },
+
+            ( Unpacked::Infinity(_), _, _ ) | ( _, Unpacked::Infinity(_), _ ) =>
+                format.infinity(product_sign),
+
+            ( _, _, Unpacked::Infinity(sign_c) ) => format.infinity(sign_c),
+
+            ( Unpacked::Zero(_), _, Unpacked::Zero(sign_c) ) |
+            ( _, Unpacked::Zero(_), Unpacked::Zero(sign_c) ) =>
+                if sign_c == product_sign
+                {
+                    format.zero(sign_c)
+                }
+                else
+                {
+                    format.zero(self.rounding_mode == RM_RDN)
+                },
+
+            ( Unpacked::Zero(_), _, _ ) | ( _, Unpacked::Zero(_), _ ) => c,
+
+            ( Unpacked::Finite(_, exponent_a, significand_a),
+              Unpacked::Finite(_, exponent_b, significand_b),
+              Unpacked::Zero(_) ) =>
+                self.round_pack(format,
+                                product_sign,
+                                exponent_a + exponent_b,
+                                significand_a * significand_b),
+
+            ( Unpacked::Finite(_, exponent_a, significand_a),
+              Unpacked::Finite(_, exponent_b, significand_b),
+              Unpacked::Finite(sign_c, exponent_c, significand_c) ) =>
+                {
+                    let product = ( product_sign,
+                                    exponent_a + exponent_b,
+                                    significand_a * significand_b );
+
+                    self.add_finite(format, product, ( sign_c, exponent_c, significand_c ))
+                }
+        }
+    }
+
+
+    pub fn div(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        match ( format.unpack(a), format.unpack(b) )
+        {
+            ( Unpacked::NaN, _ ) | ( _, Unpacked::NaN ) => self.propagate_nan(format, &[ a, b ]),
+
+            ( Unpacked::Infinity(_), Unpacked::Infinity(_) ) |
+            ( Unpacked::Zero(_), Unpacked::Zero(_) ) => self.invalid(format),
+
+            ( Unpacked::Infinity(sign_a), Unpacked::Zero(sign_b) ) |
+            ( Unpacked::Infinity(sign_a), Unpacked::Finite(sign_b, _, _) ) =>
+                format.infinity(sign_a != sign_b),
+
+            ( Unpacked::Finite(sign_a, _, _), Unpacked::Zero(sign_b) ) =>
+                {
+                    self.flags |= FLAG_DZ;
+                    format.infinity(sign_a != sign_b)
+                },
+
+            ( Unpacked::Zero(sign_a), Unpacked::Infinity(sign_b) ) |
+            ( Unpacked::Zero(sign_a), Unpacked::Finite(sign_b, _, _) ) |
+            ( Unpacked::Finite(sign_a, _, _), Unpacked::Infinity(sign_b) ) =>
+                format.zero(sign_a != sign_b),
+
+            ( Unpacked::Finite(sign_a, exponent_a, significand_a),
+              Unpacked::Finite(sign_b, exponent_b, significand_b) ) =>
+                {
+                    // With both significands at the same width a 64-bit shift leaves at least 64
+                    // bits of quotient, the remainder becomes a sticky bit.
+                    let shift_a = 52 - most_significant_bit(significand_a);
+                    let shift_b = 52 - most_significant_bit(significand_b);
+
+                    let dividend = significand_a << (shift_a + 64);
+                    let divisor = significand_b << shift_b;
+
+                    let quotient = dividend / divisor;
+                    let sticky = !dividend.is_multiple_of(divisor) as u128;
+
+                    self.round_pack(format,
+                                    sign_a != sign_b,
+                                    (exponent_a - shift_a) - (exponent_b - shift_b) - 64 - 1,
+                                    (quotient << 1) | sticky)
+                }
+        }
+    }
+
+
+    pub fn sqrt(&mut self, format: &Format, a: u64) -> u64
+    {
+        match format.unpack(a)
+        {
+            Unpacked::NaN => self.propagate_nan(format, &[ a ]),
+            Unpacked::Zero(_) => a,
+            Unpacked::Infinity(false) => a,
+            Unpacked::Infinity(true) | Unpacked::Finite(true, _, _) => self.invalid(format),
+
+            Unpacked::Finite(false, exponent, significand) =>
+                {
+                    // Scale the significand up to nearly fill the u128, keeping the exponent even
+                    // so that it can be halved.
+                    let mut shift = 126 - most_significant_bit(significand);
+
+                    if (exponent - shift) % 2 != 0
+                    {
+                        shift -= 1;
+                    }
+
+                    let scaled = significand << shift;
+                    let root = integer_sqrt(scaled);
+                    let sticky = (root * root != scaled) as u128;
+
+                    self.round_pack(format, false, (exponent - shift) / 2 - 1, (root << 1) | sticky)
+                }
+        }
+    }
+
+
+    pub fn convert(&mut self, from: &Format, to: &Format, a: u64) -> u64
+    {
+        match from.unpack(a)
+        {
+            Unpacked::NaN =>
+                {
+                    self.propagate_nan(from, &[ a ]);
+                    to.canonical_nan()
+                },
+            Unpacked::Infinity(sign) => to.infinity(sign),
+            Unpacked::Zero(sign) => to.zero(sign),
+            Unpacked::Finite(sign, exponent, significand) =>
+                self.round_pack(to, sign, exponent, significand)
+        }
+    }
+
+
+    pub fn i64_to_float(&mut self, format: &Format, value: i64) -> u64
+    {
+        self.round_pack(format, value < 0, 0, value.unsigned_abs() as u128)
+    }
+
+
+    pub fn u64_to_float(&mut self, format: &Format, value: u64) -> u64
+    {
+        self.round_pack(format, false, 0, value as u128)
+    }
+
+
+    // Convert to an integer of the given width, saturating out of range values.  NaNs convert to
+    // the largest value.  The result is returned as a two's complement u64.
+    fn float_to_integer(&mut self, format: &Format, a: u64, signed: bool, bits: u32) -> u64
+    {
+        let ( min, max ) = if signed
+            {
+                ( -(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1 )
+            }
+            else
+            {
+                ( 0, (1i128 << bits) - 1 )
+            };
+
+        let ( sign, magnitude, inexact ) = match format.unpack(a)
+            {
+                Unpacked::NaN => ( false, u128::MAX, false ),
+                Unpacked::Infinity(sign) => ( sign, u128::MAX, false ),
+                Unpacked::Zero(_) => ( false, 0, false ),
+
+                // Anything this large is out of range, no matter the width.
+                Unpacked::Finite(sign, exponent, _) if exponent > 64 => ( sign, u128::MAX, false ),
+
+                Unpacked::Finite(sign, exponent, significand) =>
+                    {
+                        let ( magnitude, inexact ) = self.shift_right_round(sign,
+                                                                            significand,
+                                                                            -exponent);
+                        ( sign, magnitude, inexact )
+                    }
+            };
+
+        let value = if magnitude > (1u128 << 64) { None }
+                    else if sign { Some(-(magnitude as i128)) }
+                    else { Some(magnitude as i128) };
+
+        match value
+        {
+            Some(value) if value >= min && value <= max =>
+                {
+                    if inexact
+                    {
+                        self.flags |= FLAG_NX;
+                    }
+
+                    value as u64
+                },
+
+            _ =>
+                {
+                    self.flags |= FLAG_NV;
+
+                    if sign && !format.is_nan(a) { min as u64 } else { max as u64 }
+                }
+        }
+    }
+
+
+    pub fn float_to_i32(&mut self, format: &Format, a: u64) -> i32
+    {
+        self.float_to_integer(format, a, true, 32) as i32
+    }
+
+
+    pub fn float_to_u32(&mut self, format: &Format, a: u64) -> u32
+    {
+        self.float_to_integer(format, a, false, 32) as u32
+    }
+
+
+    pub fn float_to_i64(&mut self, format: &Format, a: u64) -> i64
+    {
+        self.float_to_integer(format, a, true, 64) as i64
+    }
+
+
+    pub fn float_to_u64(&mut self, format: &Format, a: u64) -> u64
+    {
+        self.float_to_integer(format, a, false, 64)
+    }
+
+
+    // feq only signals for signaling NaNs, flt and fle are signaling comparisons.
+    pub fn eq(&mut self, format: &Format, a: u64, b: u64) -> bool
+    {
+        if format.is_nan(a) || format.is_nan(b)
+        {
+            self.propagate_nan(format, &[ a, b ]);
+            false
+        }
+        else
+        {
+            format.order_key(a) == format.order_key(b)
+        }
+    }
+
+
+    pub fn lt(&mut self, format: &Format, a: u64, b: u64) -> bool
+    {
+        if format.is_nan(a) || format.is_nan(b)
+        {
+            self.flags |= FLAG_NV;
+            false
+        }
+        else
+        {
+            format.order_key(a) < format.order_key(b)
+        }
+    }
+
+
+    pub fn le(&mut self, format: &Format, a: u64, b: u64) -> bool
+    {
+        if format.is_nan(a) || format.is_nan(b)
+        {
+            self.flags |= FLAG_NV;
+            false
+        }
+        else
+        {
+            format.order_key(a) <= format.order_key(b)
+        }
+    }
+
+
+    // fmin/fmax return the non-NaN operand if only one is a NaN, and treat -0.0 as less than
+    // +0.0.
+    fn min_max(&mut self, format: &Format, a: u64, b: u64, max: bool) -> u64
+    {
+        match ( format.is_nan(a), format.is_nan(b) )
+        {
+            ( true, true ) => self.propagate_nan(format, &[ a, b ]),
+            ( true, false ) => { self.propagate_nan(format, &[ a ]); b },
+            ( false, true ) => { self.propagate_nan(format, &[ b ]); a },
+
+            _ =>
+                {
+                    let ( key_a, key_b ) = ( format.order_key(a), format.order_key(b) );
+
+                    let a_is_less = if key_a == key_b
+                        {
+                            format.is_sign_negative(a)
+                        }
+                        else
+                        {
+                            key_a < key_b
+                        };
+
+                    if a_is_less != max { a } else { b }
+                }
+        }
+    }
+
+
+    pub fn min(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        self.min_max(format, a, b, false)
+    }
+
+
+    pub fn max(&mut self, format: &Format, a: u64, b: u64) -> u64
+    {
+        self.min_max(format, a, b, true)
+    }
+}
+
+
+pub fn classify(format: &Format, a: u64) -> u64
+{
+    match format.unpack(a)
+    {
+        Unpacked::NaN if format.is_signaling_nan(a) => FCLASS_SIGNALING_NAN,
+        Unpacked::NaN => FCLASS_QUIET_NAN,
+        Unpacked::Infinity(true) => FCLASS_NEG_INFINITY,
+        Unpacked::Infinity(false) => FCLASS_POS_INFINITY,
+        Unpacked::Zero(true) => FCLASS_NEG_ZERO,
+        Unpacked::Zero(false) => FCLASS_POS_ZERO,
+
+        Unpacked::Finite(sign, _, significand) =>
+            {
+                let normal = significand > format.fraction_mask() as u128;
+
+                match ( sign, normal )
+                {
+                    ( true,  true  ) => FCLASS_NEG_NORMAL,
+                    ( true,  false ) => FCLASS_NEG_SUBNORMAL,
+                    ( false, true  ) => FCLASS_POS_NORMAL,
+                    ( false, false ) => FCLASS_POS_SUBNORMAL
+                }
+            }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    const F32_ONE:        u64 = 0x_3f800000;
+    const F32_TWO:        u64 = 0x_40000000;
+    const F32_THREE:      u64 = 0x_40400000;
+    const F32_MINUS_ONE:  u64 = 0x_bf800000;
+    const F32_MAX:        u64 = 0x_7f7fffff;
+    const F32_MIN_NORMAL: u64 = 0x_00800000;
+    const F32_INFINITY:   u64 = 0x_7f800000;
+    const F32_QUIET_NAN:  u64 = 0x_7fc01234;
+    const F32_SIGNAL_NAN: u64 = 0x_7f801234;
+
+    const F64_QUIET_NAN:  u64 = 0x_7ff80000_00000000;
+
+
+    type Operation = fn(&mut SoftFloat) -> u64;
+
+
+    fn evaluate(rounding_mode: u32, operation: Operation) -> ( u64, u32 )
+    {
+        let mut float = SoftFloat::new(rounding_mode);
+        let result = operation(&mut float);
+
+        ( result, float.flags )
+    }
+
+
+    // 2^24 + 1 lies halfway between two singles, and 2^24 + 3 between an odd and an even one.
+    #[test]
+    fn rounding_modes()
+    {
+        let table =
+            [
+                ( RM_RNE,  16777217, 0x_4b800000 ),
+                ( RM_RTZ,  16777217, 0x_4b800000 ),
+                ( RM_RDN,  16777217, 0x_4b800000 ),
+                ( RM_RUP,  16777217, 0x_4b800001 ),
+                ( RM_RMM,  16777217, 0x_4b800001 ),
+                ( RM_RNE, -16777217, 0x_cb800000 ),
+                ( RM_RTZ, -16777217, 0x_cb800000 ),
+                ( RM_RDN, -16777217, 0x_cb800001 ),
+                ( RM_RUP, -16777217, 0x_cb800000 ),
+                ( RM_RMM, -16777217, 0x_cb800001 ),
+                ( RM_RNE,  16777219, 0x_4b800002 ),
+                ( RM_RTZ,  16777219, 0x_4b800001 )
+            ];
+
+        for &( rounding_mode, value, expected ) in &table
+        {
+            let mut float = SoftFloat::new(rounding_mode);
+
+            let result = float.i64_to_float(&F32, value);
+
+            assert_eq!(( result, float.flags ), ( expected, FLAG_NX ), "{} rm {}",
+                       value, rounding_mode);
+        }
+    }
+
+
+    #[test]
+    fn exception_flags()
+    {
+        let table: [ ( &str, u32, Operation, u64, u32 ); 10 ] =
+            [
+                ( "1 + 1",      RM_RNE, |fp| fp.add(&F32, F32_ONE, F32_ONE),
+                  F32_TWO, 0 ),
+                ( "1 / 3",      RM_RNE, |fp| fp.div(&F32, F32_ONE, F32_THREE),
+                  0x_3eaaaaab, FLAG_NX ),
+                ( "1 / 3",      RM_RTZ, |fp| fp.div(&F32, F32_ONE, F32_THREE),
+                  0x_3eaaaaaa, FLAG_NX ),
+                ( "1 / 0",      RM_RNE, |fp| fp.div(&F32, F32_ONE, 0),
+                  F32_INFINITY, FLAG_DZ ),
+                ( "0 / 0",      RM_RNE, |fp| fp.div(&F32, 0, 0),
+                  F32_CANONICAL_NAN, FLAG_NV ),
+                ( "inf - inf",  RM_RNE, |fp| fp.sub(&F32, F32_INFINITY, F32_INFINITY),
+                  F32_CANONICAL_NAN, FLAG_NV ),
+                ( "sqrt -1",    RM_RNE, |fp| fp.sqrt(&F32, F32_MINUS_ONE),
+                  F32_CANONICAL_NAN, FLAG_NV ),
+                ( "max * 2",    RM_RNE, |fp| fp.mul(&F32, F32_MAX, F32_TWO),
+                  F32_INFINITY, FLAG_OF | FLAG_NX ),
+                ( "max * 2",    RM_RTZ, |fp| fp.mul(&F32, F32_MAX, F32_TWO),
+                  F32_MAX, FLAG_OF | FLAG_NX ),
+                ( "fcvt.wu -1", RM_RNE, |fp| fp.float_to_u32(&F32, F32_MINUS_ONE) as u64,
+                  0, FLAG_NV )
+            ];
+
+        for &( name, rounding_mode, operation, expected, flags ) in &table
+        {
+            assert_eq!(evaluate(rounding_mode, operation), ( expected, flags ), "{}", name);
+        }
+    }
+
+
+    // Tininess is detected after rounding, so a result just below the smallest normal that rounds
+    // up to it doesn't underflow.
+    #[test]
+    fn tininess_after_rounding()
+    {
+        // (1 - 2^-25) * 2^-126, which a single can't hold exactly.
+        let below_min_normal = 0x_380fffff_f0000000;
+
+        let table =
+            [
+                ( RM_RNE, F32_MIN_NORMAL, FLAG_NX ),
+                ( RM_RUP, F32_MIN_NORMAL, FLAG_NX ),
+                ( RM_RTZ, 0x_007fffff,    FLAG_UF | FLAG_NX ),
+                ( RM_RDN, 0x_007fffff,    FLAG_UF | FLAG_NX )
+            ];
+
+        for &( rounding_mode, expected, flags ) in &table
+        {
+            let mut float = SoftFloat::new(rounding_mode);
+
+            let result = float.convert(&F64, &F32, below_min_normal);
+
+            assert_eq!(( result, float.flags ), ( expected, flags ), "rm {}", rounding_mode);
+        }
+
+        // An exact subnormal result isn't an underflow either.
+        assert_eq!(evaluate(RM_RNE, |fp| fp.convert(&F32, &F64, 0x_00000001)).1, 0);
+    }
+
+
+    // NaN results are always the canonical NaN, and only signaling NaNs raise invalid, except in
+    // the signaling comparisons.
+    #[test]
+    fn nan_propagation()
+    {
+        let table: [ ( &str, Operation, u64, u32 ); 10 ] =
+            [
+                ( "qnan + 1",    |fp| fp.add(&F32, F32_QUIET_NAN, F32_ONE),
+                  F32_CANONICAL_NAN, 0 ),
+                ( "snan + 1",    |fp| fp.add(&F32, F32_SIGNAL_NAN, F32_ONE),
+                  F32_CANONICAL_NAN, FLAG_NV ),
+                ( "1 * snan",    |fp| fp.mul(&F32, F32_ONE, F32_SIGNAL_NAN),
+                  F32_CANONICAL_NAN, FLAG_NV ),
+                ( "min qnan 1",  |fp| fp.min(&F32, F32_QUIET_NAN, F32_ONE),
+                  F32_ONE, 0 ),
+                ( "max 1 snan",  |fp| fp.max(&F32, F32_ONE, F32_SIGNAL_NAN),
+                  F32_ONE, FLAG_NV ),
+                ( "min qnan qnan", |fp| fp.min(&F32, F32_QUIET_NAN, F32_QUIET_NAN),
+                  F32_CANONICAL_NAN, 0 ),
+                ( "fcvt.d.s qnan", |fp| fp.convert(&F32, &F64, F32_QUIET_NAN),
+                  F64_QUIET_NAN, 0 ),
+                ( "feq qnan",    |fp| fp.eq(&F32, F32_QUIET_NAN, F32_ONE) as u64,
+                  0, 0 ),
+                ( "feq snan",    |fp| fp.eq(&F32, F32_SIGNAL_NAN, F32_ONE) as u64,
+                  0, FLAG_NV ),
+                ( "flt qnan",    |fp| fp.lt(&F32, F32_QUIET_NAN, F32_ONE) as u64,
+                  0, FLAG_NV )
+            ];
+
+        for &( name, operation, expected, flags ) in &table
+        {
+            assert_eq!(evaluate(RM_RNE, operation), ( expected, flags ), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+
+// Synchronous exceptions raised while fetching or executing an instruction.  Each carries the
+// value reported in mtval: the faulting address, or the instruction itself when it's illegal.
+// Interrupts are asynchronous, and taken between instructions.
+
+
+// Exception codes, as reported in mcause.
+pub const CAUSE_INSTRUCTION_ADDRESS_MISALIGNED: u64 = 0;
+pub const CAUSE_INSTRUCTION_ACCESS_FAULT:       u64 = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION:            u64 = 2;
+pub const CAUSE_BREAKPOINT:                     u64 = 3;
+pub const CAUSE_LOAD_ADDRESS_MISALIGNED:        u64 = 4;
+pub const CAUSE_LOAD_ACCESS_FAULT:              u64 = 5;
+pub const CAUSE_STORE_ADDRESS_MISALIGNED:       u64 = 6;
+pub const CAUSE_STORE_ACCESS_FAULT:             u64 = 7;
+pub const CAUSE_ECALL_FROM_U_MODE:              u64 = 8;
+pub const CAUSE_ECALL_FROM_S_MODE:              u64 = 9;
+pub const CAUSE_ECALL_FROM_M_MODE:              u64 = 11;
+pub const CAUSE_INSTRUCTION_PAGE_FAULT:         u64 = 12;
+pub const CAUSE_LOAD_PAGE_FAULT:                u64 = 13;
+pub const CAUSE_STORE_PAGE_FAULT:               u64 = 15;
+
+// Interrupt codes, reported in mcause along with its top bit set.
+pub const CAUSE_INTERRUPT: u64 = 1 << 63;
+
+pub const CAUSE_SUPERVISOR_SOFTWARE_INTERRUPT: u64 = 1;
+pub const CAUSE_MACHINE_SOFTWARE_INTERRUPT:    u64 = 3;
+pub const CAUSE_SUPERVISOR_TIMER_INTERRUPT:    u64 = 5;
+pub const CAUSE_MACHINE_TIMER_INTERRUPT:       u64 = 7;
+pub const CAUSE_SUPERVISOR_EXTERNAL_INTERRUPT: u64 = 9;
+pub const CAUSE_MACHINE_EXTERNAL_INTERRUPT:    u64 = 11;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exception
+{
+    InstructionAddressMisaligned(u64),
+    InstructionAccessFault(u64),
+    IllegalInstruction(u32),
+    Breakpoint(u64),
+    LoadAddressMisaligned(u64),
+    LoadAccessFault(u64),
+    StoreAddressMisaligned(u64),
+    StoreAccessFault(u64),
+    EnvironmentCallFromUMode,
+    EnvironmentCallFromSMode,
+    EnvironmentCallFromMMode,
+    InstructionPageFault(u64),
+    LoadPageFault(u64),
+    StorePageFault(u64)
+}
+
+
+impl Exception
+{
+    pub fn cause(&self) -> u64
+    {
+        match self
+        {
+            Exception::InstructionAddressMisaligned(_) => CAUSE_INSTRUCTION_ADDRESS_MISALIGNED,
+            Exception::InstructionAccessFault(_)       => CAUSE_INSTRUCTION_ACCESS_FAULT,
+            Exception::IllegalInstruction(_)           => CAUSE_ILLEGAL_INSTRUCTION,
+            Exception::Breakpoint(_)                   => CAUSE_BREAKPOINT,
+            Exception::LoadAddressMisaligned(_)        => CAUSE_LOAD_ADDRESS_MISALIGNED,
+            Exception::LoadAccessFault(_)              => CAUSE_LOAD_ACCESS_FAULT,
+            Exception::StoreAddressMisaligned(_)       => CAUSE_STORE_ADDRESS_MISALIGNED,
+            Exception::StoreAccessFault(_)             => CAUSE_STORE_ACCESS_FAULT,
+            Exception::EnvironmentCallFromUMode        => CAUSE_ECALL_FROM_U_MODE,
+            Exception::EnvironmentCallFromSMode        => CAUSE_ECALL_FROM_S_MODE,
+            Exception::EnvironmentCallFromMMode        => CAUSE_ECALL_FROM_M_MODE,
+            Exception::InstructionPageFault(_)         => CAUSE_INSTRUCTION_PAGE_FAULT,
+            Exception::LoadPageFault(_)                => CAUSE_LOAD_PAGE_FAULT,
+            Exception::StorePageFault(_)               => CAUSE_STORE_PAGE_FAULT
+        }
+    }
+
+
+    pub fn tval(&self) -> u64
+    {
+        match *self
+        {
+            Exception::InstructionAddressMisaligned(address) |
+            Exception::InstructionAccessFault(address) |
+            Exception::Breakpoint(address) |
+            Exception::LoadAddressMisaligned(address) |
+            Exception::LoadAccessFault(address) |
+            Exception::StoreAddressMisaligned(address) |
+            Exception::StoreAccessFault(address) |
+            Exception::InstructionPageFault(address) |
+            Exception::LoadPageFault(address) |
+            Exception::StorePageFault(address) =>
+                address,
+
+            Exception::IllegalInstruction(raw_instruction) =>
+                raw_instruction as u64,
+
+            Exception::EnvironmentCallFromUMode |
+            Exception::EnvironmentCallFromSMode |
+            Exception::EnvironmentCallFromMMode =>
+                0
+        }
+    }
+
+
+    // AMOs report their faults as store faults, even for the read half of the operation.
+    pub fn as_store_fault(self) -> Exception
+    {
+        match self
+        {
+            Exception::LoadAddressMisaligned(address) => Exception::StoreAddressMisaligned(address),
+            Exception::LoadAccessFault(address)       => Exception::StoreAccessFault(address),
+            Exception::LoadPageFault(address)         => Exception::StorePageFault(address),
+            other                                     => other
+        }
+    }
+}
+
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interrupt
+{
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal
+}
+
+
+// When several interrupts are pending at once, the first of these is taken.
+pub const INTERRUPT_PRIORITY: [ Interrupt; 6 ] = [ Interrupt::MachineExternal,
+                                                   Interrupt::MachineSoftware,
+                                                   Interrupt::MachineTimer,
+                                                   Interrupt::SupervisorExternal,
+                                                   Interrupt::SupervisorSoftware,
+                                                   Interrupt::SupervisorTimer ];
+
+
+impl Interrupt
+{
+    // The interrupt code, without the interrupt bit.  It's also the interrupt's bit in mip and mie.
+    pub fn code(&self) -> u64
+    {
+        match self
+        {
+            Interrupt::SupervisorSoftware => CAUSE_SUPERVISOR_SOFTWARE_INTERRUPT,
+            Interrupt::MachineSoftware    => CAUSE_MACHINE_SOFTWARE_INTERRUPT,
+            Interrupt::SupervisorTimer    => CAUSE_SUPERVISOR_TIMER_INTERRUPT,
+            Interrupt::MachineTimer       => CAUSE_MACHINE_TIMER_INTERRUPT,
+            Interrupt::SupervisorExternal => CAUSE_SUPERVISOR_EXTERNAL_INTERRUPT,
+            Interrupt::MachineExternal    => CAUSE_MACHINE_EXTERNAL_INTERRUPT
+        }
+    }
+
+
+    pub fn cause(&self) -> u64
+    {
+        CAUSE_INTERRUPT | self.code()
+    }
+}
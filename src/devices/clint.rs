@@ -0,0 +1,171 @@
+
+// The core-local interruptor, with the SiFive register layout for a single hart: its software
+// interrupt bit, its timer compare register and the shared timer.  mtime advances by one tick
+// for every instruction the hart runs, so runs are repeatable no matter how fast the host is.
+
+
+use crate::bus::Device;
+
+
+pub const CLINT_BASE: u64 = 0x_0200_0000;
+pub const CLINT_SIZE: u64 = 0x_1_0000;
+
+// The rate mtime nominally runs at, as advertised to the guest.
+pub const CLINT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+
+// Register offsets.
+const CLINT_MSIP:     u64 = 0x_0000;
+const CLINT_MTIMECMP: u64 = 0x_4000;
+const CLINT_MTIME:    u64 = 0x_bff8;
+
+// wfi skips ahead to the next timer interrupt, as long as it's no more than this far off.
+const MAX_IDLE_TICKS: u64 = CLINT_TIMEBASE_FREQUENCY / 10;
+
+
+
+pub struct Clint
+{
+    msip: bool,
+    mtimecmp: u64,
+    mtime: u64
+}
+
+
+impl Clint
+{
+    // The compare register starts out as far in the future as it can be, so the timer doesn't go
+    // off before anything has been set up.
+    pub fn new() -> Self
+    {
+        Self { msip: false, mtimecmp: u64::MAX, mtime: 0 }
+    }
+
+
+    pub fn tick(&mut self)
+    {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+
+    pub fn mtime(&self) -> u64
+    {
+        self.mtime
+    }
+
+
+    pub fn timer_pending(&self) -> bool
+    {
+        self.mtime >= self.mtimecmp
+    }
+
+
+    // For firmware running outside the guest, which sets the timer without going through the bus.
+    pub fn set_mtimecmp(&mut self, value: u64)
+    {
+        self.mtimecmp = value;
+    }
+
+
+    pub fn software_pending(&self) -> bool
+    {
+        self.msip
+    }
+
+
+    // Nothing will happen until the timer goes off, so skip straight to it.
+    pub fn idle(&mut self)
+    {
+        if self.mtimecmp > self.mtime && self.mtimecmp - self.mtime <= MAX_IDLE_TICKS
+        {
+            self.mtime = self.mtimecmp;
+        }
+    }
+
+
+    // Registers can be accessed as whole 64-bit values, or as 32-bit halves.  msip is only 32 bits
+    // wide.  Gives the register's offset and where the access sits within it.
+    fn locate(offset: u64, size: usize) -> Option<( u64, u64 )>
+    {
+        let register = offset & !0b_111;
+        let shift = (offset & 0b_100) * 8;
+
+        let valid = match register
+            {
+                CLINT_MSIP                   => offset == CLINT_MSIP && size == 4,
+                CLINT_MTIMECMP | CLINT_MTIME => size == 4 || size == 8,
+                _                            => false
+            };
+
+        if valid && offset.is_multiple_of(size as u64)
+        {
+            Some(( register, shift ))
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+
+impl Default for Clint
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+impl Device for Clint
+{
+    fn read(&mut self, offset: u64, bytes: &mut [ u8 ]) -> bool
+    {
+        let ( register, shift ) = match Clint::locate(offset, bytes.len())
+            {
+                Some(location) => location,
+                None           => return false
+            };
+
+        let value = match register
+            {
+                CLINT_MSIP     => self.msip as u64,
+                CLINT_MTIMECMP => self.mtimecmp,
+                _              => self.mtime
+            };
+
+        let size = bytes.len();
+
+        bytes.copy_from_slice(&(value >> shift).to_le_bytes()[..size]);
+        true
+    }
+
+
+    fn write(&mut self, offset: u64, bytes: &[ u8 ]) -> bool
+    {
+        let ( register, shift ) = match Clint::locate(offset, bytes.len())
+            {
+                Some(location) => location,
+                None           => return false
+            };
+
+        let mut buffer = [ 0; 8 ];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+
+        // Writing half of a register leaves the other half alone.
+        let mask = if bytes.len() == 8 { u64::MAX } else { 0x_ffff_ffff << shift };
+        let value = u64::from_le_bytes(buffer) << shift;
+
+        let merge = |old: u64| (old & !mask) | (value & mask);
+
+        match register
+        {
+            CLINT_MSIP     => self.msip = value & 1 != 0,
+            CLINT_MTIMECMP => self.mtimecmp = merge(self.mtimecmp),
+            _              => self.mtime = merge(self.mtime)
+        }
+
+        true
+    }
+}
@@ -0,0 +1,8 @@
+mod uart;
+mod clint;
+mod plic;
+
+
+pub use uart::*;
+pub use clint::*;
+pub use plic::*;
@@ -0,0 +1,501 @@
+
+// A SiFive compatible platform-level interrupt controller, for a single hart with a machine and a
+// supervisor context.  Each source is a device's interrupt output.  Its pending bit follows the
+// line's level until the source is claimed, and can't be set again until the claim is completed.
+
+
+use std::{ cell::RefCell, rc::Rc };
+use crate::bus::Device;
+
+
+pub const PLIC_BASE: u64 = 0x_0c00_0000;
+pub const PLIC_SIZE: u64 = 0x_0060_0000;
+
+// Source 0 doesn't exist, it's what a claim returns when nothing is pending.
+pub const PLIC_SOURCES: usize = 64;
+
+pub const PLIC_CONTEXT_MACHINE:    usize = 0;
+pub const PLIC_CONTEXT_SUPERVISOR: usize = 1;
+
+const PLIC_CONTEXTS: usize = 2;
+
+const PLIC_MAX_PRIORITY: u32 = 7;
+
+
+// Register offsets.  The enables and the threshold and claim registers are repeated for each
+// context.
+const PLIC_PRIORITY:       u64 = 0x_00_0000;
+const PLIC_PENDING:        u64 = 0x_00_1000;
+const PLIC_ENABLE:         u64 = 0x_00_2000;
+const PLIC_ENABLE_STRIDE:  u64 = 0x_80;
+const PLIC_THRESHOLD:      u64 = 0x_20_0000;
+const PLIC_CLAIM:          u64 = 0x_20_0004;
+const PLIC_CONTEXT_STRIDE: u64 = 0x_1000;
+
+
+
+pub struct Plic
+{
+    lines: Vec<( usize, Rc<RefCell<dyn Device>> )>,
+
+    priority: [ u32; PLIC_SOURCES ],
+    threshold: [ u32; PLIC_CONTEXTS ],
+
+    // One bit per source.
+    pending: u64,
+    claimed: u64,
+    enable: [ u64; PLIC_CONTEXTS ]
+}
+
+
+impl Plic
+{
+    pub fn new() -> Self
+    {
+        Self { lines: Vec::new(),
+               priority: [ 0; PLIC_SOURCES ],
+               threshold: [ 0; PLIC_CONTEXTS ],
+               pending: 0,
+               claimed: 0,
+               enable: [ 0; PLIC_CONTEXTS ] }
+    }
+
+
+    // Wire a device's interrupt output to a source.
+    pub fn connect(&mut self, source: usize, device: Rc<RefCell<dyn Device>>)
+    {
+        assert!(source > 0 && source < PLIC_SOURCES, "PLIC source {} doesn't exist.", source);
+        self.lines.push(( source, device ));
+    }
+
+
+    // Sample the interrupt lines.
+    pub fn update(&mut self)
+    {
+        for ( source, device ) in &self.lines
+        {
+            let bit = 1 << source;
+
+            if self.claimed & bit == 0
+            {
+                if device.borrow_mut().interrupt_pending()
+                {
+                    self.pending |= bit;
+                }
+                else
+                {
+                    self.pending &= !bit;
+                }
+            }
+        }
+    }
+
+
+    // Whether the context's interrupt output is raised.
+    pub fn context_pending(&self, context: usize) -> bool
+    {
+        self.best_source(context).is_some()
+    }
+
+
+    // The pending and enabled source with the highest priority above the context's threshold.  Ties
+    // go to the lowest numbered source.
+    fn best_source(&self, context: usize) -> Option<usize>
+    {
+        let candidates = self.pending & self.enable[context];
+
+        if candidates == 0
+        {
+            return None;
+        }
+
+        ( 1..PLIC_SOURCES ).filter(|&source| candidates & (1 << source) != 0)
+                           .filter(|&source| self.priority[source] > self.threshold[context])
+                           .max_by_key(|&source| ( self.priority[source], -(source as i64) ))
+    }
+
+
+    fn claim(&mut self, context: usize) -> u32
+    {
+        match self.best_source(context)
+        {
+            Some(source) =>
+                {
+                    self.pending &= !(1 << source);
+                    self.claimed |= 1 << source;
+
+                    source as u32
+                },
+
+            None =>
+                0
+        }
+    }
+
+
+    // Completing a source that isn't enabled for the context is ignored.
+    fn complete(&mut self, context: usize, source: u32)
+    {
+        let bit = 1_u64.checked_shl(source).unwrap_or(0);
+
+        if self.enable[context] & bit != 0
+        {
+            self.claimed &= !bit;
+        }
+    }
+
+
+    // Split an offset in the per-context block into the context, and the register as it's found
+    // for context 0.
+    fn context_register(offset: u64) -> Option<( usize, u64 )>
+    {
+        let context = ((offset - PLIC_THRESHOLD) / PLIC_CONTEXT_STRIDE) as usize;
+        let register = PLIC_THRESHOLD + (offset - PLIC_THRESHOLD) % PLIC_CONTEXT_STRIDE;
+
+        if context < PLIC_CONTEXTS
+        {
+            Some(( context, register ))
+        }
+        else
+        {
+            None
+        }
+    }
+
+
+    fn read_register(&mut self, offset: u64) -> Option<u32>
+    {
+        let sources = PLIC_SOURCES as u64;
+        let contexts = PLIC_CONTEXTS as u64;
+
+        let value = match offset
+            {
+                _ if offset < PLIC_PRIORITY + 4 * sources =>
+                    self.priority[(offset / 4) as usize],
+
+                _ if (PLIC_PENDING..PLIC_PENDING + sources / 8).contains(&offset) =>
+                    (self.pending >> ((offset - PLIC_PENDING) * 8)) as u32,
+
+                _ if (PLIC_ENABLE..PLIC_ENABLE + PLIC_ENABLE_STRIDE * contexts).contains(&offset) =>
+                    {
+                        let context = ((offset - PLIC_ENABLE) / PLIC_ENABLE_STRIDE) as usize;
+                        let word = (offset - PLIC_ENABLE) % PLIC_ENABLE_STRIDE;
+
+                        if word >= sources / 8
+                        {
+                            return None;
+                        }
+
+                        (self.enable[context] >> (word * 8)) as u32
+                    },
+
+                _ if offset >= PLIC_THRESHOLD =>
+                    match Plic::context_register(offset)?
+                    {
+                        ( context, PLIC_THRESHOLD ) => self.threshold[context],
+                        ( context, PLIC_CLAIM )     => self.claim(context),
+                        _                           => return None
+                    },
+
+                _ =>
+                    return None
+            };
+
+        Some(value)
+    }
+
+
+    fn write_register(&mut self, offset: u64, value: u32) -> Option<()>
+    {
+        let sources = PLIC_SOURCES as u64;
+        let contexts = PLIC_CONTEXTS as u64;
+
+        match offset
+        {
+            // Source 0 doesn't exist, so its priority stays at zero.
+            _ if offset < PLIC_PRIORITY + 4 * sources =>
+                {
+                    if offset >= 4
+                    {
+                        self.priority[(offset / 4) as usize] = value.min(PLIC_MAX_PRIORITY);
+                    }
+                },
+
+            // The pending bits are read-only.
+            _ if (PLIC_PENDING..PLIC_PENDING + sources / 8).contains(&offset) =>
+                {
+                },
+
+            _ if (PLIC_ENABLE..PLIC_ENABLE + PLIC_ENABLE_STRIDE * contexts).contains(&offset) =>
+                {
+                    let context = ((offset - PLIC_ENABLE) / PLIC_ENABLE_STRIDE) as usize;
+                    let word = (offset - PLIC_ENABLE) % PLIC_ENABLE_STRIDE;
+
+                    if word >= sources / 8
+                    {
+                        return None;
+                    }
+
+                    let shift = word * 8;
+                    let enable = (self.enable[context] & !(0x_ffff_ffff << shift))
+                                 | ((value as u64) << shift);
+
+                    self.enable[context] = enable & !1;
+                },
+
+            _ if offset >= PLIC_THRESHOLD =>
+                match Plic::context_register(offset)?
+                {
+                    ( context, PLIC_THRESHOLD ) =>
+                        self.threshold[context] = value.min(PLIC_MAX_PRIORITY),
+
+                    ( context, PLIC_CLAIM ) =>
+                        self.complete(context, value),
+
+                    _ =>
+                        return None
+                },
+
+            _ =>
+                return None
+        }
+
+        Some(())
+    }
+}
+
+
+impl Default for Plic
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+// All of the registers are 32 bits wide, and have to be accessed as a whole.
+impl Device for Plic
+{
+    fn read(&mut self, offset: u64, bytes: &mut [ u8 ]) -> bool
+    {
+        if bytes.len() != 4 || !offset.is_multiple_of(4)
+        {
+            return false;
+        }
+
+        match self.read_register(offset)
+        {
+            Some(value) => { bytes.copy_from_slice(&value.to_le_bytes()); true },
+            None        => false
+        }
+    }
+
+
+    fn write(&mut self, offset: u64, bytes: &[ u8 ]) -> bool
+    {
+        if bytes.len() != 4 || !offset.is_multiple_of(4)
+        {
+            return false;
+        }
+
+        let value = u32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ]);
+
+        self.write_register(offset, value).is_some()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    // An interrupt line, raised and lowered by hand.
+    struct Line
+    {
+        level: bool
+    }
+
+
+    impl Device for Line
+    {
+        fn read(&mut self, _offset: u64, _bytes: &mut [ u8 ]) -> bool
+        {
+            false
+        }
+
+
+        fn write(&mut self, _offset: u64, _bytes: &[ u8 ]) -> bool
+        {
+            false
+        }
+
+
+        fn interrupt_pending(&mut self) -> bool
+        {
+            self.level
+        }
+    }
+
+
+    fn read(plic: &mut Plic, offset: u64) -> Option<u32>
+    {
+        let mut bytes = [ 0; 4 ];
+
+        if plic.read(offset, &mut bytes) { Some(u32::from_le_bytes(bytes)) } else { None }
+    }
+
+
+    fn write(plic: &mut Plic, offset: u64, value: u32) -> bool
+    {
+        plic.write(offset, &value.to_le_bytes())
+    }
+
+
+    fn claim_register(context: usize) -> u64
+    {
+        PLIC_CLAIM + PLIC_CONTEXT_STRIDE * context as u64
+    }
+
+
+    // A PLIC with lines on the given sources, all of them enabled for the supervisor context.
+    fn plic(sources: &[ ( usize, u32 ) ]) -> ( Plic, Vec<Rc<RefCell<Line>>> )
+    {
+        let mut plic = Plic::new();
+        let mut lines = Vec::new();
+
+        for &( source, priority ) in sources
+        {
+            let line = Rc::new(RefCell::new(Line { level: false }));
+
+            plic.connect(source, line.clone());
+            assert!(write(&mut plic, PLIC_PRIORITY + 4 * source as u64, priority));
+            lines.push(line);
+        }
+
+        let enable = sources.iter().fold(0_u64, |enable, &( source, _ )| enable | 1 << source);
+        let supervisor = PLIC_ENABLE + PLIC_ENABLE_STRIDE * PLIC_CONTEXT_SUPERVISOR as u64;
+
+        assert!(write(&mut plic, supervisor, enable as u32));
+        assert!(write(&mut plic, supervisor + 4, (enable >> 32) as u32));
+
+        ( plic, lines )
+    }
+
+
+    #[test]
+    fn registers()
+    {
+        let mut plic = Plic::new();
+
+        // Writes, and what reading back the register gives.
+        let table =
+            [
+                ( 0x_00_0004, 3,            Some(3) ),              // source 1's priority
+                ( 0x_00_00fc, 100,          Some(7) ),              // clamped to the maximum
+                ( 0x_00_0000, 5,            Some(0) ),              // source 0 has no priority
+                ( 0x_00_1000, 0x_ffff,      Some(0) ),              // pending is read-only
+                ( 0x_00_2000, 0x_ffff,      Some(0x_fffe) ),        // source 0 can't be enabled
+                ( 0x_00_2084, 0x_8000_0000, Some(0x_8000_0000) ),   // supervisor, source 63
+                ( 0x_20_0000, 9,            Some(7) ),              // machine threshold, clamped
+                ( 0x_20_1000, 2,            Some(2) ),              // supervisor threshold
+                ( 0x_00_1008, 0,            None ),                 // past the pending bits
+                ( 0x_00_2008, 0,            None ),                 // past the enables
+                ( 0x_20_0008, 0,            None ),                 // between context registers
+                ( 0x_20_2000, 0,            None )                  // a context that doesn't exist
+            ];
+
+        for &( offset, value, expected ) in &table
+        {
+            assert_eq!(write(&mut plic, offset, value), expected.is_some(), "{:#x}", offset);
+            assert_eq!(read(&mut plic, offset), expected, "{:#x}", offset);
+        }
+
+        // Only whole, aligned registers.
+        assert!(!plic.write(0x_00_0004, &[ 1, 0 ]));
+        assert!(!plic.read(0x_00_0006, &mut [ 0; 4 ]));
+    }
+
+
+    // Sources are claimed highest priority first, with ties going to the lowest numbered source.
+    #[test]
+    fn claim_order()
+    {
+        let ( mut plic, lines ) = plic(&[ ( 1, 1 ), ( 5, 3 ), ( 9, 3 ), ( 40, 2 ) ]);
+        let claim = claim_register(PLIC_CONTEXT_SUPERVISOR);
+
+        for line in &lines
+        {
+            line.borrow_mut().level = true;
+        }
+
+        plic.update();
+
+        assert!(plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+        assert!(!plic.context_pending(PLIC_CONTEXT_MACHINE));
+        assert_eq!(read(&mut plic, PLIC_PENDING), Some(1 << 1 | 1 << 5 | 1 << 9));
+        assert_eq!(read(&mut plic, PLIC_PENDING + 4), Some(1 << (40 - 32)));
+
+        for &expected in &[ 5, 9, 40, 1, 0 ]
+        {
+            assert_eq!(read(&mut plic, claim), Some(expected));
+        }
+
+        assert!(!plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+    }
+
+
+    // A claimed source stays quiet until it's completed, then follows its line again.
+    #[test]
+    fn claim_and_complete()
+    {
+        let ( mut plic, lines ) = plic(&[ ( 10, 1 ) ]);
+        let claim = claim_register(PLIC_CONTEXT_SUPERVISOR);
+
+        lines[0].borrow_mut().level = true;
+        plic.update();
+
+        assert_eq!(read(&mut plic, claim), Some(10));
+
+        plic.update();
+        assert!(!plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+
+        // Completing from a context the source isn't enabled for does nothing.
+        assert!(write(&mut plic, claim_register(PLIC_CONTEXT_MACHINE), 10));
+        plic.update();
+        assert!(!plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+
+        assert!(write(&mut plic, claim, 10));
+        plic.update();
+        assert!(plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+
+        // Lowering the line withdraws the interrupt before it's claimed.
+        lines[0].borrow_mut().level = false;
+        plic.update();
+        assert!(!plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+        assert_eq!(read(&mut plic, claim), Some(0));
+    }
+
+
+    // Only priorities above the context's threshold interrupt it.
+    #[test]
+    fn threshold()
+    {
+        let ( mut plic, lines ) = plic(&[ ( 3, 2 ) ]);
+        let threshold = PLIC_THRESHOLD + PLIC_CONTEXT_STRIDE * PLIC_CONTEXT_SUPERVISOR as u64;
+
+        lines[0].borrow_mut().level = true;
+        plic.update();
+
+        assert!(write(&mut plic, threshold, 2));
+        assert!(!plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+        assert_eq!(read(&mut plic, claim_register(PLIC_CONTEXT_SUPERVISOR)), Some(0));
+
+        assert!(write(&mut plic, threshold, 1));
+        assert!(plic.context_pending(PLIC_CONTEXT_SUPERVISOR));
+        assert_eq!(read(&mut plic, claim_register(PLIC_CONTEXT_SUPERVISOR)), Some(3));
+    }
+}
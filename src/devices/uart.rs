@@ -0,0 +1,332 @@
+
+// An NS16550A compatible UART, connected to the host's standard input and output.  Transmitted
+// bytes are written out straight away, so the transmitter is always empty.  Received bytes come
+// from a thread reading stdin and queue up in the 16 byte receive FIFO.  The divisor latch and
+// modem control registers hold their values but otherwise do nothing.
+
+
+use std::{ collections::VecDeque,
+           io::{ self, Read, Write },
+           sync::mpsc::{ self, Receiver },
+           thread };
+use crate::bus::Device;
+
+
+pub const UART_BASE: u64 = 0x_1000_0000;
+pub const UART_SIZE: u64 = 0x_100;
+
+// The PLIC source the interrupt output is wired to.
+pub const UART_IRQ: usize = 10;
+
+
+// Register offsets.  Some share an offset, selected by whether it's read or written, or by the
+// divisor latch access bit in LCR.
+const UART_RBR: u64 = 0;  // Receive buffer, read.
+const UART_THR: u64 = 0;  // Transmit holding, write.
+const UART_DLL: u64 = 0;  // Divisor latch low, with DLAB.
+const UART_IER: u64 = 1;  // Interrupt enable.
+const UART_DLM: u64 = 1;  // Divisor latch high, with DLAB.
+const UART_IIR: u64 = 2;  // Interrupt identification, read.
+const UART_FCR: u64 = 2;  // FIFO control, write.
+const UART_LCR: u64 = 3;  // Line control.
+const UART_MCR: u64 = 4;  // Modem control.
+const UART_LSR: u64 = 5;  // Line status.
+const UART_MSR: u64 = 6;  // Modem status.
+const UART_SCR: u64 = 7;  // Scratch.
+
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY:     u8 = 1 << 1;
+const IER_MASK:         u8 = 0b_1111;
+
+const IIR_NONE:         u8 = 0b_0001;
+const IIR_TX_EMPTY:     u8 = 0b_0010;
+const IIR_RX_AVAILABLE: u8 = 0b_0100;
+const IIR_FIFO_ENABLED: u8 = 0b_1100_0000;
+
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_RX_RESET:    u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY:  u8 = 1 << 5;
+const LSR_TX_EMPTY:   u8 = 1 << 6;
+
+// Carrier detect, data set ready and clear to send, as if a terminal were always connected.
+const MSR_CONNECTED: u8 = 0b_1011_0000;
+
+const FIFO_SIZE: usize = 16;
+
+
+
+pub struct Uart
+{
+    input: Receiver<u8>,
+    rx_fifo: VecDeque<u8>,
+
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    fifo_enabled: bool,
+
+    // Set when the transmitter becomes empty, cleared by writing THR or reading IIR while it's the
+    // reported interrupt.
+    tx_empty_pending: bool
+}
+
+
+impl Uart
+{
+    pub fn new() -> Self
+    {
+        let ( sender, input ) = mpsc::channel();
+
+        // The thread finishes at the end of stdin, or once the UART has gone away.
+        thread::spawn(move ||
+            {
+                for byte in io::stdin().lock().bytes()
+                {
+                    match byte
+                    {
+                        Ok(byte) if sender.send(byte).is_ok() => continue,
+                        _                                     => break
+                    }
+                }
+            });
+
+        Self { input,
+               rx_fifo: VecDeque::with_capacity(FIFO_SIZE),
+               ier: 0,
+               lcr: 0,
+               mcr: 0,
+               scr: 0,
+               dll: 0,
+               dlm: 0,
+               fifo_enabled: false,
+               tx_empty_pending: false }
+    }
+
+
+    // Move whatever the host has typed into the receive FIFO, as far as it has room.
+    fn receive(&mut self)
+    {
+        while self.rx_fifo.len() < FIFO_SIZE
+        {
+            match self.input.try_recv()
+            {
+                Ok(byte) => self.rx_fifo.push_back(byte),
+                Err(_)   => break
+            }
+        }
+    }
+
+
+    fn transmit(&mut self, byte: u8)
+    {
+        let mut stdout = io::stdout();
+
+        // There's nothing the guest could do about a failed write, so the byte is just dropped.
+        let _ = stdout.write_all(&[ byte ]).and_then(|_| stdout.flush());
+
+        self.tx_empty_pending = true;
+    }
+
+
+    // Take the next byte of input from outside the guest's view of the registers, as firmware
+    // console calls do.
+    pub fn take_input(&mut self) -> Option<u8>
+    {
+        self.receive();
+        self.rx_fifo.pop_front()
+    }
+
+
+    // As above, but waits for the host to type something.  There's nothing more to come once stdin
+    // has been closed.
+    pub fn wait_input(&mut self) -> Option<u8>
+    {
+        match self.rx_fifo.pop_front()
+        {
+            Some(byte) => Some(byte),
+            None       => self.input.recv().ok()
+        }
+    }
+
+
+    // The highest priority interrupt that is both enabled and pending.
+    fn interrupt_id(&mut self) -> u8
+    {
+        self.receive();
+
+        if self.ier & IER_RX_AVAILABLE != 0 && !self.rx_fifo.is_empty()
+        {
+            IIR_RX_AVAILABLE
+        }
+        else if self.ier & IER_TX_EMPTY != 0 && self.tx_empty_pending
+        {
+            IIR_TX_EMPTY
+        }
+        else
+        {
+            IIR_NONE
+        }
+    }
+
+
+    fn read_register(&mut self, offset: u64) -> Option<u8>
+    {
+        let dlab = self.lcr & LCR_DLAB != 0;
+
+        let value = match offset
+            {
+                UART_DLL if dlab => self.dll,
+                UART_DLM if dlab => self.dlm,
+
+                UART_RBR =>
+                    {
+                        self.receive();
+                        self.rx_fifo.pop_front().unwrap_or(0)
+                    },
+
+                UART_IER =>
+                    self.ier,
+
+                UART_IIR =>
+                    {
+                        let id = self.interrupt_id();
+
+                        if id == IIR_TX_EMPTY
+                        {
+                            self.tx_empty_pending = false;
+                        }
+
+                        if self.fifo_enabled { id | IIR_FIFO_ENABLED } else { id }
+                    },
+
+                UART_LCR =>
+                    self.lcr,
+
+                UART_MCR =>
+                    self.mcr,
+
+                UART_LSR =>
+                    {
+                        self.receive();
+
+                        let ready = if self.rx_fifo.is_empty() { 0 } else { LSR_DATA_READY };
+
+                        ready | LSR_THR_EMPTY | LSR_TX_EMPTY
+                    },
+
+                UART_MSR =>
+                    MSR_CONNECTED,
+
+                UART_SCR =>
+                    self.scr,
+
+                _ =>
+                    return None
+            };
+
+        Some(value)
+    }
+
+
+    fn write_register(&mut self, offset: u64, value: u8) -> Option<()>
+    {
+        let dlab = self.lcr & LCR_DLAB != 0;
+
+        match offset
+        {
+            UART_DLL if dlab => self.dll = value,
+            UART_DLM if dlab => self.dlm = value,
+
+            UART_THR =>
+                self.transmit(value),
+
+            // The transmitter is already empty, so enabling its interrupt raises it.
+            UART_IER =>
+                {
+                    if value & IER_TX_EMPTY != 0 && self.ier & IER_TX_EMPTY == 0
+                    {
+                        self.tx_empty_pending = true;
+                    }
+
+                    self.ier = value & IER_MASK;
+                },
+
+            UART_FCR =>
+                {
+                    self.fifo_enabled = value & FCR_FIFO_ENABLE != 0;
+
+                    if value & FCR_RX_RESET != 0
+                    {
+                        self.rx_fifo.clear();
+                    }
+                },
+
+            UART_LCR =>
+                self.lcr = value,
+
+            UART_MCR =>
+                self.mcr = value,
+
+            UART_SCR =>
+                self.scr = value,
+
+            // The line and modem status registers are read-only.
+            UART_LSR | UART_MSR =>
+                {
+                },
+
+            _ =>
+                return None
+        }
+
+        Some(())
+    }
+}
+
+
+impl Default for Uart
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+// The registers are all a byte wide, and can only be accessed a byte at a time.
+impl Device for Uart
+{
+    fn read(&mut self, offset: u64, bytes: &mut [ u8 ]) -> bool
+    {
+        if bytes.len() != 1
+        {
+            return false;
+        }
+
+        match self.read_register(offset)
+        {
+            Some(value) => { bytes[0] = value; true },
+            None        => false
+        }
+    }
+
+
+    fn write(&mut self, offset: u64, bytes: &[ u8 ]) -> bool
+    {
+        bytes.len() == 1 && self.write_register(offset, bytes[0]).is_some()
+    }
+
+
+    fn interrupt_pending(&mut self) -> bool
+    {
+        self.interrupt_id() != IIR_NONE
+    }
+}
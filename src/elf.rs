@@ -0,0 +1,556 @@
+
+// Loader for RISC-V ELF executables, both ELF32 and ELF64.  Each PT_LOAD segment is placed at its
+// physical address on a bare machine, or its virtual address in a process, with the remainder of
+// its memory size zero filled.  The symbol table is kept around so that addresses can be given
+// names in diagnostics.
+
+
+use std::{ convert::TryInto, io::{ Error, ErrorKind }, ops::Range };
+use crate::bus::Bus;
+
+
+const ELF_MAGIC: [ u8; 4 ] = [ 0x_7f, b'E', b'L', b'F' ];
+
+const ELFCLASS32:  u8 = 1;
+const ELFCLASS64:  u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const ET_EXEC:  u16 = 2;
+const EM_RISCV: u16 = 243;
+
+const PT_LOAD:    u32 = 1;
+const PT_PHDR:    u32 = 6;
+const SHT_SYMTAB: u32 = 2;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC:   u8 = 2;
+
+const SHN_UNDEF: u16 = 0;
+
+
+
+// Which of a segment's addresses it's loaded at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AddressSpace
+{
+    Physical,
+    Virtual
+}
+
+
+pub struct Segment
+{
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub memory_size: u64
+}
+
+
+pub struct Symbol
+{
+    pub name: String,
+    pub address: u64,
+    pub size: u64
+}
+
+
+pub struct Elf
+{
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+
+    // Where the program headers end up in memory, if they're loaded at all.  A C library finds its
+    // thread-local storage through them.
+    pub program_headers: Option<u64>,
+    pub program_header_size: u64,
+    pub program_header_count: u64,
+
+    // Sorted by address.
+    pub symbols: Vec<Symbol>
+}
+
+
+
+fn invalid(message: &str) -> Error
+{
+    Error::new(ErrorKind::InvalidData, format!("Invalid ELF file: {}.", message))
+}
+
+
+// Little-endian field access into the file, where the size of an address depends on the class.
+struct Reader<'a>
+{
+    file: &'a [ u8 ],
+    is_64_bit: bool
+}
+
+
+impl<'a> Reader<'a>
+{
+    fn bytes(&self, offset: u64, size: u64) -> Result<&'a [ u8 ], Error>
+    {
+        offset.checked_add(size)
+              .and_then(|end| self.file.get(offset as usize..end as usize))
+              .ok_or_else(|| invalid("truncated"))
+    }
+
+
+    fn u8(&self, offset: u64) -> Result<u8, Error>
+    {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+
+    fn u16(&self, offset: u64) -> Result<u16, Error>
+    {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+
+    fn u32(&self, offset: u64) -> Result<u32, Error>
+    {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+
+    fn u64(&self, offset: u64) -> Result<u64, Error>
+    {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+
+    // An address or offset sized field, 32-bit in ELF32 and 64-bit in ELF64.
+    fn word(&self, offset: u64) -> Result<u64, Error>
+    {
+        if self.is_64_bit
+        {
+            self.u64(offset)
+        }
+        else
+        {
+            Ok(self.u32(offset)? as u64)
+        }
+    }
+
+
+    // Pick a field's offset for the file's class.
+    fn pick(&self, offset_32: u64, offset_64: u64) -> u64
+    {
+        if self.is_64_bit { offset_64 } else { offset_32 }
+    }
+
+
+    // The offset of an entry in a table, once it's known to be within the file.
+    fn entry(&self, table: u64, index: u64, size: u64) -> Result<u64, Error>
+    {
+        let offset = table.checked_add(index * size).ok_or_else(|| invalid("truncated"))?;
+
+        self.bytes(offset, size)?;
+        Ok(offset)
+    }
+
+
+    fn string(&self, table: u64, index: u64) -> Result<String, Error>
+    {
+        let tail = table.checked_add(index)
+                        .and_then(|offset| self.file.get(offset as usize..))
+                        .ok_or_else(|| invalid("bad string offset"))?;
+        let length = tail.iter().position(|&byte| byte == 0).unwrap_or(tail.len());
+
+        Ok(String::from_utf8_lossy(&tail[..length]).into_owned())
+    }
+}
+
+
+
+impl Elf
+{
+    pub fn is_elf(file: &[ u8 ]) -> bool
+    {
+        file.starts_with(&ELF_MAGIC)
+    }
+
+
+    // Every segment has to fit in the given memory, at the address it's loaded at.
+    pub fn parse(file: &[ u8 ], space: AddressSpace, memory: Range<u64>) -> Result<Self, Error>
+    {
+        if !Elf::is_elf(file) || file.len() < 16
+        {
+            return Err(invalid("missing magic number"));
+        }
+
+        let is_64_bit = match file[4]
+            {
+                ELFCLASS32 => false,
+                ELFCLASS64 => true,
+                _          => return Err(invalid("unknown class"))
+            };
+
+        let reader = Reader { file, is_64_bit };
+
+        if file[5] != ELFDATA2LSB
+        {
+            return Err(invalid("not little-endian"));
+        }
+
+        if reader.u16(16)? != ET_EXEC
+        {
+            return Err(invalid("not an executable"));
+        }
+
+        if reader.u16(18)? != EM_RISCV
+        {
+            return Err(invalid("not a RISC-V executable"));
+        }
+
+        let entry = reader.word(24)?;
+
+        let program_header_size = reader.u16(reader.pick(42, 54))? as u64;
+        let program_header_count = reader.u16(reader.pick(44, 56))? as u64;
+
+        let ( segments, program_headers ) = Elf::parse_segments(&reader, space, memory)?;
+        let symbols = Elf::parse_symbols(&reader)?;
+
+        Ok(Elf { entry,
+                 segments,
+                 program_headers,
+                 program_header_size,
+                 program_header_count,
+                 symbols })
+    }
+
+
+    // The program headers are found from PT_PHDR, or failing that from the segment whose file
+    // data covers them.
+    fn parse_segments(reader: &Reader,
+                      space: AddressSpace,
+                      memory: Range<u64>) -> Result<( Vec<Segment>, Option<u64> ), Error>
+    {
+        let phoff = reader.word(reader.pick(28, 32))?;
+        let phentsize = reader.u16(reader.pick(42, 54))? as u64;
+        let phnum = reader.u16(reader.pick(44, 56))? as u64;
+
+        let mut segments = Vec::new();
+        let mut program_headers = None;
+
+        for index in 0..phnum
+        {
+            let header = reader.entry(phoff, index, phentsize)?;
+            let kind = reader.u32(header)?;
+
+            let offset = reader.word(header + reader.pick(4, 8))?;
+            let address = match space
+                {
+                    AddressSpace::Physical => reader.word(header + reader.pick(12, 24))?,
+                    AddressSpace::Virtual  => reader.word(header + reader.pick(8, 16))?
+                };
+            let file_size = reader.word(header + reader.pick(16, 32))?;
+            let memory_size = reader.word(header + reader.pick(20, 40))?;
+
+            if kind == PT_PHDR
+            {
+                program_headers = Some(address);
+            }
+
+            if kind != PT_LOAD
+            {
+                continue;
+            }
+
+            if file_size > memory_size
+            {
+                return Err(invalid("segment larger in the file than in memory"));
+            }
+
+            let fits = address.checked_add(memory_size)
+                              .is_some_and(|end| address >= memory.start && end <= memory.end);
+
+            if !fits
+            {
+                return Err(invalid("segment outside of memory"));
+            }
+
+            if program_headers.is_none() && phoff >= offset && phoff - offset < file_size
+            {
+                program_headers = Some(address + (phoff - offset));
+            }
+
+            let data = reader.bytes(offset, file_size)?.to_vec();
+
+            segments.push(Segment { address, data, memory_size });
+        }
+
+        Ok(( segments, program_headers ))
+    }
+
+
+    // Only named code and data symbols are kept, stripped executables simply have none.
+    fn parse_symbols(reader: &Reader) -> Result<Vec<Symbol>, Error>
+    {
+        let shoff = reader.word(reader.pick(32, 40))?;
+        let shentsize = reader.u16(reader.pick(46, 58))? as u64;
+        let shnum = reader.u16(reader.pick(48, 60))? as u64;
+
+        let section = |index: u64| reader.entry(shoff, index, shentsize);
+
+        let mut symbols = Vec::new();
+
+        for index in 0..shnum
+        {
+            let header = section(index)?;
+
+            if reader.u32(header + 4)? != SHT_SYMTAB
+            {
+                continue;
+            }
+
+            let offset = reader.word(header + reader.pick(16, 24))?;
+            let size = reader.word(header + reader.pick(20, 32))?;
+            let link = reader.u32(header + reader.pick(24, 40))? as u64;
+            let entry_size = reader.word(header + reader.pick(36, 56))?;
+
+            let strings = reader.word(section(link)? + reader.pick(16, 24))?;
+
+            if entry_size == 0
+            {
+                return Err(invalid("bad symbol table"));
+            }
+
+            for index in 0..size / entry_size
+            {
+                let symbol = reader.entry(offset, index, entry_size)?;
+                let name = reader.u32(symbol)? as u64;
+                let info = reader.u8(symbol + reader.pick(12, 4))?;
+                let shndx = reader.u16(symbol + reader.pick(14, 6))?;
+                let address = reader.word(symbol + reader.pick(4, 8))?;
+                let size = reader.word(symbol + reader.pick(8, 16))?;
+
+                let kind = info & 0b_1111;
+
+                if    name == 0
+                   || shndx == SHN_UNDEF
+                   || !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+                {
+                    continue;
+                }
+
+                symbols.push(Symbol { name: reader.string(strings, name)?, address, size });
+            }
+        }
+
+        symbols.sort_by_key(|symbol| symbol.address);
+        Ok(symbols)
+    }
+
+
+    // The first address past every segment, where a heap can start.
+    pub fn end(&self) -> u64
+    {
+        self.segments.iter()
+                     .map(|segment| segment.address.saturating_add(segment.memory_size))
+                     .max()
+                     .unwrap_or(0)
+    }
+
+
+    // Copy the segments into memory.  The space past each segment's file data is zeroed, that's
+    // where .bss lives.  Parsing has already checked that it's no bigger than memory.
+    pub fn load(&self, bus: &mut Bus) -> Result<(), Error>
+    {
+        for segment in &self.segments
+        {
+            let zeros = vec![ 0; (segment.memory_size as usize) - segment.data.len() ];
+            let bss = segment.address + segment.data.len() as u64;
+
+            if !bus.load(segment.address, &segment.data) || !bus.load(bss, &zeros)
+            {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                                      format!("The segment at {:#x} doesn't fit in memory.",
+                                              segment.address)));
+            }
+        }
+
+        Ok(())
+    }
+
+
+    pub fn symbol(&self, name: &str) -> Option<u64>
+    {
+        self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.address)
+    }
+
+
+    // Name an address as the closest symbol at or below it, along with the offset into it.
+    pub fn symbol_at(&self, address: u64) -> Option<( &str, u64 )>
+    {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols[..index].last()?;
+        let offset = address - symbol.address;
+
+        if symbol.size == 0 || offset < symbol.size
+        {
+            Some(( symbol.name.as_str(), offset ))
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    const MEMORY: Range<u64> = 0x_8000_0000..0x_8010_0000;
+
+    const ET_DYN: u16 = 3;
+    const EM_X86_64: u16 = 62;
+
+    const EHDR_SIZE: usize = 64;
+    const PHDR_SIZE: usize = 56;
+
+
+    // An ELF64 executable with a single PT_LOAD segment, cut off at the given length.
+    #[derive(Copy, Clone)]
+    struct Image
+    {
+        class: u8,
+        kind: u16,
+        machine: u16,
+        offset: u64,
+        virtual_address: u64,
+        physical_address: u64,
+        file_size: u64,
+        memory_size: u64,
+        length: usize
+    }
+
+
+    const VALID: Image = Image { class: ELFCLASS64,
+                                 kind: ET_EXEC,
+                                 machine: EM_RISCV,
+                                 offset: 0x_200,
+                                 virtual_address: 0x_1_0000,
+                                 physical_address: 0x_8000_0000,
+                                 file_size: 0x_100,
+                                 memory_size: 0x_1000,
+                                 length: 0x_300 };
+
+
+    impl Image
+    {
+        fn build(&self) -> Vec<u8>
+        {
+            let mut file = vec![ 0; 0x_300 ];
+            let mut put = |offset: usize, bytes: &[ u8 ]|
+                {
+                    file[offset..offset + bytes.len()].copy_from_slice(bytes)
+                };
+
+            put(0, &ELF_MAGIC);
+            put(4, &[ self.class, ELFDATA2LSB, 1 ]);
+            put(16, &self.kind.to_le_bytes());
+            put(18, &self.machine.to_le_bytes());
+            put(24, &self.physical_address.to_le_bytes());
+            put(32, &(EHDR_SIZE as u64).to_le_bytes());
+            put(54, &(PHDR_SIZE as u16).to_le_bytes());
+            put(56, &1_u16.to_le_bytes());
+
+            put(EHDR_SIZE, &PT_LOAD.to_le_bytes());
+            put(EHDR_SIZE + 8, &self.offset.to_le_bytes());
+            put(EHDR_SIZE + 16, &self.virtual_address.to_le_bytes());
+            put(EHDR_SIZE + 24, &self.physical_address.to_le_bytes());
+            put(EHDR_SIZE + 32, &self.file_size.to_le_bytes());
+            put(EHDR_SIZE + 40, &self.memory_size.to_le_bytes());
+
+            file.truncate(self.length);
+            file
+        }
+    }
+
+
+    // Each image, and where its segment goes or why it's refused.
+    #[test]
+    fn parse()
+    {
+        let table =
+            [
+                ( VALID, AddressSpace::Physical, Ok(0x_8000_0000) ),
+                ( Image { virtual_address: 0x_8000_2000, ..VALID },
+                  AddressSpace::Virtual, Ok(0x_8000_2000) ),
+
+                // Loaded as a process, the virtual address has to be in memory.
+                ( VALID, AddressSpace::Virtual, Err("segment outside of memory") ),
+
+                ( Image { length: 10, ..VALID }, AddressSpace::Physical,
+                  Err("missing magic number") ),
+                ( Image { length: 40, ..VALID }, AddressSpace::Physical,
+                  Err("truncated") ),
+                ( Image { length: EHDR_SIZE + 20, ..VALID }, AddressSpace::Physical,
+                  Err("truncated") ),
+
+                ( Image { class: 3, ..VALID }, AddressSpace::Physical,
+                  Err("unknown class") ),
+                ( Image { kind: ET_DYN, ..VALID }, AddressSpace::Physical,
+                  Err("not an executable") ),
+                ( Image { machine: EM_X86_64, ..VALID }, AddressSpace::Physical,
+                  Err("not a RISC-V executable") ),
+
+                // The segment's data has to be in the file.
+                ( Image { offset: 0x_280, ..VALID }, AddressSpace::Physical,
+                  Err("truncated") ),
+                ( Image { offset: u64::MAX - 0x_80, ..VALID }, AddressSpace::Physical,
+                  Err("truncated") ),
+
+                ( Image { memory_size: 0x_80, ..VALID }, AddressSpace::Physical,
+                  Err("segment larger in the file than in memory") ),
+
+                // The whole of the segment has to be in memory.
+                ( Image { physical_address: 0x_7fff_f000, ..VALID }, AddressSpace::Physical,
+                  Err("segment outside of memory") ),
+                ( Image { physical_address: 0x_800f_f800, ..VALID }, AddressSpace::Physical,
+                  Err("segment outside of memory") ),
+                ( Image { physical_address: 0x_800f_f000, ..VALID }, AddressSpace::Physical,
+                  Ok(0x_800f_f000) ),
+                ( Image { physical_address: u64::MAX - 0x_800, ..VALID }, AddressSpace::Physical,
+                  Err("segment outside of memory") )
+            ];
+
+        for ( index, &( image, space, expected ) ) in table.iter().enumerate()
+        {
+            let parsed = Elf::parse(&image.build(), space, MEMORY);
+
+            let result = parsed.map(|elf| elf.segments[0].address)
+                               .map_err(|error| error.to_string());
+
+            let expected = expected.map_err(|message| format!("Invalid ELF file: {}.", message));
+
+            assert_eq!(result, expected, "row {}", index);
+        }
+    }
+
+
+    // The segment keeps its file data, to be zero filled up to its memory size when loaded.
+    #[test]
+    fn segment()
+    {
+        let mut file = VALID.build();
+
+        file[0x_200..0x_300].iter_mut().enumerate().for_each(|( index, byte )| *byte = index as u8);
+
+        let elf = Elf::parse(&file, AddressSpace::Physical, MEMORY).unwrap();
+        let segment = &elf.segments[0];
+
+        assert_eq!(elf.entry, 0x_8000_0000);
+        assert_eq!(segment.data, &file[0x_200..0x_300]);
+        assert_eq!(segment.memory_size, 0x_1000);
+        assert_eq!(elf.program_headers, None);
+        assert!(elf.symbols.is_empty());
+    }
+}
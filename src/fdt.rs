@@ -0,0 +1,196 @@
+
+// A writer for flattened device tree blobs, version 17.  Nodes and their properties are added
+// depth first, and the blob is put together by finish.
+
+
+const FDT_MAGIC:             u32 = 0x_d00d_feed;
+const FDT_VERSION:           u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE:   u32 = 2;
+const FDT_PROP:       u32 = 3;
+const FDT_END:        u32 = 9;
+
+const FDT_HEADER_SIZE: usize = 40;
+
+// The memory reservation block is empty, just its terminating entry.
+const FDT_RESERVATION_SIZE: usize = 16;
+
+
+
+pub struct Fdt
+{
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    depth: usize
+}
+
+
+impl Fdt
+{
+    pub fn new() -> Self
+    {
+        Self { structure: Vec::new(), strings: Vec::new(), depth: 0 }
+    }
+
+
+    // The root node's name is empty.
+    pub fn begin_node(&mut self, name: &str)
+    {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+
+        self.depth += 1;
+    }
+
+
+    pub fn end_node(&mut self)
+    {
+        assert!(self.depth > 0, "Ending a device tree node that was never begun.");
+
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+
+    pub fn property(&mut self, name: &str, value: &[ u8 ])
+    {
+        let name_offset = self.string_offset(name);
+
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+
+
+    pub fn property_empty(&mut self, name: &str)
+    {
+        self.property(name, &[]);
+    }
+
+
+    pub fn property_u32(&mut self, name: &str, value: u32)
+    {
+        self.property_cells(name, &[ value ]);
+    }
+
+
+    pub fn property_cells(&mut self, name: &str, cells: &[ u32 ])
+    {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+
+    // 64-bit values, each as a pair of cells.
+    pub fn property_u64s(&mut self, name: &str, values: &[ u64 ])
+    {
+        let value: Vec<u8> = values.iter().flat_map(|value| value.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+
+    pub fn property_string(&mut self, name: &str, value: &str)
+    {
+        self.property_strings(name, &[ value ]);
+    }
+
+
+    pub fn property_strings(&mut self, name: &str, values: &[ &str ])
+    {
+        let mut value = Vec::new();
+
+        for string in values
+        {
+            value.extend_from_slice(string.as_bytes());
+            value.push(0);
+        }
+
+        self.property(name, &value);
+    }
+
+
+    pub fn finish(mut self) -> Vec<u8>
+    {
+        assert!(self.depth == 0, "Device tree nodes were left open.");
+
+        self.push_u32(FDT_END);
+
+        let structure_offset = FDT_HEADER_SIZE + FDT_RESERVATION_SIZE;
+        let strings_offset = structure_offset + self.structure.len();
+        let total_size = strings_offset + self.strings.len();
+
+        let header = [ FDT_MAGIC,
+                       total_size as u32,
+                       structure_offset as u32,
+                       strings_offset as u32,
+                       FDT_HEADER_SIZE as u32,
+                       FDT_VERSION,
+                       FDT_LAST_COMP_VERSION,
+                       0,
+                       self.strings.len() as u32,
+                       self.structure.len() as u32 ];
+
+        let mut blob: Vec<u8> = header.iter().flat_map(|field| field.to_be_bytes()).collect();
+
+        blob.extend_from_slice(&[ 0; FDT_RESERVATION_SIZE ]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+
+        blob
+    }
+
+
+    fn push_u32(&mut self, value: u32)
+    {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+
+    // Everything in the structure block is aligned to 4 bytes.
+    fn pad(&mut self)
+    {
+        while !self.structure.len().is_multiple_of(4)
+        {
+            self.structure.push(0);
+        }
+    }
+
+
+    // Property names are stored once each in the strings block.
+    fn string_offset(&mut self, name: &str) -> u32
+    {
+        let mut offset = 0;
+
+        for string in self.strings.split(|&byte| byte == 0)
+        {
+            if string == name.as_bytes() && offset < self.strings.len()
+            {
+                return offset as u32;
+            }
+
+            offset += string.len() + 1;
+        }
+
+        let offset = self.strings.len();
+
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+
+        offset as u32
+    }
+}
+
+
+impl Default for Fdt
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
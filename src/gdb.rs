@@ -0,0 +1,796 @@
+
+// A stub for GDB's remote serial protocol, so a debugger can drive the hart.  The debugger connects
+// over TCP or a Unix socket and the hart waits for it, stopped, until told to step or continue.
+// Breakpoints are kept here and checked against the pc before each instruction, and watchpoints
+// are checked by the hart's loads and stores.
+//
+// Registers are numbered the way GDB numbers them for RISC-V: the integer registers, the pc, the
+// floating-point registers, then each CSR at 65 plus its address, and the privilege level last.
+
+
+use std::{ fs,
+           io::{ self, Error, Read, Write },
+           net::{ TcpListener, TcpStream },
+           os::unix::net::{ UnixListener, UnixStream },
+           str };
+use crate::cpu::{ Cpu, Exception, PrivilegeLvel, Watchpoint, WatchKind, CSR_FFLAGS, CSR_FRM,
+                  CSR_FCSR, CSR_NAMES, REGISTER_NAMES, FP_REGISTER_NAMES };
+
+
+// Register numbers.
+const REG_PC:   usize = 32;
+const REG_F0:   usize = 33;
+const REG_CSR0: usize = 65;
+const REG_PRIV: usize = REG_CSR0 + 4096;
+
+const REGISTER_SIZE: usize = 8;
+
+// Signals reported when the hart stops.
+const SIGINT:  u8 = 2;
+const SIGILL:  u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS:  u8 = 7;
+const SIGSEGV: u8 = 11;
+
+// Typing ^C in the debugger sends this outside of any packet.
+const INTERRUPT: u8 = 0x_03;
+
+// While running, the connection is checked for an interrupt this often, in instructions.
+const INTERRUPT_POLL_INTERVAL: u64 = 0x_1_0000;
+
+const PACKET_SIZE: usize = 0x_1000;
+
+// Breakpoint and watchpoint types in Z and z packets.
+const Z_SOFTWARE_BREAKPOINT: u8 = b'0';
+const Z_HARDWARE_BREAKPOINT: u8 = b'1';
+const Z_WRITE_WATCHPOINT:    u8 = b'2';
+const Z_READ_WATCHPOINT:     u8 = b'3';
+const Z_ACCESS_WATCHPOINT:   u8 = b'4';
+
+const REPLY_OK:    &str = "OK";
+const REPLY_ERROR: &str = "E01";
+
+const TARGET_XML_QUERY: &str = "qXfer:features:read:target.xml:";
+const NO_ACK_MODE:      &str = "QStartNoAckMode";
+
+
+
+trait Connection: Read + Write
+{
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+
+impl Connection for TcpStream
+{
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>
+    {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+
+impl Connection for UnixStream
+{
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>
+    {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stop
+{
+    Signal(u8),
+    Breakpoint,
+    Watch(Watchpoint)
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State
+{
+    Stopped(Stop),
+    Stepping,
+    Running,
+
+    // The debugger has gone, the hart runs on by itself.
+    Detached
+}
+
+
+// What to do about a packet from the debugger.
+enum Action
+{
+    Reply(String),
+    Resume(State),
+    Detach,
+    Kill
+}
+
+
+pub struct Gdb
+{
+    connection: Box<dyn Connection>,
+    acknowledge: bool,
+    state: State,
+    breakpoints: Vec<usize>,
+    instructions: u64
+}
+
+
+// The signal a program on a real machine would get for an exception it couldn't handle.
+fn signal(exception: Exception) -> u8
+{
+    match exception
+    {
+        Exception::Breakpoint(_)                   => SIGTRAP,
+        Exception::IllegalInstruction(_)           => SIGILL,
+        Exception::InstructionAddressMisaligned(_) |
+        Exception::LoadAddressMisaligned(_)        |
+        Exception::StoreAddressMisaligned(_)       => SIGBUS,
+        _                                          => SIGSEGV
+    }
+}
+
+
+fn checksum(data: &[ u8 ]) -> u8
+{
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+
+fn to_hex(bytes: &[ u8 ]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+fn from_hex(text: &[ u8 ]) -> Option<Vec<u8>>
+{
+    if !text.len().is_multiple_of(2)
+    {
+        return None;
+    }
+
+    text.chunks(2).map(|pair| u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+
+fn parse_hex(text: &[ u8 ]) -> Option<u64>
+{
+    u64::from_str_radix(str::from_utf8(text).ok()?, 16).ok()
+}
+
+
+// Two hex numbers separated by a comma, like the address and length of a memory access.
+fn parse_pair(text: &[ u8 ]) -> Option<( u64, u64 )>
+{
+    let comma = text.iter().position(|&byte| byte == b',')?;
+
+    Some(( parse_hex(&text[..comma])?, parse_hex(&text[comma + 1..])? ))
+}
+
+
+// The register layout, in GDB's target description format.
+fn target_xml() -> String
+{
+    let register = |name: &str, number: usize, kind: &str|
+        {
+            format!("<reg name=\"{}\" bitsize=\"64\" regnum=\"{}\" type=\"{}\"/>\n",
+                    name,
+                    number,
+                    kind)
+        };
+
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n\
+                                <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+                                <target version=\"1.0\">\n\
+                                <architecture>riscv:rv64</architecture>\n");
+
+    xml += "<feature name=\"org.gnu.gdb.riscv.cpu\">\n";
+
+    for ( number, name ) in REGISTER_NAMES.iter().enumerate()
+    {
+        let kind = match *name
+            {
+                "ra" => "code_ptr",
+                "sp" => "data_ptr",
+                _    => "int"
+            };
+
+        xml += &register(name, number, kind);
+    }
+
+    xml += &register("pc", REG_PC, "code_ptr");
+    xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.fpu\">\n";
+
+    for ( number, name ) in FP_REGISTER_NAMES.iter().enumerate()
+    {
+        xml += &register(name, REG_F0 + number, "ieee_double");
+    }
+
+    let float_csr = |csr: &usize| matches!(*csr, CSR_FFLAGS | CSR_FRM | CSR_FCSR);
+
+    for ( csr, name ) in CSR_NAMES.iter().filter(|( csr, _ )| float_csr(csr))
+    {
+        xml += &register(name, REG_CSR0 + csr, "int");
+    }
+
+    xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.csr\">\n";
+
+    for ( csr, name ) in CSR_NAMES.iter().filter(|( csr, _ )| !float_csr(csr))
+    {
+        xml += &register(name, REG_CSR0 + csr, "int");
+    }
+
+    xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.virtual\">\n";
+    xml += &register("priv", REG_PRIV, "int");
+    xml += "</feature>\n</target>\n";
+
+    xml
+}
+
+
+
+impl Gdb
+{
+    // Wait for the debugger to connect, on a TCP port on this machine when the address is a
+    // number and on a Unix socket otherwise.
+    pub fn connect(address: &str) -> Result<Self, Error>
+    {
+        eprintln!("Waiting for GDB to connect on {}.", address);
+
+        let connection: Box<dyn Connection> = match address.parse::<u16>()
+            {
+                Ok(port) => Box::new(TcpListener::bind(( "127.0.0.1", port ))?.accept()?.0),
+                Err(_)   =>
+                    {
+                        // The socket's name is only needed until the debugger has connected.
+                        let stream = UnixListener::bind(address)?.accept()?.0;
+
+                        fs::remove_file(address)?;
+                        Box::new(stream)
+                    }
+            };
+
+        Ok(Self
+            {
+                connection,
+                acknowledge: true,
+                state: State::Stopped(Stop::Signal(SIGTRAP)),
+                breakpoints: Vec::new(),
+                instructions: 0
+            })
+    }
+
+
+    // Called before each instruction.  Stops the hart once it has made its single step, touched a
+    // watchpoint, reached a breakpoint or been interrupted, and then lets the debugger have its
+    // way until it resumes the hart.  False when the debugger has killed the program.
+    pub fn update(&mut self, cpu: &mut Cpu) -> bool
+    {
+        let state = self.state;
+
+        let stop = match state
+            {
+                State::Stopped(_) | State::Detached =>
+                    None,
+
+                _ if cpu.watch_hit.is_some() =>
+                    cpu.watch_hit.take().map(Stop::Watch),
+
+                State::Stepping =>
+                    Some(Stop::Signal(SIGTRAP)),
+
+                State::Running if self.breakpoints.contains(&cpu.pc) =>
+                    Some(Stop::Breakpoint),
+
+                State::Running if self.interrupted(cpu) =>
+                    Some(Stop::Signal(SIGINT)),
+
+                State::Running =>
+                    None
+            };
+
+        if let Some(stop) = stop
+        {
+            self.stop(cpu, stop);
+        }
+
+        match self.state
+        {
+            State::Stopped(_) => self.serve(cpu),
+            _                 => true
+        }
+    }
+
+
+    // An exception the program can't handle stops it with a signal, as it would under a debugger
+    // on a real machine, instead of ending it.  False when there's no debugger to stop for.
+    pub fn trapped(&mut self, cpu: &mut Cpu, exception: Exception) -> bool
+    {
+        if self.state == State::Detached
+        {
+            return false;
+        }
+
+        self.stop(cpu, Stop::Signal(signal(exception)));
+        self.state != State::Detached
+    }
+
+
+    pub fn exited(&mut self, status: i32)
+    {
+        if self.state != State::Detached
+        {
+            let _ = self.send(&format!("W{:02x}", status as u8));
+        }
+    }
+
+
+    fn stop(&mut self, cpu: &mut Cpu, stop: Stop)
+    {
+        self.state = State::Stopped(stop);
+
+        if self.send(&self.stop_reply()).is_none()
+        {
+            self.detach(cpu);
+        }
+    }
+
+
+    fn stop_reply(&self) -> String
+    {
+        match self.state
+        {
+            State::Stopped(Stop::Breakpoint) =>
+                format!("T{:02x}swbreak:;", SIGTRAP),
+
+            State::Stopped(Stop::Watch(watchpoint)) =>
+                {
+                    let kind = match watchpoint.kind
+                        {
+                            WatchKind::Write  => "watch",
+                            WatchKind::Read   => "rwatch",
+                            WatchKind::Access => "awatch"
+                        };
+
+                    format!("T{:02x}{}:{:x};", SIGTRAP, kind, watchpoint.address)
+                },
+
+            State::Stopped(Stop::Signal(signal)) =>
+                format!("S{:02x}", signal),
+
+            _ =>
+                format!("S{:02x}", SIGTRAP)
+        }
+    }
+
+
+    // The debugger's breakpoints and watchpoints go with it.
+    fn detach(&mut self, cpu: &mut Cpu)
+    {
+        self.state = State::Detached;
+        self.breakpoints.clear();
+
+        cpu.watchpoints.clear();
+        cpu.watch_hit = None;
+    }
+
+
+    // Now and then, look for the debugger interrupting the running hart.  A closed connection
+    // detaches it.
+    fn interrupted(&mut self, cpu: &mut Cpu) -> bool
+    {
+        self.instructions += 1;
+
+        if !self.instructions.is_multiple_of(INTERRUPT_POLL_INTERVAL)
+        {
+            return false;
+        }
+
+        let mut byte = [ 0 ];
+
+        let _ = self.connection.set_nonblocking(true);
+        let result = self.connection.read(&mut byte);
+        let _ = self.connection.set_nonblocking(false);
+
+        match result
+        {
+            Ok(0)  => { self.detach(cpu); false },
+            Ok(_)  => byte[0] == INTERRUPT,
+            Err(_) => false
+        }
+    }
+
+
+    // Answer packets until the debugger resumes the hart.
+    fn serve(&mut self, cpu: &mut Cpu) -> bool
+    {
+        loop
+        {
+            let packet = match self.receive()
+                {
+                    Some(packet) => packet,
+                    None         => { self.detach(cpu); return true; }
+                };
+
+            match self.handle(cpu, &packet)
+            {
+                Action::Reply(reply) =>
+                    {
+                        if self.send(&reply).is_none()
+                        {
+                            self.detach(cpu);
+                            return true;
+                        }
+
+                        // Acknowledgements stop once the reply to the request has gone.
+                        if packet == NO_ACK_MODE.as_bytes()
+                        {
+                            self.acknowledge = false;
+                        }
+                    },
+
+                Action::Resume(state) =>
+                    {
+                        self.state = state;
+                        return true;
+                    },
+
+                Action::Detach =>
+                    {
+                        let _ = self.send(REPLY_OK);
+                        self.detach(cpu);
+                        return true;
+                    },
+
+                Action::Kill =>
+                    {
+                        self.detach(cpu);
+                        return false;
+                    }
+            }
+        }
+    }
+
+
+    fn read_byte(&mut self) -> Option<u8>
+    {
+        let mut byte = [ 0 ];
+
+        match self.connection.read(&mut byte)
+        {
+            Ok(1) => Some(byte[0]),
+            _     => None
+        }
+    }
+
+
+    // The next packet's data, with escaped bytes restored, or None once the connection has gone.
+    // Anything outside a packet, like acknowledgements or a stray interrupt, is skipped.
+    fn receive(&mut self) -> Option<Vec<u8>>
+    {
+        loop
+        {
+            while self.read_byte()? != b'$'
+            {
+            }
+
+            let mut raw = Vec::new();
+
+            loop
+            {
+                match self.read_byte()?
+                {
+                    b'#' => break,
+                    byte => raw.push(byte)
+                }
+            }
+
+            let sum = [ self.read_byte()?, self.read_byte()? ];
+            let valid = parse_hex(&sum) == Some(checksum(&raw) as u64);
+
+            if self.acknowledge
+            {
+                self.connection.write_all(if valid { b"+" } else { b"-" }).ok()?;
+            }
+
+            if valid || !self.acknowledge
+            {
+                let mut data = Vec::with_capacity(raw.len());
+                let mut bytes = raw.into_iter();
+
+                while let Some(byte) = bytes.next()
+                {
+                    match byte
+                    {
+                        b'}' => data.push(bytes.next()? ^ 0x_20),
+                        _    => data.push(byte)
+                    }
+                }
+
+                return Some(data);
+            }
+        }
+    }
+
+
+    // Send a packet, again for as long as the debugger asks for it again.
+    fn send(&mut self, data: &str) -> Option<()>
+    {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+
+        loop
+        {
+            self.connection.write_all(packet.as_bytes()).ok()?;
+            self.connection.flush().ok()?;
+
+            if !self.acknowledge || self.read_byte()? != b'-'
+            {
+                return Some(());
+            }
+        }
+    }
+
+
+    fn handle(&mut self, cpu: &mut Cpu, packet: &[ u8 ]) -> Action
+    {
+        let ( command, arguments ) = match packet.split_first()
+            {
+                Some(( command, arguments )) => ( *command, arguments ),
+                None                         => return Action::Reply(String::new())
+            };
+
+        let reply = match command
+            {
+                b'?' =>
+                    Some(self.stop_reply()),
+
+                b'g' =>
+                    {
+                        let mut bytes = Vec::new();
+
+                        for number in 0..=REG_PC
+                        {
+                            bytes.extend(read_register(cpu, number).unwrap_or(0).to_le_bytes());
+                        }
+
+                        Some(to_hex(&bytes))
+                    },
+
+                b'G' =>
+                    from_hex(arguments).map(|bytes|
+                        {
+                            for ( number, value ) in bytes.chunks_exact(REGISTER_SIZE).enumerate()
+                            {
+                                let value = u64::from_le_bytes([ value[0], value[1], value[2],
+                                                                 value[3], value[4], value[5],
+                                                                 value[6], value[7] ]);
+
+                                write_register(cpu, number, value);
+                            }
+
+                            REPLY_OK.to_string()
+                        }),
+
+                b'p' =>
+                    parse_hex(arguments).and_then(|number| read_register(cpu, number as usize))
+                                        .map(|value| to_hex(&value.to_le_bytes())),
+
+                b'P' =>
+                    {
+                        let equals = arguments.iter().position(|&byte| byte == b'=');
+
+                        equals.and_then(|equals|
+                            {
+                                let number = parse_hex(&arguments[..equals])? as usize;
+                                let mut bytes = [ 0; REGISTER_SIZE ];
+                                let value = from_hex(&arguments[equals + 1..])?;
+
+                                bytes.get_mut(..value.len())?.copy_from_slice(&value);
+                                write_register(cpu, number, u64::from_le_bytes(bytes))
+                            })
+                            .map(|_| REPLY_OK.to_string())
+                    },
+
+                b'm' =>
+                    parse_pair(arguments).and_then(|( address, length )|
+                        {
+                            let mut bytes = vec![ 0; (length as usize).min(PACKET_SIZE / 2) ];
+
+                            if cpu.read_debug(address as usize, &mut bytes)
+                            {
+                                Some(to_hex(&bytes))
+                            }
+                            else
+                            {
+                                None
+                            }
+                        }),
+
+                b'M' =>
+                    {
+                        let colon = arguments.iter().position(|&byte| byte == b':');
+
+                        colon.and_then(|colon|
+                            {
+                                let ( address, length ) = parse_pair(&arguments[..colon])?;
+                                let bytes = from_hex(&arguments[colon + 1..])?;
+
+                                if    bytes.len() as u64 == length
+                                   && cpu.write_debug(address as usize, &bytes)
+                                {
+                                    Some(REPLY_OK.to_string())
+                                }
+                                else
+                                {
+                                    None
+                                }
+                            })
+                    },
+
+                // Either resumes where it stopped, or at the address given.
+                b'c' | b's' =>
+                    {
+                        if let Some(address) = parse_hex(arguments)
+                        {
+                            cpu.pc = address as usize;
+                        }
+
+                        return Action::Resume(if command == b'c'
+                            {
+                                State::Running
+                            }
+                            else
+                            {
+                                State::Stepping
+                            });
+                    },
+
+                b'Z' | b'z' =>
+                    return self.breakpoint(cpu, command == b'Z', arguments),
+
+                b'D' =>
+                    return Action::Detach,
+
+                b'k' =>
+                    return Action::Kill,
+
+                // There's only the one thread.
+                b'H' | b'T' =>
+                    Some(REPLY_OK.to_string()),
+
+                b'q' | b'Q' =>
+                    return Action::Reply(query(&String::from_utf8_lossy(packet))),
+
+                // Anything else isn't supported, which the debugger takes from an empty reply.
+                _ =>
+                    return Action::Reply(String::new())
+            };
+
+        Action::Reply(reply.unwrap_or_else(|| REPLY_ERROR.to_string()))
+    }
+
+
+    // Insert or remove a breakpoint or watchpoint, given its type, address and length.
+    fn breakpoint(&mut self, cpu: &mut Cpu, insert: bool, arguments: &[ u8 ]) -> Action
+    {
+        let end = arguments.iter().position(|&byte| byte == b';').unwrap_or(arguments.len());
+
+        let ( address, length ) = match arguments.get(2..end).and_then(parse_pair)
+            {
+                Some(( address, length )) => ( address as usize, length as usize ),
+                None                      => return Action::Reply(REPLY_ERROR.to_string())
+            };
+
+        let kind = match arguments[0]
+            {
+                Z_SOFTWARE_BREAKPOINT | Z_HARDWARE_BREAKPOINT =>
+                    {
+                        self.breakpoints.retain(|&breakpoint| breakpoint != address);
+
+                        if insert
+                        {
+                            self.breakpoints.push(address);
+                        }
+
+                        return Action::Reply(REPLY_OK.to_string());
+                    },
+
+                Z_WRITE_WATCHPOINT  => WatchKind::Write,
+                Z_READ_WATCHPOINT   => WatchKind::Read,
+                Z_ACCESS_WATCHPOINT => WatchKind::Access,
+                _                   => return Action::Reply(String::new())
+            };
+
+        let watchpoint = Watchpoint { kind, address, length };
+
+        cpu.watchpoints.retain(|watch| *watch != watchpoint);
+
+        if insert
+        {
+            cpu.watchpoints.push(watchpoint);
+        }
+
+        Action::Reply(REPLY_OK.to_string())
+    }
+}
+
+
+fn read_register(cpu: &mut Cpu, number: usize) -> Option<u64>
+{
+    match number
+    {
+        0                  => Some(0),
+        1..=31             => Some(cpu.regs[number - 1]),
+        REG_PC             => Some(cpu.pc as u64),
+        REG_F0..REG_CSR0   => Some(cpu.fregs[number - REG_F0]),
+        REG_CSR0..REG_PRIV => cpu.read_csr_debug(number - REG_CSR0),
+        REG_PRIV           => Some(cpu.privilege as u64),
+        _                  => None
+    }
+}
+
+
+fn write_register(cpu: &mut Cpu, number: usize, value: u64) -> Option<()>
+{
+    match number
+    {
+        0                  => {},
+        1..=31             => cpu.regs[number - 1] = value,
+        REG_PC             => cpu.pc = value as usize,
+        REG_F0..REG_CSR0   => cpu.fregs[number - REG_F0] = value,
+        REG_CSR0..REG_PRIV => cpu.write_csr_debug(number - REG_CSR0, value)?,
+        REG_PRIV           => cpu.privilege = PrivilegeLvel::from_bits(value),
+        _                  => return None
+    }
+
+    Some(())
+}
+
+
+// The general queries and settings.  Only the one thread is ever reported.
+fn query(packet: &str) -> String
+{
+    if packet.starts_with("qSupported")
+    {
+        return format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;{}+",
+                       PACKET_SIZE,
+                       NO_ACK_MODE);
+    }
+
+    if let Some(range) = packet.strip_prefix(TARGET_XML_QUERY)
+    {
+        let xml = target_xml();
+
+        return match parse_pair(range.as_bytes())
+            {
+                Some(( offset, length )) =>
+                    {
+                        let start = (offset as usize).min(xml.len());
+                        let end = start.saturating_add(length as usize).min(xml.len());
+                        let more = if end < xml.len() { 'm' } else { 'l' };
+
+                        format!("{}{}", more, &xml[start..end])
+                    },
+
+                None =>
+                    REPLY_ERROR.to_string()
+            };
+    }
+
+    let reply = match packet
+        {
+            NO_ACK_MODE    => REPLY_OK,
+            "qAttached"    => "1",
+            "qC"           => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _              => ""
+        };
+
+    reply.to_string()
+}
@@ -0,0 +1,142 @@
+
+// The host-target interface that Spike and the riscv-tests use.  The program talks to the host
+// through two words in its own memory, found by their symbols: it writes a command to tohost, and
+// the host clears it and answers in fromhost.  A command names a device, a command for it, and a
+// 48-bit payload.  Device 0 carries system calls, or the exit status when the payload's low bit is
+// set, and device 1 is a console.
+
+
+use std::{ cell::RefCell, io::{ self, Write }, rc::Rc };
+use crate::{ cpu::Cpu, devices::Uart, elf::Elf, newlib::{ Newlib, Syscall } };
+
+
+const TOHOST_SYMBOL:   &str = "tohost";
+const FROMHOST_SYMBOL: &str = "fromhost";
+
+const HTIF_DEVICE_SHIFT:  u64 = 56;
+const HTIF_COMMAND_SHIFT: u64 = 48;
+const HTIF_PAYLOAD_MASK:  u64 = (1 << HTIF_COMMAND_SHIFT) - 1;
+
+const HTIF_DEVICE_SYSCALL: u64 = 0;
+const HTIF_DEVICE_CONSOLE: u64 = 1;
+
+const HTIF_CONSOLE_GETCHAR: u64 = 0;
+const HTIF_CONSOLE_PUTCHAR: u64 = 1;
+
+// A system call is passed as the address of a block of words: the call number and then its
+// arguments, with the result written back over the number.
+const HTIF_SYSCALL_WORDS: usize = 5;
+const HTIF_SYSCALL_DONE:  u64 = 1;
+
+
+
+pub struct Htif
+{
+    tohost: u64,
+    fromhost: Option<u64>,
+
+    // The console answers a read once there's something to read.
+    uart: Rc<RefCell<Uart>>,
+    pending_read: bool
+}
+
+
+fn read_u64(cpu: &mut Cpu, address: u64) -> Option<u64>
+{
+    let mut bytes = [ 0; 8 ];
+
+    if cpu.bus.read(address, &mut bytes) { Some(u64::from_le_bytes(bytes)) } else { None }
+}
+
+
+impl Htif
+{
+    // Only a program with a tohost can use the interface.  Console input is shared with the UART.
+    pub fn new(elf: &Elf, uart: Rc<RefCell<Uart>>) -> Option<Self>
+    {
+        let tohost = elf.symbol(TOHOST_SYMBOL)?;
+
+        Some(Self { tohost, fromhost: elf.symbol(FROMHOST_SYMBOL), uart, pending_read: false })
+    }
+
+
+    // Look for a command in tohost, carrying it out if there is one.  Gives the exit status once
+    // the program has asked to exit.
+    pub fn update(&mut self, cpu: &mut Cpu, newlib: &mut Newlib) -> Option<i32>
+    {
+        if self.pending_read
+        {
+            if let Some(byte) = self.uart.borrow_mut().take_input()
+            {
+                self.pending_read = false;
+                self.respond(cpu, HTIF_DEVICE_CONSOLE, HTIF_CONSOLE_GETCHAR, byte as u64);
+            }
+        }
+
+        let command = match read_u64(cpu, self.tohost)
+            {
+                Some(0) | None => return None,
+                Some(command)  => command
+            };
+
+        cpu.bus.write(self.tohost, &[ 0; 8 ]);
+
+        let device = command >> HTIF_DEVICE_SHIFT;
+        let code = (command >> HTIF_COMMAND_SHIFT) & 0x_ff;
+        let payload = command & HTIF_PAYLOAD_MASK;
+
+        match ( device, code )
+        {
+            ( HTIF_DEVICE_SYSCALL, _ ) if payload & 1 != 0 =>
+                return Some((payload >> 1) as i32),
+
+            ( HTIF_DEVICE_SYSCALL, _ ) =>
+                {
+                    let mut words = [ 0; HTIF_SYSCALL_WORDS ];
+
+                    for ( index, word ) in words.iter_mut().enumerate()
+                    {
+                        *word = read_u64(cpu, payload + index as u64 * 8).unwrap_or(0);
+                    }
+
+                    let args = [ words[1], words[2], words[3], words[4] ];
+
+                    match newlib.syscall(cpu, words[0], args)
+                    {
+                        Syscall::Exited(status)   => return Some(status),
+                        Syscall::Returned(result) =>
+                            { cpu.bus.write(payload, &result.to_le_bytes()); }
+                    }
+
+                    self.respond(cpu, device, code, HTIF_SYSCALL_DONE);
+                },
+
+            // Writes to the console aren't answered.
+            ( HTIF_DEVICE_CONSOLE, HTIF_CONSOLE_PUTCHAR ) =>
+                {
+                    let mut stdout = io::stdout();
+                    let _ = stdout.write_all(&[ payload as u8 ]).and_then(|_| stdout.flush());
+                },
+
+            ( HTIF_DEVICE_CONSOLE, HTIF_CONSOLE_GETCHAR ) =>
+                self.pending_read = true,
+
+            // Unknown devices and commands are dropped.
+            _ =>
+                {}
+        }
+
+        None
+    }
+
+
+    fn respond(&self, cpu: &mut Cpu, device: u64, code: u64, payload: u64)
+    {
+        if let Some(fromhost) = self.fromhost
+        {
+            let response = (device << HTIF_DEVICE_SHIFT) | (code << HTIF_COMMAND_SHIFT) | payload;
+
+            cpu.bus.write(fromhost, &response.to_le_bytes());
+        }
+    }
+}
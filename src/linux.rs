@@ -0,0 +1,1487 @@
+
+// User-mode emulation of Linux for statically linked riscv64 executables, in the way qemu-user
+// does it.  The program runs in user mode on flat memory, with no kernel underneath: its system
+// calls are carried out on the host here.  Memory is laid out with the program at the bottom, the
+// heap growing up from its end, the stack at the top, and mappings growing down from below the
+// stack.  Files are only visible from under a root directory on the host, which the guest sees as
+// its /.
+
+
+use std::{ collections::hash_map::RandomState,
+           env,
+           fs::{ self, File },
+           hash::{ BuildHasher, Hasher },
+           io::{ self, Error, ErrorKind, Read, Seek, SeekFrom, Write },
+           ops::Range,
+           path::{ Path, PathBuf },
+           process,
+           thread,
+           time::{ Duration, Instant, SystemTime } };
+use crate::{ bus::Bus,
+             cpu::{ misa_extension, Cpu, PrivilegeLvel, CSR_MCOUNTEREN, PAGE_SIZE },
+             elf::Elf,
+             newlib::{ self, errno, put_u64, read_path, timestamp } };
+
+
+// Executables are linked to start at 64K, the page at zero is left out to catch null pointers.
+pub const LINUX_MEMORY_BASE: u64 = 0x_1_0000;
+
+const STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+// Call numbers, in a7.
+const SYS_GETCWD            : u64 = 17;
+const SYS_DUP               : u64 = 23;
+const SYS_DUP3              : u64 = 24;
+const SYS_FCNTL             : u64 = 25;
+const SYS_IOCTL             : u64 = 29;
+const SYS_MKDIRAT           : u64 = 34;
+const SYS_UNLINKAT          : u64 = 35;
+const SYS_FACCESSAT         : u64 = 48;
+const SYS_CHDIR             : u64 = 49;
+const SYS_OPENAT            : u64 = 56;
+const SYS_CLOSE             : u64 = 57;
+const SYS_GETDENTS64        : u64 = 61;
+const SYS_LSEEK             : u64 = 62;
+const SYS_READ              : u64 = 63;
+const SYS_WRITE             : u64 = 64;
+const SYS_READV             : u64 = 65;
+const SYS_WRITEV            : u64 = 66;
+const SYS_PREAD64           : u64 = 67;
+const SYS_PWRITE64          : u64 = 68;
+const SYS_READLINKAT        : u64 = 78;
+const SYS_NEWFSTATAT        : u64 = 79;
+const SYS_FSTAT             : u64 = 80;
+const SYS_EXIT              : u64 = 93;
+const SYS_EXIT_GROUP        : u64 = 94;
+const SYS_SET_TID_ADDRESS   : u64 = 96;
+const SYS_FUTEX             : u64 = 98;
+const SYS_SET_ROBUST_LIST   : u64 = 99;
+const SYS_NANOSLEEP         : u64 = 101;
+const SYS_CLOCK_GETTIME     : u64 = 113;
+const SYS_CLOCK_GETRES      : u64 = 114;
+const SYS_CLOCK_NANOSLEEP   : u64 = 115;
+const SYS_SCHED_YIELD       : u64 = 124;
+const SYS_KILL              : u64 = 129;
+const SYS_TKILL             : u64 = 130;
+const SYS_TGKILL            : u64 = 131;
+const SYS_RT_SIGACTION      : u64 = 134;
+const SYS_RT_SIGPROCMASK    : u64 = 135;
+const SYS_UNAME             : u64 = 160;
+const SYS_GETTIMEOFDAY      : u64 = 169;
+const SYS_GETPID            : u64 = 172;
+const SYS_GETPPID           : u64 = 173;
+const SYS_GETUID            : u64 = 174;
+const SYS_GETEUID           : u64 = 175;
+const SYS_GETGID            : u64 = 176;
+const SYS_GETEGID           : u64 = 177;
+const SYS_GETTID            : u64 = 178;
+const SYS_BRK               : u64 = 214;
+const SYS_MUNMAP            : u64 = 215;
+const SYS_MREMAP            : u64 = 216;
+const SYS_MMAP              : u64 = 222;
+const SYS_MPROTECT          : u64 = 226;
+const SYS_MADVISE           : u64 = 233;
+const SYS_RISCV_FLUSH_ICACHE: u64 = 259;
+const SYS_PRLIMIT64         : u64 = 261;
+const SYS_GETRANDOM         : u64 = 278;
+
+// Error numbers, beyond those newlib shares.
+const ENOENT:  i64 = 2;
+const EAGAIN:  i64 = 11;
+const ENOMEM:  i64 = 12;
+const EACCES:  i64 = 13;
+const ENOTDIR: i64 = 20;
+const ENOTTY:  i64 = 25;
+const ERANGE:  i64 = 34;
+
+const O_DIRECTORY: u64 = 0o_200000;
+
+const AT_SYMLINK_NOFOLLOW: u64 = 0x_100;
+const AT_REMOVEDIR:        u64 = 0x_200;
+const AT_EMPTY_PATH:       u64 = 0x_1000;
+
+const F_DUPFD:         u64 = 0;
+const F_DUPFD_CLOEXEC: u64 = 1030;
+
+const FUTEX_WAIT:     u64 = 0;
+const FUTEX_WAKE:     u64 = 1;
+const FUTEX_CMD_MASK: u64 = 0x_7f;
+
+const CLOCK_REALTIME: u64 = 0;
+const TIMER_ABSTIME:  u64 = 1;
+
+const MAP_FIXED:     u64 = 0x_10;
+const MAP_ANONYMOUS: u64 = 0x_20;
+
+const RLIMIT_STACK:  u64 = 3;
+const RLIM_INFINITY: u64 = u64::MAX;
+
+// The kernel's struct sigaction and sigset_t.
+const SIGACTION_SIZE: usize = 24;
+const SIGSET_SIZE:    usize = 8;
+
+// Killed by a signal, as a shell would report it.
+const SIGNAL_EXIT_STATUS: i32 = 128;
+
+// Each field of struct utsname is this long, NUL included.
+const UTSNAME_FIELD: usize = 65;
+const UTSNAME: [ &str; 6 ] = [ "Linux", "riscv", "6.1.0", "#1", "riscv64", "(none)" ];
+
+// Entry types for getdents64.
+const DT_UNKNOWN: u8 = 0;
+const DT_DIR:     u8 = 4;
+const DT_REG:     u8 = 8;
+const DT_LNK:     u8 = 10;
+
+// The fixed part of struct linux_dirent64, before the name.
+const DIRENT_HEADER: usize = 19;
+
+// Auxiliary vector entry types.
+const AT_NULL:   u64 = 0;
+const AT_PHDR:   u64 = 3;
+const AT_PHENT:  u64 = 4;
+const AT_PHNUM:  u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY:  u64 = 9;
+const AT_UID:    u64 = 11;
+const AT_EUID:   u64 = 12;
+const AT_GID:    u64 = 13;
+const AT_EGID:   u64 = 14;
+const AT_HWCAP:  u64 = 16;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+// A bit for each single letter extension, as in misa but without the privilege modes.
+const HWCAP: u64 = misa_extension(b'A')
+                   | misa_extension(b'C')
+                   | misa_extension(b'D')
+                   | misa_extension(b'F')
+                   | misa_extension(b'I')
+                   | misa_extension(b'M');
+const CLOCK_TICKS: u64 = 100;
+
+const RANDOM_SIZE: usize = 16;
+
+const PROC_SELF_EXE: &str = "/proc/self/exe";
+
+// Longer reads and writes are cut short, which the caller has to allow for anyway.
+const MAX_TRANSFER: u64 = 1024 * 1024;
+
+// Indices of the registers used by the calling convention.
+const REG_SP: usize = 2;
+const REG_A0: usize = 10;
+const REG_A7: usize = 17;
+
+
+
+enum Handle
+{
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+
+    // Directories are read by listing them again, from where the last read left off.
+    Directory { host: PathBuf, guest: String, position: usize }
+}
+
+
+impl Handle
+{
+    fn duplicate(&self) -> Result<Handle, Error>
+    {
+        let duplicate = match self
+            {
+                Handle::Stdin      => Handle::Stdin,
+                Handle::Stdout     => Handle::Stdout,
+                Handle::Stderr     => Handle::Stderr,
+                Handle::File(file) => Handle::File(file.try_clone()?),
+
+                Handle::Directory { host, guest, position } =>
+                    Handle::Directory { host: host.clone(),
+                                        guest: guest.clone(),
+                                        position: *position }
+            };
+
+        Ok(duplicate)
+    }
+}
+
+
+pub struct Linux
+{
+    // The host directory that is the guest's /, and the guest's working directory within it.
+    root: PathBuf,
+    cwd: String,
+
+    // Indexed by the guest's file descriptor, closed ones are left empty for reuse.
+    files: Vec<Option<Handle>>,
+
+    // What /proc/self/exe points at.
+    executable: String,
+
+    // The heap runs up from the end of the program, mappings run down from below the stack.
+    memory_size: u64,
+    heap_start: u64,
+    brk: u64,
+    mmap_bottom: u64,
+    stack_bottom: u64,
+
+    started: Instant
+}
+
+
+// Bytes from the host's hasher, seeded randomly for each process.
+fn random_bytes(count: usize) -> Vec<u8>
+{
+    let state = RandomState::new();
+
+    (0..count.div_ceil(8)).flat_map(|index|
+                              {
+                                  let mut hasher = state.build_hasher();
+                                  hasher.write_usize(index);
+                                  hasher.finish().to_le_bytes()
+                              })
+                          .take(count)
+                          .collect()
+}
+
+
+// Round up to a whole number of pages, as long as that doesn't overflow.
+fn page_align(value: u64) -> Option<u64>
+{
+    value.checked_next_multiple_of(PAGE_SIZE as u64)
+}
+
+
+// Join a path onto a directory, both in the guest's view, leaving just the names.  There's nothing
+// above /, so .. stops there.
+fn join_guest_path(directory: &str, path: &str) -> String
+{
+    let mut names: Vec<&str> = Vec::new();
+    let start = if path.starts_with('/') { "" } else { directory };
+
+    for name in start.split('/').chain(path.split('/'))
+    {
+        match name
+        {
+            "" | "." => {},
+            ".."     => { names.pop(); },
+            _        => names.push(name)
+        }
+    }
+
+    format!("/{}", names.join("/"))
+}
+
+
+// Push bytes onto the stack as it's being set up, giving where they went.
+fn push(cpu: &mut Cpu, sp: &mut u64, bytes: &[ u8 ]) -> u64
+{
+    *sp -= bytes.len() as u64;
+    cpu.bus.write(*sp, bytes);
+    *sp
+}
+
+
+fn push_string(cpu: &mut Cpu, sp: &mut u64, string: &str) -> u64
+{
+    push(cpu, sp, &[ 0 ]);
+    push(cpu, sp, string.as_bytes())
+}
+
+
+fn write_guest(cpu: &mut Cpu, address: u64, bytes: &[ u8 ]) -> Result<(), i64>
+{
+    if cpu.bus.write(address, bytes) { Ok(()) } else { Err(-newlib::EFAULT) }
+}
+
+
+fn read_guest(cpu: &mut Cpu, address: u64, length: u64) -> Result<Vec<u8>, i64>
+{
+    let mut bytes = vec![ 0; length as usize ];
+
+    if cpu.bus.read(address, &mut bytes) { Ok(bytes) } else { Err(-newlib::EFAULT) }
+}
+
+
+fn read_u64(cpu: &mut Cpu, address: u64) -> Result<u64, i64>
+{
+    let mut bytes = [ 0; 8 ];
+
+    if !cpu.bus.read(address, &mut bytes)
+    {
+        return Err(-newlib::EFAULT);
+    }
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+
+// A struct timespec.
+fn read_timespec(cpu: &mut Cpu, address: u64) -> Result<Duration, i64>
+{
+    let seconds = read_u64(cpu, address)?;
+    let nanoseconds = read_u64(cpu, address + 8)?;
+
+    if nanoseconds >= 1_000_000_000
+    {
+        return Err(-newlib::EINVAL);
+    }
+
+    Ok(Duration::new(seconds, nanoseconds as u32))
+}
+
+
+fn write_timespec(cpu: &mut Cpu, address: u64, time: Duration) -> Result<(), i64>
+{
+    let mut timespec = [ 0; 16 ];
+
+    put_u64(&mut timespec, 0, time.as_secs());
+    put_u64(&mut timespec, 8, time.subsec_nanos() as u64);
+
+    write_guest(cpu, address, &timespec)
+}
+
+
+
+impl Linux
+{
+    // The root has to exist, the guest's paths are checked against where it really is.
+    pub fn new(root: &Path, memory_size: u64) -> Result<Self, Error>
+    {
+        Ok(Self { root: fs::canonicalize(root)?,
+                  cwd: String::from("/"),
+                  files: vec![ Some(Handle::Stdin), Some(Handle::Stdout), Some(Handle::Stderr) ],
+                  executable: String::new(),
+                  memory_size,
+                  heap_start: 0,
+                  brk: 0,
+                  mmap_bottom: 0,
+                  stack_bottom: 0,
+                  started: Instant::now() })
+    }
+
+
+    // Where the program's memory is, in its address space.
+    pub fn memory(&self) -> Range<u64>
+    {
+        LINUX_MEMORY_BASE..LINUX_MEMORY_BASE + self.memory_size
+    }
+
+
+    // Memory for the program, starting where executables are linked.
+    pub fn bus(&self) -> Result<Bus, Error>
+    {
+        let mut bus = Bus::new();
+
+        bus.add_ram(LINUX_MEMORY_BASE, self.memory_size)?;
+        Ok(bus)
+    }
+
+
+    // Load the program and set up its stack the way the kernel's exec does, then drop to user mode
+    // at its entry point.  The program sees the host's environment.
+    pub fn start(&mut self, cpu: &mut Cpu, elf: &Elf, args: &[ String ]) -> Result<(), Error>
+    {
+        elf.load(&mut cpu.bus)?;
+
+        let memory_top = LINUX_MEMORY_BASE + self.memory_size;
+
+        self.heap_start = page_align(elf.end()).unwrap_or(u64::MAX);
+        self.brk = self.heap_start;
+        self.executable = args.first().cloned().unwrap_or_default();
+
+        if self.memory_size < STACK_SIZE || self.heap_start >= memory_top - STACK_SIZE
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, "The program doesn't fit in memory."));
+        }
+
+        self.stack_bottom = memory_top - STACK_SIZE;
+        self.mmap_bottom = self.stack_bottom;
+
+        let environment: Vec<String> = env::vars().map(|( name, value )| name + "=" + &value)
+                                                  .collect();
+
+        let mut sp = memory_top;
+
+        let execfn = push_string(cpu, &mut sp, &self.executable);
+        let argv: Vec<u64> = args.iter().map(|arg| push_string(cpu, &mut sp, arg)).collect();
+        let envp: Vec<u64> = environment.iter()
+                                        .map(|variable| push_string(cpu, &mut sp, variable))
+                                        .collect();
+        let random = push(cpu, &mut sp, &random_bytes(RANDOM_SIZE));
+
+        let auxv = [ ( AT_PHDR,   elf.program_headers.unwrap_or(0) ),
+                     ( AT_PHENT,  elf.program_header_size ),
+                     ( AT_PHNUM,  elf.program_header_count ),
+                     ( AT_PAGESZ, PAGE_SIZE as u64 ),
+                     ( AT_ENTRY,  elf.entry ),
+                     ( AT_UID,    0 ),
+                     ( AT_EUID,   0 ),
+                     ( AT_GID,    0 ),
+                     ( AT_EGID,   0 ),
+                     ( AT_HWCAP,  HWCAP ),
+                     ( AT_CLKTCK, CLOCK_TICKS ),
+                     ( AT_SECURE, 0 ),
+                     ( AT_RANDOM, random ),
+                     ( AT_EXECFN, execfn ),
+                     ( AT_NULL,   0 ) ];
+
+        // argc, then argv and envp each ending with a null, then the auxiliary vector.
+        let mut words = vec![ argv.len() as u64 ];
+
+        words.extend(argv.iter().chain(&[ 0 ]));
+        words.extend(envp.iter().chain(&[ 0 ]));
+        words.extend(auxv.iter().flat_map(|&( kind, value )| [ kind, value ]));
+
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let sp = (sp - bytes.len() as u64) & !0b_1111;
+
+        if sp < self.stack_bottom + PAGE_SIZE as u64 || !cpu.bus.write(sp, &bytes)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "The arguments don't fit on the stack."));
+        }
+
+        cpu.write_csr(CSR_MCOUNTEREN, 0b_111);
+        cpu.privilege = PrivilegeLvel::User;
+        cpu.pc = elf.entry as usize;
+        cpu.regs[REG_SP - 1] = sp;
+
+        Ok(())
+    }
+
+
+    // Carry out the system call the hart has stopped at, leaving the pc past the ecall.  Gives the
+    // exit status once the program has exited.
+    pub fn call(&mut self, cpu: &mut Cpu) -> Option<i32>
+    {
+        let number = cpu.regs[REG_A7 - 1];
+        let args = [ 0, 1, 2, 3, 4, 5 ].map(|index| cpu.regs[REG_A0 - 1 + index]);
+
+        cpu.pc += 4;
+
+        let result = match number
+            {
+                SYS_EXIT | SYS_EXIT_GROUP =>
+                    return Some(args[0] as i32),
+
+                // Any signal sent to the only process there is kills it.
+                SYS_KILL | SYS_TKILL if args[1] != 0 =>
+                    return Some(SIGNAL_EXIT_STATUS + args[1] as i32),
+
+                SYS_TGKILL if args[2] != 0 =>
+                    return Some(SIGNAL_EXIT_STATUS + args[2] as i32),
+
+                SYS_GETCWD           => self.getcwd(cpu, args[0], args[1]),
+                SYS_DUP              => self.dup(args[0], 0),
+                SYS_DUP3             => self.dup3(args[0], args[1]),
+                SYS_FCNTL            => self.fcntl(args[0], args[1], args[2]),
+                SYS_IOCTL            => self.ioctl(args[0]),
+                SYS_MKDIRAT          => self.mkdirat(cpu, args[0], args[1]),
+                SYS_UNLINKAT         => self.unlinkat(cpu, args[0], args[1], args[2]),
+                SYS_FACCESSAT        => self.faccessat(cpu, args[0], args[1]),
+                SYS_CHDIR            => self.chdir(cpu, args[0]),
+                SYS_OPENAT           => self.openat(cpu, args[0], args[1], args[2]),
+                SYS_CLOSE            => self.close(args[0]),
+                SYS_GETDENTS64       => self.getdents64(cpu, args[0], args[1], args[2]),
+                SYS_LSEEK            => self.lseek(args[0], args[1] as i64, args[2]),
+                SYS_READ             => self.read(cpu, args[0], args[1], args[2]),
+                SYS_WRITE            => self.write(cpu, args[0], args[1], args[2]),
+                SYS_READV            => self.vectored(cpu, args[0], args[1], args[2], Linux::read),
+                SYS_WRITEV           => self.vectored(cpu, args[0], args[1], args[2], Linux::write),
+                SYS_PREAD64          => self.positioned(cpu, args, Linux::read),
+                SYS_PWRITE64         => self.positioned(cpu, args, Linux::write),
+                SYS_READLINKAT       => self.readlinkat(cpu, args[0], args[1], args[2], args[3]),
+                SYS_NEWFSTATAT       => self.newfstatat(cpu, args[0], args[1], args[2], args[3]),
+                SYS_FSTAT            => self.fstat(cpu, args[0], args[1]),
+                SYS_NANOSLEEP        => self.clock_nanosleep(cpu, 0, 0, args[0]),
+                SYS_CLOCK_NANOSLEEP  => self.clock_nanosleep(cpu, args[0], args[1], args[2]),
+                SYS_CLOCK_GETTIME    => self.clock_gettime(cpu, args[0], args[1]),
+                SYS_CLOCK_GETRES     => Linux::clock_getres(cpu, args[1]),
+                SYS_GETTIMEOFDAY     => Linux::gettimeofday(cpu, args[0]),
+                SYS_FUTEX            => Linux::futex(cpu, args[0], args[1]),
+                SYS_RT_SIGACTION     => Linux::zero(cpu, args[2], SIGACTION_SIZE),
+                SYS_RT_SIGPROCMASK   => Linux::zero(cpu, args[2], SIGSET_SIZE),
+                SYS_UNAME            => Linux::uname(cpu, args[0]),
+                SYS_PRLIMIT64        => Linux::prlimit64(cpu, args[1], args[3]),
+                SYS_GETRANDOM        => Linux::getrandom(cpu, args[0], args[1]),
+                SYS_BRK              => self.brk(cpu, args[0]) as i64,
+                SYS_MMAP             => self.mmap(cpu, args),
+                SYS_MUNMAP           => self.munmap(args[0], args[1]),
+
+                SYS_SET_TID_ADDRESS | SYS_GETPID | SYS_GETTID =>
+                    process::id() as i64,
+
+                // There's only the one process and one user.
+                SYS_GETPPID | SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID |
+                SYS_KILL | SYS_TKILL | SYS_TGKILL =>
+                    0,
+
+                // Memory is always readable, writable and executable, and fetched fresh each time.
+                SYS_MPROTECT | SYS_MADVISE | SYS_RISCV_FLUSH_ICACHE |
+                SYS_SET_ROBUST_LIST | SYS_SCHED_YIELD =>
+                    0,
+
+                // Making a mapping bigger where it is isn't supported, so the C library falls back
+                // to a new one.
+                SYS_MREMAP =>
+                    -ENOMEM,
+
+                _ =>
+                    -newlib::ENOSYS
+            };
+
+        cpu.regs[REG_A0 - 1] = result as u64;
+
+        None
+    }
+
+
+    fn handle(&mut self, fd: u64) -> Option<&mut Handle>
+    {
+        self.files.get_mut(fd as usize).and_then(|handle| handle.as_mut())
+    }
+
+
+    // The lowest free descriptor, at or above the one given.
+    fn insert(&mut self, handle: Handle, lowest: u64) -> i64
+    {
+        let lowest = lowest as usize;
+
+        if self.files.len() < lowest
+        {
+            self.files.resize_with(lowest, || None);
+        }
+
+        match self.files[lowest..].iter().position(|handle| handle.is_none())
+        {
+            Some(index) => { self.files[lowest + index] = Some(handle); (lowest + index) as i64 },
+            None        => { self.files.push(Some(handle)); self.files.len() as i64 - 1 }
+        }
+    }
+
+
+    // Where a guest path is on the host, relative to a directory descriptor.  Symbolic links could
+    // still lead out of the root, so where the path exists, or its parent does, it's checked.
+    fn resolve(&mut self,
+               cpu: &mut Cpu,
+               directory: u64,
+               path: u64) -> Result<( String, PathBuf ), i64>
+    {
+        let path = read_path(cpu, path).ok_or(-newlib::EFAULT)?;
+
+        let base = if path.starts_with('/') || directory as i64 == newlib::AT_FDCWD
+            {
+                self.cwd.clone()
+            }
+            else
+            {
+                match self.handle(directory)
+                {
+                    Some(Handle::Directory { guest, .. }) => guest.clone(),
+                    Some(_)                               => return Err(-ENOTDIR),
+                    None                                  => return Err(-newlib::EBADF)
+                }
+            };
+
+        let guest = join_guest_path(&base, &path);
+        let host = self.root.join(&guest[1..]);
+
+        let real = fs::canonicalize(&host).or_else(|error| match host.parent()
+            {
+                Some(parent) => fs::canonicalize(parent),
+                None         => Err(error)
+            });
+
+        match real
+        {
+            Ok(real) if !real.starts_with(&self.root) => Err(-EACCES),
+            _                                         => Ok(( guest, host ))
+        }
+    }
+
+
+    fn getcwd(&mut self, cpu: &mut Cpu, address: u64, size: u64) -> i64
+    {
+        let mut cwd = self.cwd.clone().into_bytes();
+
+        cwd.push(0);
+
+        if (cwd.len() as u64) > size
+        {
+            return -ERANGE;
+        }
+
+        write_guest(cpu, address, &cwd).map_or_else(|error| error, |_| cwd.len() as i64)
+    }
+
+
+    fn dup(&mut self, fd: u64, lowest: u64) -> i64
+    {
+        let duplicate = match self.handle(fd).map(|handle| handle.duplicate())
+            {
+                Some(Ok(duplicate)) => duplicate,
+                Some(Err(error))    => return errno(&error),
+                None                => return -newlib::EBADF
+            };
+
+        self.insert(duplicate, lowest)
+    }
+
+
+    fn dup3(&mut self, fd: u64, target: u64) -> i64
+    {
+        if fd == target
+        {
+            return -newlib::EINVAL;
+        }
+
+        let duplicate = match self.handle(fd).map(|handle| handle.duplicate())
+            {
+                Some(Ok(duplicate)) => duplicate,
+                Some(Err(error))    => return errno(&error),
+                None                => return -newlib::EBADF
+            };
+
+        if self.files.len() <= target as usize
+        {
+            self.files.resize_with(target as usize + 1, || None);
+        }
+
+        self.files[target as usize] = Some(duplicate);
+        target as i64
+    }
+
+
+    // Only duplication does anything, descriptor and status flags are accepted and ignored.
+    fn fcntl(&mut self, fd: u64, command: u64, argument: u64) -> i64
+    {
+        match command
+        {
+            F_DUPFD | F_DUPFD_CLOEXEC       => self.dup(fd, argument),
+            _ if self.handle(fd).is_none()  => -newlib::EBADF,
+            _                               => 0
+        }
+    }
+
+
+    // Nothing is a terminal, so programs buffer their output fully.
+    fn ioctl(&mut self, fd: u64) -> i64
+    {
+        if self.handle(fd).is_some() { -ENOTTY } else { -newlib::EBADF }
+    }
+
+
+    fn mkdirat(&mut self, cpu: &mut Cpu, directory: u64, path: u64) -> i64
+    {
+        match self.resolve(cpu, directory, path)
+        {
+            Ok(( _, host )) => fs::create_dir(host).map_or_else(|error| errno(&error), |_| 0),
+            Err(error)      => error
+        }
+    }
+
+
+    fn unlinkat(&mut self, cpu: &mut Cpu, directory: u64, path: u64, flags: u64) -> i64
+    {
+        let host = match self.resolve(cpu, directory, path)
+            {
+                Ok(( _, host )) => host,
+                Err(error)      => return error
+            };
+
+        let removed = if flags & AT_REMOVEDIR != 0
+            {
+                fs::remove_dir(host)
+            }
+            else
+            {
+                fs::remove_file(host)
+            };
+
+        removed.map_or_else(|error| errno(&error), |_| 0)
+    }
+
+
+    // Everything that exists can be accessed in every way.
+    fn faccessat(&mut self, cpu: &mut Cpu, directory: u64, path: u64) -> i64
+    {
+        match self.resolve(cpu, directory, path)
+        {
+            Ok(( _, host )) => fs::metadata(host).map_or_else(|error| errno(&error), |_| 0),
+            Err(error)      => error
+        }
+    }
+
+
+    fn chdir(&mut self, cpu: &mut Cpu, path: u64) -> i64
+    {
+        let ( guest, host ) = match self.resolve(cpu, newlib::AT_FDCWD as u64, path)
+            {
+                Ok(resolved) => resolved,
+                Err(error)   => return error
+            };
+
+        match fs::metadata(host)
+        {
+            Ok(metadata) if metadata.is_dir() => { self.cwd = guest; 0 },
+            Ok(_)                             => -ENOTDIR,
+            Err(error)                        => errno(&error)
+        }
+    }
+
+
+    fn openat(&mut self, cpu: &mut Cpu, directory: u64, path: u64, flags: u64) -> i64
+    {
+        let ( guest, host ) = match self.resolve(cpu, directory, path)
+            {
+                Ok(resolved) => resolved,
+                Err(error)   => return error
+            };
+
+        let is_directory = fs::metadata(&host).map(|metadata| metadata.is_dir()).unwrap_or(false);
+
+        let handle = if is_directory
+            {
+                Handle::Directory { host, guest, position: 0 }
+            }
+            else if flags & O_DIRECTORY != 0
+            {
+                return if host.exists() { -ENOTDIR } else { -ENOENT };
+            }
+            else
+            {
+                match newlib::open_options(flags).open(host)
+                {
+                    Ok(file)   => Handle::File(file),
+                    Err(error) => return errno(&error)
+                }
+            };
+
+        self.insert(handle, 0)
+    }
+
+
+    fn close(&mut self, fd: u64) -> i64
+    {
+        match self.files.get_mut(fd as usize)
+        {
+            Some(handle @ Some(_)) => { *handle = None; 0 },
+            _                      => -newlib::EBADF
+        }
+    }
+
+
+    // Each entry is a struct linux_dirent64, padded out to 8 bytes.  The offset of an entry is
+    // just its index in the listing, . and .. included.
+    fn getdents64(&mut self, cpu: &mut Cpu, fd: u64, address: u64, size: u64) -> i64
+    {
+        let ( host, position ) = match self.handle(fd)
+            {
+                Some(Handle::Directory { host, position, .. }) => ( host.clone(), position ),
+                Some(_)                                        => return -ENOTDIR,
+                None                                           => return -newlib::EBADF
+            };
+
+        let listing = match fs::read_dir(&host)
+            {
+                Ok(listing) => listing,
+                Err(error)  => return errno(&error)
+            };
+
+        let entries = vec![ ( String::from("."), DT_DIR ), ( String::from(".."), DT_DIR ) ];
+        let listed = listing.filter_map(|entry| entry.ok()).map(|entry|
+            {
+                let kind = match entry.file_type()
+                    {
+                        Ok(kind) if kind.is_dir()     => DT_DIR,
+                        Ok(kind) if kind.is_file()    => DT_REG,
+                        Ok(kind) if kind.is_symlink() => DT_LNK,
+                        _                             => DT_UNKNOWN
+                    };
+
+                ( entry.file_name().to_string_lossy().into_owned(), kind )
+            });
+
+        let mut buffer = Vec::new();
+        let mut next = *position;
+        let mut full = false;
+
+        for ( name, kind ) in entries.into_iter().chain(listed).skip(*position)
+        {
+            let length = (DIRENT_HEADER + name.len() + 1).next_multiple_of(8);
+
+            if (buffer.len() + length) as u64 > size
+            {
+                full = true;
+                break;
+            }
+
+            let mut entry = vec![ 0; length ];
+
+            put_u64(&mut entry, 0, next as u64 + 1);
+            put_u64(&mut entry, 8, next as u64 + 1);
+            entry[16..18].copy_from_slice(&(length as u16).to_le_bytes());
+            entry[18] = kind;
+            entry[DIRENT_HEADER..DIRENT_HEADER + name.len()].copy_from_slice(name.as_bytes());
+
+            buffer.extend(entry);
+            next += 1;
+        }
+
+        // A buffer too small for even the next entry is an error, rather than the end of the
+        // listing.
+        if buffer.is_empty() && full
+        {
+            return -newlib::EINVAL;
+        }
+
+        *position = next;
+
+        write_guest(cpu, address, &buffer).map_or_else(|error| error, |_| buffer.len() as i64)
+    }
+
+
+    fn lseek(&mut self, fd: u64, offset: i64, whence: u64) -> i64
+    {
+        let position = match whence
+            {
+                newlib::SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+                newlib::SEEK_CUR                => SeekFrom::Current(offset),
+                newlib::SEEK_END                => SeekFrom::End(offset),
+                _                               => return -newlib::EINVAL
+            };
+
+        match self.handle(fd)
+        {
+            Some(Handle::File(file)) =>
+                file.seek(position).map_or_else(|error| errno(&error), |offset| offset as i64),
+
+            // Rewinding a directory starts the listing again.
+            Some(Handle::Directory { position, .. }) if whence == newlib::SEEK_SET && offset == 0 =>
+                { *position = 0; 0 },
+
+            Some(_) =>
+                -newlib::ESPIPE,
+
+            None =>
+                -newlib::EBADF
+        }
+    }
+
+
+    fn read(&mut self, cpu: &mut Cpu, fd: u64, address: u64, length: u64) -> i64
+    {
+        let mut buffer = vec![ 0; length.min(MAX_TRANSFER) as usize ];
+
+        let read = match self.handle(fd)
+            {
+                Some(Handle::File(file)) => file.read(&mut buffer),
+                Some(Handle::Stdin)      => io::stdin().read(&mut buffer),
+                _                        => return -newlib::EBADF
+            };
+
+        let count = match read
+            {
+                Ok(count)  => count,
+                Err(error) => return errno(&error)
+            };
+
+        write_guest(cpu, address, &buffer[..count]).map_or_else(|error| error, |_| count as i64)
+    }
+
+
+    fn write(&mut self, cpu: &mut Cpu, fd: u64, address: u64, length: u64) -> i64
+    {
+        let buffer = match read_guest(cpu, address, length.min(MAX_TRANSFER))
+            {
+                Ok(buffer) => buffer,
+                Err(error) => return error
+            };
+
+        let written = match self.handle(fd)
+            {
+                Some(Handle::File(file)) => file.write(&buffer),
+
+                Some(Handle::Stdout) =>
+                    {
+                        let mut stdout = io::stdout();
+                        stdout.write_all(&buffer).and_then(|_| stdout.flush()).map(|_| buffer.len())
+                    },
+
+                Some(Handle::Stderr) =>
+                    io::stderr().write_all(&buffer).map(|_| buffer.len()),
+
+                _ =>
+                    return -newlib::EBADF
+            };
+
+        written.map_or_else(|error| errno(&error), |count| count as i64)
+    }
+
+
+    // readv and writev, as a read or write for each struct iovec until one comes up short.
+    fn vectored(&mut self,
+                cpu: &mut Cpu,
+                fd: u64,
+                vectors: u64,
+                count: u64,
+                transfer: fn(&mut Linux, &mut Cpu, u64, u64, u64) -> i64) -> i64
+    {
+        let mut total = 0;
+
+        for index in 0..count
+        {
+            let vector = vectors + index * 16;
+
+            let ( address, length ) = match ( read_u64(cpu, vector), read_u64(cpu, vector + 8) )
+                {
+                    ( Ok(address), Ok(length) ) => ( address, length ),
+                    ( Err(error), _ ) |
+                    ( _, Err(error) )           => return error
+                };
+
+            let done = transfer(self, cpu, fd, address, length);
+
+            if done < 0
+            {
+                return if total > 0 { total } else { done };
+            }
+
+            total += done;
+
+            if (done as u64) < length
+            {
+                break;
+            }
+        }
+
+        total
+    }
+
+
+    // pread64 and pwrite64, which leave the file's position where it was.
+    fn positioned(&mut self,
+                  cpu: &mut Cpu,
+                  args: [ u64; 6 ],
+                  transfer: fn(&mut Linux, &mut Cpu, u64, u64, u64) -> i64) -> i64
+    {
+        let [ fd, address, length, offset, .. ] = args;
+
+        let saved = match self.handle(fd)
+            {
+                Some(Handle::File(file)) =>
+                    match file.stream_position().and_then(|saved|
+                              file.seek(SeekFrom::Start(offset)).map(|_| saved))
+                    {
+                        Ok(saved)  => saved,
+                        Err(error) => return errno(&error)
+                    },
+
+                Some(_) => return -newlib::ESPIPE,
+                None    => return -newlib::EBADF
+            };
+
+        let done = transfer(self, cpu, fd, address, length);
+
+        if let Some(Handle::File(file)) = self.handle(fd)
+        {
+            let _ = file.seek(SeekFrom::Start(saved));
+        }
+
+        done
+    }
+
+
+    // The program itself is the only link in /proc.
+    fn readlinkat(&mut self,
+                  cpu: &mut Cpu,
+                  directory: u64,
+                  path: u64,
+                  address: u64,
+                  size: u64) -> i64
+    {
+        let target = match read_path(cpu, path)
+            {
+                Some(path) if path == PROC_SELF_EXE => self.executable.clone(),
+                Some(_) =>
+                    match self.resolve(cpu, directory, path).map(|( _, host )| fs::read_link(host))
+                    {
+                        Ok(Ok(target))   => target.to_string_lossy().into_owned(),
+                        Ok(Err(error))   => return errno(&error),
+                        Err(error)       => return error
+                    },
+                None =>
+                    return -newlib::EFAULT
+            };
+
+        let target = &target.as_bytes()[..target.len().min(size as usize)];
+
+        write_guest(cpu, address, target).map_or_else(|error| error, |_| target.len() as i64)
+    }
+
+
+    fn newfstatat(&mut self,
+                  cpu: &mut Cpu,
+                  directory: u64,
+                  path: u64,
+                  address: u64,
+                  flags: u64) -> i64
+    {
+        if flags & AT_EMPTY_PATH != 0 && read_path(cpu, path).is_some_and(|path| path.is_empty())
+        {
+            return self.fstat(cpu, directory, address);
+        }
+
+        let host = match self.resolve(cpu, directory, path)
+            {
+                Ok(( _, host )) => host,
+                Err(error)      => return error
+            };
+
+        let metadata = if flags & AT_SYMLINK_NOFOLLOW != 0
+            {
+                fs::symlink_metadata(host)
+            }
+            else
+            {
+                fs::metadata(host)
+            };
+
+        match metadata
+        {
+            Ok(metadata) => Linux::write_stat(cpu, address, Some(&metadata)),
+            Err(error)   => errno(&error)
+        }
+    }
+
+
+    fn fstat(&mut self, cpu: &mut Cpu, fd: u64, address: u64) -> i64
+    {
+        let metadata = match self.handle(fd)
+            {
+                Some(Handle::File(file))             => Some(file.metadata()),
+                Some(Handle::Directory { host, .. }) => Some(fs::metadata(host)),
+                Some(_)                              => None,
+                None                                 => return -newlib::EBADF
+            };
+
+        match metadata
+        {
+            Some(Ok(metadata)) => Linux::write_stat(cpu, address, Some(&metadata)),
+            Some(Err(error))   => errno(&error),
+            None               => Linux::write_stat(cpu, address, None)
+        }
+    }
+
+
+    // The kernel's struct stat is the same one newlib's libgloss uses.
+    fn write_stat(cpu: &mut Cpu, address: u64, metadata: Option<&fs::Metadata>) -> i64
+    {
+        write_guest(cpu, address, &newlib::stat(metadata)).map_or_else(|error| error, |_| 0)
+    }
+
+
+    // The realtime clock is the host's, every other clock counts from when the program started.
+    fn clock(&self, clock: u64) -> Duration
+    {
+        if clock == CLOCK_REALTIME
+        {
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+        }
+        else
+        {
+            self.started.elapsed()
+        }
+    }
+
+
+    fn clock_gettime(&self, cpu: &mut Cpu, clock: u64, address: u64) -> i64
+    {
+        write_timespec(cpu, address, self.clock(clock)).map_or_else(|error| error, |_| 0)
+    }
+
+
+    fn clock_getres(cpu: &mut Cpu, address: u64) -> i64
+    {
+        if address == 0
+        {
+            return 0;
+        }
+
+        write_timespec(cpu, address, Duration::from_nanos(1)).map_or_else(|error| error, |_| 0)
+    }
+
+
+    // A sleep is never interrupted, so the remaining time is never needed.
+    fn clock_nanosleep(&self, cpu: &mut Cpu, clock: u64, flags: u64, address: u64) -> i64
+    {
+        let time = match read_timespec(cpu, address)
+            {
+                Ok(time)   => time,
+                Err(error) => return error
+            };
+
+        let duration = if flags & TIMER_ABSTIME != 0
+            {
+                time.saturating_sub(self.clock(clock))
+            }
+            else
+            {
+                time
+            };
+
+        thread::sleep(duration);
+        0
+    }
+
+
+    fn gettimeofday(cpu: &mut Cpu, address: u64) -> i64
+    {
+        let ( seconds, nanoseconds ) = timestamp(Ok(SystemTime::now()));
+        let mut timeval = [ 0; 16 ];
+
+        put_u64(&mut timeval, 0, seconds);
+        put_u64(&mut timeval, 8, nanoseconds / 1000);
+
+        if address == 0
+        {
+            return 0;
+        }
+
+        write_guest(cpu, address, &timeval).map_or_else(|error| error, |_| 0)
+    }
+
+
+    // With a single thread, nothing could ever wake a waiter, so waits return straight away as if
+    // the value had already changed.
+    fn futex(cpu: &mut Cpu, address: u64, operation: u64) -> i64
+    {
+        match operation & FUTEX_CMD_MASK
+        {
+            FUTEX_WAIT =>
+                read_guest(cpu, address, 4).map_or_else(|error| error, |_| -EAGAIN),
+
+            FUTEX_WAKE =>
+                0,
+
+            _ =>
+                -newlib::ENOSYS
+        }
+    }
+
+
+    // Signal handlers and masks are accepted but never used, whatever was set before reads back
+    // as nothing.
+    fn zero(cpu: &mut Cpu, address: u64, size: usize) -> i64
+    {
+        if address == 0
+        {
+            return 0;
+        }
+
+        write_guest(cpu, address, &vec![ 0; size ]).map_or_else(|error| error, |_| 0)
+    }
+
+
+    fn uname(cpu: &mut Cpu, address: u64) -> i64
+    {
+        let mut utsname = vec![ 0; UTSNAME.len() * UTSNAME_FIELD ];
+
+        for ( index, field ) in UTSNAME.iter().enumerate()
+        {
+            let start = index * UTSNAME_FIELD;
+            utsname[start..start + field.len()].copy_from_slice(field.as_bytes());
+        }
+
+        write_guest(cpu, address, &utsname).map_or_else(|error| error, |_| 0)
+    }
+
+
+    // Limits can't be changed, and apart from the stack there aren't any.
+    fn prlimit64(cpu: &mut Cpu, resource: u64, address: u64) -> i64
+    {
+        let limit = if resource == RLIMIT_STACK { STACK_SIZE } else { RLIM_INFINITY };
+        let mut rlimit = [ 0; 16 ];
+
+        put_u64(&mut rlimit, 0, limit);
+        put_u64(&mut rlimit, 8, limit);
+
+        if address == 0
+        {
+            return 0;
+        }
+
+        write_guest(cpu, address, &rlimit).map_or_else(|error| error, |_| 0)
+    }
+
+
+    fn getrandom(cpu: &mut Cpu, address: u64, length: u64) -> i64
+    {
+        let bytes = random_bytes(length.min(MAX_TRANSFER) as usize);
+
+        write_guest(cpu, address, &bytes).map_or_else(|error| error, |_| bytes.len() as i64)
+    }
+
+
+    // A break outside the heap is refused by leaving it where it is.  Memory given back and taken
+    // again has to come back zeroed.
+    fn brk(&mut self, cpu: &mut Cpu, address: u64) -> u64
+    {
+        if address < self.heap_start || address > self.mmap_bottom
+        {
+            return self.brk;
+        }
+
+        if address > self.brk
+        {
+            cpu.bus.write(self.brk, &vec![ 0; (address - self.brk) as usize ]);
+        }
+
+        self.brk = address;
+        self.brk
+    }
+
+
+    // New mappings are placed below the last, fixed ones go where they're asked to.  Files are
+    // copied in, so writes to a shared mapping never reach the file.
+    fn mmap(&mut self, cpu: &mut Cpu, args: [ u64; 6 ]) -> i64
+    {
+        let [ address, length, _, flags, fd, offset ] = args;
+        let memory_top = LINUX_MEMORY_BASE + self.memory_size;
+
+        let length = match page_align(length)
+            {
+                Some(0)      => return -newlib::EINVAL,
+                Some(length) => length,
+                None         => return -ENOMEM
+            };
+
+        // The file is read before any space is set aside, so that a bad descriptor leaves nothing
+        // behind.
+        let mut contents = Vec::new();
+
+        if flags & MAP_ANONYMOUS == 0
+        {
+            let read = match self.handle(fd)
+                {
+                    Some(Handle::File(file)) =>
+                        file.seek(SeekFrom::Start(offset))
+                            .and_then(|_| Read::by_ref(file).take(length)
+                                                            .read_to_end(&mut contents)),
+
+                    _ =>
+                        return -newlib::EBADF
+                };
+
+            if let Err(error) = read
+            {
+                return errno(&error);
+            }
+        }
+
+        let start = if flags & MAP_FIXED != 0
+            {
+                if !address.is_multiple_of(PAGE_SIZE as u64) || address < LINUX_MEMORY_BASE
+                {
+                    return -newlib::EINVAL;
+                }
+
+                match address.checked_add(length)
+                {
+                    Some(end) if end <= memory_top => address,
+                    _                              => return -ENOMEM
+                }
+            }
+            else
+            {
+                match self.mmap_bottom.checked_sub(length)
+                {
+                    Some(start) if start >= self.brk => { self.mmap_bottom = start; start },
+                    _                                => return -ENOMEM
+                }
+            };
+
+        if !cpu.bus.write(start, &vec![ 0; length as usize ])
+        {
+            return -ENOMEM;
+        }
+
+        write_guest(cpu, start, &contents).map_or_else(|error| error, |_| start as i64)
+    }
+
+
+    // Only the most recent mapping can be handed back for reuse, anything else is just forgotten.
+    fn munmap(&mut self, address: u64, length: u64) -> i64
+    {
+        if !address.is_multiple_of(PAGE_SIZE as u64)
+        {
+            return -newlib::EINVAL;
+        }
+
+        if address == self.mmap_bottom
+        {
+            self.mmap_bottom = page_align(length).and_then(|length| address.checked_add(length))
+                                                 .map_or(self.stack_bottom,
+                                                         |end| end.min(self.stack_bottom));
+        }
+
+        0
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    const DIRECTORY_FD: u64 = 3;
+    const FILE_FD:      u64 = 4;
+
+
+    // A root holding /dir/sub and /file, with a directory next to it that the guest can't see.
+    // The guest has /dir open as a directory and /file open as a file.
+    struct Sandbox
+    {
+        top: PathBuf,
+        linux: Linux,
+        cpu: Cpu
+    }
+
+
+    impl Sandbox
+    {
+        fn new(name: &str) -> Self
+        {
+            let top = env::temp_dir().join(format!("riscv-{}-{}", name, process::id()));
+            let root = top.join("root");
+
+            fs::create_dir_all(root.join("dir/sub")).unwrap();
+            fs::create_dir_all(top.join("outside")).unwrap();
+            File::create(root.join("file")).unwrap();
+
+            let mut linux = Linux::new(&root, 0x_1_0000).unwrap();
+            let cpu = Cpu::new(linux.bus().unwrap());
+
+            let directory = Handle::Directory { host: linux.root.join("dir"),
+                                                guest: String::from("/dir"),
+                                                position: 0 };
+            let file = Handle::File(File::open(root.join("file")).unwrap());
+
+            assert_eq!(linux.insert(directory, 0), DIRECTORY_FD as i64);
+            assert_eq!(linux.insert(file, 0), FILE_FD as i64);
+
+            Self { top, linux, cpu }
+        }
+
+
+        // Resolve a path as the guest would pass it, giving its place in the guest's view.
+        fn resolve(&mut self, cwd: &str, directory: u64, path: &str) -> Result<String, i64>
+        {
+            let mut bytes = path.as_bytes().to_vec();
+
+            bytes.push(0);
+            assert!(self.cpu.bus.write(LINUX_MEMORY_BASE, &bytes));
+
+            self.linux.cwd = String::from(cwd);
+
+            let ( guest, host ) = self.linux.resolve(&mut self.cpu,
+                                                     directory,
+                                                     LINUX_MEMORY_BASE)?;
+
+            assert_eq!(host, self.linux.root.join(&guest[1..]), "{}", path);
+            Ok(guest)
+        }
+    }
+
+
+    impl Drop for Sandbox
+    {
+        fn drop(&mut self)
+        {
+            let _ = fs::remove_dir_all(&self.top);
+        }
+    }
+
+
+    #[test]
+    fn resolve()
+    {
+        let mut sandbox = Sandbox::new("resolve");
+        let cwd = newlib::AT_FDCWD as u64;
+
+        let table =
+            [
+                ( "/",    cwd,          "dir/sub",             Ok("/dir/sub") ),
+                ( "/dir", cwd,          "sub",                 Ok("/dir/sub") ),
+                ( "/dir", cwd,          "./sub/../../file",    Ok("/file") ),
+
+                // Absolute paths ignore the working directory, and any directory descriptor.
+                ( "/dir", cwd,          "/file",               Ok("/file") ),
+                ( "/",    DIRECTORY_FD, "/file",               Ok("/file") ),
+                ( "/",    FILE_FD,      "/file",               Ok("/file") ),
+
+                // There's nothing above /.
+                ( "/",    cwd,          "..",                  Ok("/") ),
+                ( "/dir", cwd,          "../../..",            Ok("/") ),
+                ( "/",    cwd,          "/../../etc/passwd",   Ok("/etc/passwd") ),
+                ( "/",    DIRECTORY_FD, "../../file",          Ok("/file") ),
+
+                // Relative paths start from the directory descriptor rather than the working
+                // directory.
+                ( "/",    DIRECTORY_FD, "sub",                 Ok("/dir/sub") ),
+                ( "/dir", DIRECTORY_FD, "sub/..",              Ok("/dir") ),
+                ( "/",    FILE_FD,      "sub",                 Err(-ENOTDIR) ),
+                ( "/",    99,           "sub",                 Err(-newlib::EBADF) )
+            ];
+
+        for &( working, directory, path, expected ) in &table
+        {
+            assert_eq!(sandbox.resolve(working, directory, path),
+                       expected.map(String::from),
+                       "{} {} {}", working, directory as i64, path);
+        }
+    }
+
+
+    // Symbolic links are followed on the host, so one inside the root that leads out of it is
+    // refused, as is anything beneath it.
+    #[cfg(unix)]
+    #[test]
+    fn resolve_symbolic_links()
+    {
+        use std::os::unix::fs::symlink;
+
+        let mut sandbox = Sandbox::new("links");
+        let root = sandbox.linux.root.clone();
+        let cwd = newlib::AT_FDCWD as u64;
+
+        symlink(sandbox.top.join("outside"), root.join("escape")).unwrap();
+        symlink("/", root.join("dir/host")).unwrap();
+        symlink("dir/sub", root.join("inside")).unwrap();
+
+        let table =
+            [
+                ( "/",    cwd,          "/escape",             Err(-EACCES) ),
+                ( "/",    cwd,          "escape/new",          Err(-EACCES) ),
+                ( "/dir", cwd,          "host",                Err(-EACCES) ),
+                ( "/",    DIRECTORY_FD, "host/etc",            Err(-EACCES) ),
+                ( "/",    cwd,          "/inside",             Ok("/inside") ),
+                ( "/",    cwd,          "inside/new",          Ok("/inside/new") )
+            ];
+
+        for &( working, directory, path, expected ) in &table
+        {
+            assert_eq!(sandbox.resolve(working, directory, path),
+                       expected.map(String::from),
+                       "{} {} {}", working, directory as i64, path);
+        }
+    }
+}
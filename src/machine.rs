@@ -0,0 +1,288 @@
+
+// The emulated board, laid out like QEMU's virt machine: a single hart with RAM, a CLINT, a PLIC
+// and a UART.  The machine describes itself to the guest with a generated device tree.
+
+
+use std::{ cell::RefCell, io::{ Error, ErrorKind }, ops::Range, rc::Rc };
+use crate::{ bus::Bus, cpu::Cpu, devices::*, elf::{ AddressSpace, Elf }, fdt::Fdt };
+
+
+// The ISA string reported in the device tree, it has to agree with misa.
+pub const ISA: &str = "rv64imafdc_zicsr_zifencei";
+pub const ISA_EXTENSIONS: [ &str; 8 ] = [ "i", "m", "a", "f", "d", "c", "zicsr", "zifencei" ];
+
+pub const MMU_TYPE: &str = "riscv,sv57";
+
+// Indices of the argument registers the boot protocol uses.
+const REG_A0: usize = 10;
+const REG_A1: usize = 11;
+
+const HART_ID: u64 = 0;
+
+// Device tree handles of the interrupt controllers.
+const PHANDLE_CPU_INTC: u32 = 1;
+const PHANDLE_PLIC:     u32 = 2;
+
+// The interrupts as numbered by the hart's local interrupt controller.
+const IRQ_M_SOFTWARE: u32 = 3;
+const IRQ_M_TIMER:    u32 = 7;
+const IRQ_S_EXTERNAL: u32 = 9;
+const IRQ_M_EXTERNAL: u32 = 11;
+
+// The UART's input clock, which the guest uses to work out its divisor.
+const UART_CLOCK_FREQUENCY: u32 = 3_686_400;
+
+// The device tree goes at the top of RAM, aligned for the kernel.
+const DEVICE_TREE_ALIGNMENT: u64 = 4096;
+
+// Where a 64-bit kernel goes, relative to the start of RAM.  The firmware in front of it jumps
+// here when it's done.
+pub const KERNEL_OFFSET: u64 = 0x_20_0000;
+
+// The initrd goes half way up RAM, well clear of the kernel as it unpacks itself.
+const INITRD_ALIGNMENT: u64 = 4096;
+
+// Firmware starts from a reset vector in ROM, as it does on Spike and QEMU.  The code there loads
+// the device tree's address and the entry point from just after itself.
+const RESET_VECTOR: u64 = 0x_1000;
+
+const RESET_VECTOR_CODE: [ u32; 6 ] =
+    [
+        0x_00000297,  // auipc  t0, 0
+        0x_f1402573,  // csrr   a0, mhartid
+        0x_0202b583,  // ld     a1, 32(t0)
+        0x_0182b283,  // ld     t0, 24(t0)
+        0x_00028067,  // jr     t0
+        0
+    ];
+
+
+
+pub struct Machine
+{
+    pub cpu: Cpu,
+    pub ram_base: u64,
+    pub ram_size: u64,
+
+    // Kept for firmware that runs outside the guest and shares the console with it.
+    pub uart: Rc<RefCell<Uart>>,
+
+    // Passed on to the kernel through /chosen in the device tree.
+    pub bootargs: Option<String>,
+    pub initrd: Option<( u64, u64 )>
+}
+
+
+impl Machine
+{
+    pub fn new(ram_base: u64, ram_size: u64) -> Result<Self, Error>
+    {
+        let mut bus = Bus::new();
+
+        let clint = Rc::new(RefCell::new(Clint::new()));
+        let plic = Rc::new(RefCell::new(Plic::new()));
+        let uart = Rc::new(RefCell::new(Uart::new()));
+
+        plic.borrow_mut().connect(UART_IRQ, uart.clone());
+
+        bus.add_ram(ram_base, ram_size)?;
+        bus.add_device(CLINT_BASE, CLINT_SIZE, Box::new(clint.clone()))?;
+        bus.add_device(PLIC_BASE, PLIC_SIZE, Box::new(plic.clone()))?;
+        bus.add_device(UART_BASE, UART_SIZE, Box::new(uart.clone()))?;
+
+        let mut cpu = Cpu::new(bus);
+
+        cpu.clint = Some(clint);
+        cpu.plic = Some(plic);
+
+        Ok(Self { cpu, ram_base, ram_size, uart, bootargs: None, initrd: None })
+    }
+
+
+    // Where RAM is, in the physical address space.
+    pub fn memory(&self) -> Range<u64>
+    {
+        self.ram_base..self.ram_base + self.ram_size
+    }
+
+
+    // Load an ELF image where its segments say, or a raw one at the given address.  Gives the
+    // image's entry point.
+    pub fn load_image(&mut self, image: &[ u8 ], address: u64) -> Result<u64, Error>
+    {
+        if Elf::is_elf(image)
+        {
+            let elf = Elf::parse(image, AddressSpace::Physical, self.memory())?;
+
+            elf.load(&mut self.cpu.bus)?;
+            return Ok(elf.entry);
+        }
+
+        if !self.cpu.bus.load(address, image)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("The image at {:#x} doesn't fit in memory.", address)));
+        }
+
+        Ok(address)
+    }
+
+
+    pub fn load_initrd(&mut self, initrd: &[ u8 ]) -> Result<(), Error>
+    {
+        let start = (self.ram_base + self.ram_size / 2) & !(INITRD_ALIGNMENT - 1);
+        let end = start + initrd.len() as u64;
+
+        if !self.cpu.bus.load(start, initrd)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, "The initrd doesn't fit in RAM."));
+        }
+
+        self.initrd = Some(( start, end ));
+        Ok(())
+    }
+
+
+    pub fn device_tree(&self) -> Vec<u8>
+    {
+        let mut fdt = Fdt::new();
+
+        fdt.begin_node("");
+        fdt.property_u32("#address-cells", 2);
+        fdt.property_u32("#size-cells", 2);
+        fdt.property_string("compatible", "riscv-virtio");
+        fdt.property_string("model", "riscv-virtio,qemu");
+
+        fdt.begin_node("chosen");
+        fdt.property_string("stdout-path", &format!("/soc/serial@{:x}", UART_BASE));
+
+        if let Some(bootargs) = &self.bootargs
+        {
+            fdt.property_string("bootargs", bootargs);
+        }
+
+        if let Some(( start, end )) = self.initrd
+        {
+            fdt.property_u64s("linux,initrd-start", &[ start ]);
+            fdt.property_u64s("linux,initrd-end", &[ end ]);
+        }
+
+        fdt.end_node();
+
+        fdt.begin_node(&format!("memory@{:x}", self.ram_base));
+        fdt.property_string("device_type", "memory");
+        fdt.property_u64s("reg", &[ self.ram_base, self.ram_size ]);
+        fdt.end_node();
+
+        fdt.begin_node("cpus");
+        fdt.property_u32("#address-cells", 1);
+        fdt.property_u32("#size-cells", 0);
+        fdt.property_u32("timebase-frequency", CLINT_TIMEBASE_FREQUENCY as u32);
+
+        fdt.begin_node(&format!("cpu@{}", HART_ID));
+        fdt.property_string("device_type", "cpu");
+        fdt.property_u32("reg", HART_ID as u32);
+        fdt.property_string("status", "okay");
+        fdt.property_string("compatible", "riscv");
+        fdt.property_string("riscv,isa", ISA);
+        fdt.property_string("riscv,isa-base", "rv64i");
+        fdt.property_strings("riscv,isa-extensions", &ISA_EXTENSIONS);
+        fdt.property_string("mmu-type", MMU_TYPE);
+
+        fdt.begin_node("interrupt-controller");
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_string("compatible", "riscv,cpu-intc");
+        fdt.property_u32("phandle", PHANDLE_CPU_INTC);
+        fdt.end_node();
+
+        fdt.end_node();
+        fdt.end_node();
+
+        fdt.begin_node("soc");
+        fdt.property_u32("#address-cells", 2);
+        fdt.property_u32("#size-cells", 2);
+        fdt.property_string("compatible", "simple-bus");
+        fdt.property_empty("ranges");
+
+        fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+        fdt.property_strings("compatible", &[ "sifive,clint0", "riscv,clint0" ]);
+        fdt.property_u64s("reg", &[ CLINT_BASE, CLINT_SIZE ]);
+        fdt.property_cells("interrupts-extended", &[ PHANDLE_CPU_INTC, IRQ_M_SOFTWARE,
+                                                     PHANDLE_CPU_INTC, IRQ_M_TIMER ]);
+        fdt.end_node();
+
+        // The PLIC's contexts are the hart's machine and supervisor modes, in that order.
+        fdt.begin_node(&format!("plic@{:x}", PLIC_BASE));
+        fdt.property_strings("compatible", &[ "sifive,plic-1.0.0", "riscv,plic0" ]);
+        fdt.property_u64s("reg", &[ PLIC_BASE, PLIC_SIZE ]);
+        fdt.property_u32("#address-cells", 0);
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_u32("riscv,ndev", PLIC_SOURCES as u32 - 1);
+        fdt.property_cells("interrupts-extended", &[ PHANDLE_CPU_INTC, IRQ_M_EXTERNAL,
+                                                     PHANDLE_CPU_INTC, IRQ_S_EXTERNAL ]);
+        fdt.property_u32("phandle", PHANDLE_PLIC);
+        fdt.end_node();
+
+        fdt.begin_node(&format!("serial@{:x}", UART_BASE));
+        fdt.property_string("compatible", "ns16550a");
+        fdt.property_u64s("reg", &[ UART_BASE, UART_SIZE ]);
+        fdt.property_u32("clock-frequency", UART_CLOCK_FREQUENCY);
+        fdt.property_u32("interrupt-parent", PHANDLE_PLIC);
+        fdt.property_u32("interrupts", UART_IRQ as u32);
+        fdt.end_node();
+
+        fdt.end_node();
+        fdt.end_node();
+
+        fdt.finish()
+    }
+
+
+    // Place the device tree at the top of RAM, above the initrd, giving its address.
+    pub fn load_device_tree(&mut self) -> Result<u64, Error>
+    {
+        let device_tree = self.device_tree();
+        let size = device_tree.len() as u64;
+
+        let bottom = self.initrd.map_or(self.ram_base, |( _, end )| end);
+
+        let address = (self.ram_base + self.ram_size).checked_sub(size)
+                                                     .map(|top| top & !(DEVICE_TREE_ALIGNMENT - 1))
+                                                     .filter(|&address| address >= bottom);
+
+        match address
+        {
+            Some(address) if self.cpu.bus.load(address, &device_tree) => Ok(address),
+
+            _ =>
+                Err(Error::new(ErrorKind::InvalidInput, "The device tree doesn't fit in RAM."))
+        }
+    }
+
+
+    // Put the reset vector in ROM, pointed at the entry point and the device tree.  Gives its
+    // address.
+    pub fn add_reset_vector(&mut self, entry: u64, device_tree: u64) -> Result<u64, Error>
+    {
+        let rom = RESET_VECTOR_CODE.iter()
+                                   .flat_map(|word| word.to_le_bytes())
+                                   .chain(entry.to_le_bytes())
+                                   .chain(device_tree.to_le_bytes())
+                                   .collect();
+
+        self.cpu.bus.add_rom(RESET_VECTOR, rom)?;
+        Ok(RESET_VECTOR)
+    }
+
+
+    // Start the hart the way firmware and kernels expect, with the hart id in a0 and the device
+    // tree's address in a1.
+    pub fn reset(&mut self, entry: u64, device_tree: u64)
+    {
+        self.cpu.pc = entry as usize;
+        self.cpu.regs[REG_A0 - 1] = HART_ID;
+        self.cpu.regs[REG_A1 - 1] = device_tree;
+    }
+}
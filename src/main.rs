@@ -1,40 +1,526 @@
 
+#![allow(clippy::unusual_byte_groupings)]
+
 #[allow(unused_imports)]
 #[allow(unused_variables)]
-#[allow(dead_code)]
-
 mod cpu;
+mod bus;
+mod elf;
+mod devices;
+mod fdt;
+mod machine;
+mod sbi;
+mod newlib;
+mod linux;
+mod htif;
+mod semihosting;
+mod gdb;
+mod monitor;
+
+use std::{ env, fs::File, io::{ Read, Error }, path::Path, process };
+use cpu::{ Cpu, CSR_MTVEC, Exception, CommitLog };
+use bus::{ DEFAULT_RAM_BASE, DEFAULT_RAM_SIZE };
+use elf::{ AddressSpace, Elf };
+use machine::{ Machine, KERNEL_OFFSET };
+use sbi::*;
+use newlib::Newlib;
+use linux::Linux;
+use htif::Htif;
+use semihosting::Semihosting;
+use gdb::Gdb;
+use monitor::Monitor;
+
+
+// ra starts out pointing here, so returning from the entry point ends the run.
+const EXIT_ADDRESS: usize = !0b_11;
+
+// A program killed from the debugger or the monitor ends with this status.
+const KILLED_STATUS: i32 = 1;
+
+// Indices of the ra, sp and a0 registers.
+const REG_RA: usize = 1;
+const REG_SP: usize = 2;
+const REG_A0: usize = 10;
+
+
+const USAGE: &str = "\
+Usage: riscv [--ram-base <address>] [--ram-size <MiB>] <program.elf> [<argument>...]
+       riscv [--ram-base <address>] [--ram-size <MiB>] --firmware <image>
+             [--kernel <image> [--initrd <file>] [--append <command line>]]
+       riscv [--ram-base <address>] [--ram-size <MiB>] --kernel <image>
+             [--initrd <file>] [--append <command line>]
+       riscv [--ram-size <MiB>] [--root <directory>] --linux <program> [<argument>...]
+
+Without --firmware, the kernel starts in supervisor mode on the built-in SBI.
+With --linux, a static Linux executable runs in user mode, seeing only the files
+under the root, which defaults to the current directory.  A program's arguments
+reach it through semihosting.
+
+Any of them can also take --gdb <port|socket>, to wait for GDB to connect on
+that TCP port or Unix socket and run under its control, --monitor, to start
+stopped at a prompt on the console for stepping and inspecting the hart, and
+--log-commits, to trace each instruction and what it changed on stderr, as
+Spike does.";
+
+
+
+struct Options
+{
+    ram_base: u64,
+    ram_size: u64,
+
+    // Running a bare-metal program, with the rest of the command line as its arguments.
+    program: Option<String>,
+
+    // Booting an operating system, instead of running a program.
+    firmware: Option<String>,
+    kernel: Option<String>,
+    initrd: Option<String>,
+    append: Option<String>,
+
+    // Running a Linux executable, also with arguments.
+    linux: Option<String>,
+    args: Vec<String>,
+    root: Option<String>,
+
+    // Where to wait for a debugger.
+    gdb: Option<String>,
+
+    // Whether to stop at the monitor's prompt, and whether to trace execution.
+    monitor: bool,
+    log_commits: bool
+}
+
+
+fn usage() -> !
+{
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+
+// Numbers are decimal, or hex with a 0x prefix.
+fn parse_number(text: &str) -> u64
+{
+    let parsed = match text.strip_prefix("0x")
+        {
+            Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+            None      => text.replace('_', "").parse()
+        };
+
+    parsed.unwrap_or_else(|_| usage())
+}
+
+
+fn parse_options() -> Options
+{
+    let mut options = Options { ram_base: DEFAULT_RAM_BASE,
+                                ram_size: DEFAULT_RAM_SIZE,
+                                program: None,
+                                firmware: None,
+                                kernel: None,
+                                initrd: None,
+                                append: None,
+                                linux: None,
+                                args: Vec::new(),
+                                root: None,
+                                gdb: None,
+                                monitor: false,
+                                log_commits: false };
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next()
+    {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+
+        match arg.as_str()
+        {
+            "--ram-base"                => options.ram_base = parse_number(&value()),
+            "--ram-size"                => options.ram_size = parse_number(&value()) * 1024 * 1024,
+            "--firmware"                => options.firmware = Some(value()),
+            "--kernel"                  => options.kernel = Some(value()),
+            "--initrd"                  => options.initrd = Some(value()),
+            "--append"                  => options.append = Some(value()),
+            "--root"                    => options.root = Some(value()),
+            "--gdb"                     => options.gdb = Some(value()),
+            "--monitor"                 => options.monitor = true,
+            "--log-commits"             => options.log_commits = true,
+
+            "--linux" =>
+                {
+                    options.linux = Some(value());
+                    options.args = args.collect();
+                    break;
+                },
+
+            _ if !arg.starts_with('-') =>
+                {
+                    options.program = Some(arg);
+                    options.args = args.collect();
+                    break;
+                },
+
+            _ =>
+                usage()
+        }
+    }
+
+    // Either a program, firmware, a kernel or a Linux executable.  Only a kernel has an initrd or
+    // command line, and only a Linux executable has a root.
+    let booting = options.firmware.is_some() || options.kernel.is_some();
+    let kernel = options.kernel.is_some();
+    let linux = options.linux.is_some();
+
+    if    [ options.program.is_some(), booting, linux ].iter().filter(|&&given| given).count() != 1
+       || ((options.initrd.is_some() || options.append.is_some()) && !kernel)
+       || (options.root.is_some() && !linux)
+    {
+        usage();
+    }
+
+    options
+}
+
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error>
+{
+    let mut contents = Vec::new();
+
+    File::open(path).and_then(|mut file| file.read_to_end(&mut contents))
+                    .map_err(|error| Error::new(error.kind(), format!("{}: {}", path, error)))?;
+
+    Ok(contents)
+}
+
+
+// The firmware goes at the start of RAM, and the kernel after it where the firmware expects to find
+// it.  Gives the firmware's entry point, or the kernel's when it runs on the built-in SBI.
+fn load_boot_images(machine: &mut Machine, options: &Options) -> Result<u64, Error>
+{
+    let ram_base = machine.ram_base;
+    let mut entry = None;
+
+    if let Some(firmware) = &options.firmware
+    {
+        entry = Some(machine.load_image(&read_file(firmware)?, ram_base)?);
+    }
+
+    if let Some(kernel) = &options.kernel
+    {
+        let kernel_entry = machine.load_image(&read_file(kernel)?, ram_base + KERNEL_OFFSET)?;
+
+        entry = entry.or(Some(kernel_entry));
+    }
+
+    if let Some(initrd) = &options.initrd
+    {
+        machine.load_initrd(&read_file(initrd)?)?;
+    }
+
+    machine.bootargs = options.append.clone();
 
-use std::{ env, fs::File, io::{ Read, Error } };
-use cpu::Cpu;
+    Ok(entry.unwrap_or_else(|| usage()))
+}
+
+
+
+// The executable is the first argument the program sees, as the path it was given by.
+fn run_linux(options: &Options, program: &str) -> Result<i32, Error>
+{
+    let mut linux = Linux::new(Path::new(options.root.as_deref().unwrap_or(".")),
+                               options.ram_size)?;
+    let elf = Elf::parse(&read_file(program)?, AddressSpace::Virtual, linux.memory())?;
+    let mut cpu = Cpu::new(linux.bus()?);
+
+    let args: Vec<String> = Some(program.to_string()).into_iter()
+                                                     .chain(options.args.iter().cloned())
+                                                     .collect();
+
+    linux.start(&mut cpu, &elf, &args)?;
+
+    if options.log_commits
+    {
+        cpu.commit_log = Some(CommitLog::new());
+    }
+
+    let mut gdb = options.gdb.as_deref().map(Gdb::connect).transpose()?;
+
+    // The executable has stdin to itself, so the monitor reads from it directly.
+    let mut monitor = options.monitor.then(|| Monitor::new(None, Some(&elf)));
+
+    let status = loop
+    {
+        if let Some(gdb) = &mut gdb
+        {
+            if !gdb.update(&mut cpu)
+            {
+                break KILLED_STATUS;
+            }
+        }
+
+        if let Some(monitor) = &mut monitor
+        {
+            if !monitor.update(&mut cpu)
+            {
+                break KILLED_STATUS;
+            }
+        }
+
+        match cpu.step()
+        {
+            Ok(()) =>
+                {},
+
+            Err(Exception::EnvironmentCallFromUMode) =>
+                if let Some(status) = linux.call(&mut cpu)
+                {
+                    break status;
+                },
+
+            Err(exception) =>
+                {
+                    if    gdb.as_mut().is_some_and(|gdb| gdb.trapped(&mut cpu, exception))
+                       || monitor.as_mut().is_some_and(|monitor| monitor.trapped(&mut cpu,
+                                                                                 exception))
+                    {
+                        continue;
+                    }
+
+                    eprintln!("Unhandled {:?} at {:#x}.", exception, cpu.pc);
+                    break 1;
+                }
+        }
+    };
+
+    if let Some(gdb) = &mut gdb
+    {
+        gdb.exited(status);
+    }
+
+    if let Some(monitor) = &mut monitor
+    {
+        monitor.exited(status);
+    }
+
+    Ok(status)
+}
 
 
 
 fn main() -> Result<(), Error>
 {
-    let args: Vec<String> = env::args().collect();
+    let options = parse_options();
 
-    if args.len() != 2
+    if let Some(program) = &options.linux
     {
-        panic!("Binary file to load is missing.");
+        process::exit(run_linux(&options, program)?);
     }
 
-    let mut file = File::open(&args[1])?;
-    let mut binary = Vec::new();
+    let mut machine = Machine::new(options.ram_base, options.ram_size)?;
+
+    let program = match &options.program
+        {
+            Some(program) =>
+                Some(Elf::parse(&read_file(program)?, AddressSpace::Physical, machine.memory())?),
+
+            None =>
+                None
+        };
+
+    let entry = match &program
+        {
+            Some(elf) => { elf.load(&mut machine.cpu.bus)?; elf.entry },
+            None      => load_boot_images(&mut machine, &options)?
+        };
+
+    let device_tree = machine.load_device_tree()?;
+
+    // Firmware is started through the reset vector, the rest are jumped to directly.
+    let entry = if program.is_none() && options.firmware.is_some()
+        {
+            machine.add_reset_vector(entry, device_tree)?
+        }
+        else
+        {
+            entry
+        };
+
+    machine.reset(entry, device_tree);
+
+    // A kernel without firmware in front of it has its environment calls handled here.
+    let mut sbi = match ( &options.kernel, &options.firmware )
+        {
+            ( Some(_), None ) => Some(Sbi::new(machine.uart.clone())),
+            _                 => None
+        };
 
-    file.read_to_end(&mut binary)?;
+    // A program's system calls are carried out by the host, its heap runs from the end of the
+    // program up to the device tree.
+    let mut newlib = program.as_ref()
+                            .map(|elf| Newlib::new(machine.uart.clone(), elf.end(), device_tree));
 
-    let mut cpu = Cpu::new(binary);
+    // Programs written for Spike talk to the host through tohost instead.
+    let mut htif = program.as_ref().and_then(|elf| Htif::new(elf, machine.uart.clone()));
 
-    while cpu.pc < cpu.memory.len()
+    // Or through semihosting, which also gives them their command line.
+    let mut semihosting = options.program.as_ref().map(|program|
+        {
+            let args = Some(program.clone()).into_iter().chain(options.args.iter().cloned());
+            Semihosting::new(args.collect::<Vec<_>>().join(" "))
+        });
+
+    let cpu = &mut machine.cpu;
+
+    if options.log_commits
+    {
+        cpu.commit_log = Some(CommitLog::new());
+    }
+
+    if let Some(sbi) = &sbi
     {
-        let instruction = cpu.fetch();
+        sbi.start(cpu);
+    }
+
+    // A program gets a stack just below the device tree, and ends by returning from its entry
+    // point or calling exit.
+    if program.is_some()
+    {
+        cpu.regs[REG_SP - 1] = device_tree;
+        cpu.regs[REG_RA - 1] = EXIT_ADDRESS as u64;
+    }
+
+    // A debugger, when there is one, gets control before each instruction, and so does the
+    // monitor.
+    let mut gdb = options.gdb.as_deref().map(Gdb::connect).transpose()?;
+    let console = &machine.uart;
+    let mut monitor = options.monitor.then(|| Monitor::new(Some(console.clone()), program.as_ref()));
+
+    let mut status = 0;
+
+    while cpu.pc != EXIT_ADDRESS
+    {
+        if let Some(gdb) = &mut gdb
+        {
+            if !gdb.update(cpu)
+            {
+                status = KILLED_STATUS;
+                break;
+            }
+        }
+
+        if let Some(monitor) = &mut monitor
+        {
+            if !monitor.update(cpu)
+            {
+                status = KILLED_STATUS;
+                break;
+            }
+        }
+
+        if let Some(sbi) = &sbi
+        {
+            sbi.update(cpu);
+        }
+
+        if let ( Some(htif), Some(newlib) ) = ( &mut htif, &mut newlib )
+        {
+            if let Some(code) = htif.update(cpu, newlib)
+            {
+                status = code;
+                break;
+            }
+        }
+
+        if let Some(interrupt) = cpu.pending_interrupt()
+        {
+            cpu.interrupt(interrupt);
+        }
 
-        cpu.pc = cpu.pc + 4;
-        cpu.execute(&instruction);
+        if let Err(exception) = cpu.step()
+        {
+            if let ( Exception::EnvironmentCallFromSMode, Some(sbi) ) = ( exception, &mut sbi )
+            {
+                match sbi.call(cpu)
+                {
+                    Some(reset) if reset.kind == SBI_RESET_SHUTDOWN =>
+                        {
+                            status = (reset.reason != SBI_RESET_REASON_NONE) as i32;
+                            break;
+                        },
+
+                    // There's nothing to reboot into, so a reboot just stops.
+                    Some(_) =>
+                        {
+                            eprintln!("The guest asked for a reboot.");
+                            break;
+                        },
+
+                    None =>
+                        continue
+                }
+            }
+
+            // A breakpoint between two particular no-ops is a semihosting call.
+            if let ( Exception::Breakpoint(_), Some(semihosting), Some(newlib) ) =
+                   ( exception, &mut semihosting, &mut newlib )
+            {
+                if Semihosting::is_call(cpu)
+                {
+                    match semihosting.call(cpu, newlib)
+                    {
+                        Some(code) => { status = code; break; },
+                        None       => continue
+                    }
+                }
+            }
+
+            // Without a trap handler installed the program has nowhere to go, unless it's making a
+            // system call.
+            if cpu.csrs[CSR_MTVEC] == 0
+            {
+                if let ( Exception::EnvironmentCallFromMMode, Some(newlib) ) = ( exception,
+                                                                                 &mut newlib )
+                {
+                    match newlib.call(cpu)
+                    {
+                        Some(code) => { status = code; break; },
+                        None       => continue
+                    }
+                }
+
+                if    gdb.as_mut().is_some_and(|gdb| gdb.trapped(cpu, exception))
+                   || monitor.as_mut().is_some_and(|monitor| monitor.trapped(cpu, exception))
+                {
+                    continue;
+                }
+
+                eprintln!("Unhandled {:?} at {:#x}.", exception, cpu.pc);
+                status = 1;
+                break;
+            }
+
+            cpu.trap(exception);
+        }
+    }
+
+    // Returning from the entry point is the same as calling exit.
+    if cpu.pc == EXIT_ADDRESS
+    {
+        status = cpu.regs[REG_A0 - 1] as i32;
+    }
+
+    if let Some(gdb) = &mut gdb
+    {
+        gdb.exited(status);
+    }
+
+    if let Some(monitor) = &mut monitor
+    {
+        monitor.exited(status);
     }
 
     //println!("{:?}", cpu);
 
-    Ok(())
+    process::exit(status)
 }
@@ -0,0 +1,890 @@
+
+// An interactive monitor for driving the hart from the console, as a hardware debugger would.  The
+// hart starts out stopped, and between commands it can be stepped, run to a breakpoint or an
+// address, and have its registers, CSRs and memory looked at and changed.  Breakpoints can have a
+// condition on a register, checked each time the hart reaches them.
+//
+// Commands are read from the console the guest's input comes from.  While the hart is stopped the
+// monitor has it to itself, and while it runs the guest does.
+
+
+use std::{ cell::RefCell, convert::TryFrom, io::{ self, BufRead }, rc::Rc };
+use crate::{ cpu::*, devices::Uart, elf::Elf };
+
+
+const PROMPT: &str = "(riscv) ";
+
+const HELP: &str = "\
+step [<count>]               run a number of instructions, one by default
+continue                     run until a breakpoint
+until <address>              run until the pc gets to the address, or a breakpoint
+regs                         show the pc, the privilege level and the integer registers
+fregs                        show the floating-point registers
+csrs                         show the CSRs
+csr <name|number> [<value>]  show or set a CSR
+set <register> <value>       set a register, the pc, a CSR or the privilege level
+x <address> [<length>]       dump memory in hex
+patch <address> <byte>...    write bytes to memory
+dis [<address> [<count>]]    disassemble, around the pc by default
+break <address> [if <register> <comparison> <value>]
+                             set a breakpoint, which only stops when the condition holds
+delete [<number>]            delete a breakpoint, or all of them
+breaks                       list the breakpoints
+quit                         end the program
+
+Numbers are decimal, or hex with a 0x prefix, and a register can stand in for one.
+Comparisons are ==, !=, <, <=, > and >=, all unsigned.  An empty line steps.";
+
+const REGISTERS_PER_LINE: usize = 4;
+const CSRS_PER_LINE:      usize = 3;
+
+const HEXDUMP_WIDTH:  usize = 16;
+const HEXDUMP_LENGTH: u64 = 64;
+
+// By default the disassembly starts up to this many bytes before the pc, and runs for this many
+// instructions.
+const DISASSEMBLY_CONTEXT: usize = 16;
+const DISASSEMBLY_LENGTH:  usize = 10;
+
+
+
+// Where commands come from: the machine's console, or for a Linux executable, stdin itself.
+enum Input
+{
+    Console(Rc<RefCell<Uart>>),
+    Stdin
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State
+{
+    Stopped,
+    Stepping(u64),
+    Running,
+    RunningTo(usize),
+
+    // The console has closed, the hart runs on by itself.
+    Detached
+}
+
+
+// What to do after a command.
+enum Action
+{
+    Stay,
+    Resume(State),
+    Quit
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Register
+{
+    Integer(usize),
+    Float(usize),
+    Pc,
+    Privilege,
+    Csr(usize)
+}
+
+
+#[derive(Debug, Copy, Clone)]
+enum Operand
+{
+    Number(u64),
+    Register(Register)
+}
+
+
+#[derive(Debug, Copy, Clone)]
+enum Comparison
+{
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual
+}
+
+
+struct Condition
+{
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+
+    // As it was typed, for listing.
+    text: String
+}
+
+
+struct Breakpoint
+{
+    number: usize,
+    address: usize,
+    condition: Option<Condition>
+}
+
+
+pub struct Monitor<'a>
+{
+    input: Input,
+
+    // The program's symbols name the addresses shown, when there's a program.
+    program: Option<&'a Elf>,
+    state: State,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: usize
+}
+
+
+// Numbers are decimal, or hex with a 0x prefix.
+fn parse_number(text: &str) -> Option<u64>
+{
+    match text.strip_prefix("0x")
+    {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None      => text.replace('_', "").parse().ok()
+    }
+}
+
+
+// Registers go by their numbers or ABI names, and CSRs by their names.
+fn parse_register(name: &str) -> Option<Register>
+{
+    let numbered = |prefix| name.strip_prefix(prefix)
+                                .and_then(|number| number.parse::<usize>().ok())
+                                .filter(|&number| number < 32);
+
+    if let Some(number) = numbered('x')
+    {
+        return Some(Register::Integer(number));
+    }
+
+    if let Some(number) = numbered('f')
+    {
+        return Some(Register::Float(number));
+    }
+
+    match name
+    {
+        "pc"   => Some(Register::Pc),
+        "priv" => Some(Register::Privilege),
+        "fp"   => Some(Register::Integer(8)),
+
+        _ =>
+            REGISTER_NAMES.iter().position(|&register| register == name).map(Register::Integer)
+                .or_else(|| FP_REGISTER_NAMES.iter()
+                                             .position(|&register| register == name)
+                                             .map(Register::Float))
+                .or_else(|| CSR_NAMES.iter()
+                                     .find(|( _, csr )| *csr == name)
+                                     .map(|( address, _ )| Register::Csr(*address)))
+    }
+}
+
+
+// A CSR can also be given by its address.
+fn parse_csr(text: &str) -> Option<usize>
+{
+    match parse_register(text)
+    {
+        Some(Register::Csr(address)) => Some(address),
+        _                            => parse_number(text).filter(|&address| address < 4096)
+                                                          .map(|address| address as usize)
+    }
+}
+
+
+fn parse_operand(text: &str) -> Result<Operand, String>
+{
+    parse_register(text).map(Operand::Register)
+                        .or_else(|| parse_number(text).map(Operand::Number))
+                        .ok_or_else(|| format!("'{}' isn't a number or a register.", text))
+}
+
+
+fn parse_comparison(text: &str) -> Result<Comparison, String>
+{
+    match text
+    {
+        "==" => Ok(Comparison::Equal),
+        "!=" => Ok(Comparison::NotEqual),
+        "<"  => Ok(Comparison::Less),
+        "<=" => Ok(Comparison::LessOrEqual),
+        ">"  => Ok(Comparison::Greater),
+        ">=" => Ok(Comparison::GreaterOrEqual),
+        _    => Err(format!("'{}' isn't a comparison.", text))
+    }
+}
+
+
+fn read_register(cpu: &mut Cpu, register: Register) -> Option<u64>
+{
+    match register
+    {
+        Register::Integer(0)      => Some(0),
+        Register::Integer(number) => Some(cpu.regs[number - 1]),
+        Register::Float(number)   => Some(cpu.fregs[number]),
+        Register::Pc              => Some(cpu.pc as u64),
+        Register::Privilege       => Some(cpu.privilege as u64),
+        Register::Csr(address)    => cpu.read_csr_debug(address)
+    }
+}
+
+
+// x0 stays zero, and the reserved privilege level can't be set.
+fn write_register(cpu: &mut Cpu, register: Register, value: u64) -> Option<()>
+{
+    match register
+    {
+        Register::Integer(0)      => return None,
+        Register::Integer(number) => cpu.regs[number - 1] = value,
+        Register::Float(number)   => cpu.fregs[number] = value,
+        Register::Pc              => cpu.pc = value as usize,
+        Register::Csr(address)    => return cpu.write_csr_debug(address, value),
+
+        Register::Privilege =>
+            {
+                if value > PrivilegeLvel::Machine as u64 || value == 0b_10
+                {
+                    return None;
+                }
+
+                cpu.privilege = PrivilegeLvel::from_bits(value);
+            }
+    }
+
+    Some(())
+}
+
+
+fn privilege_name(privilege: PrivilegeLvel) -> &'static str
+{
+    match privilege
+    {
+        PrivilegeLvel::User       => "U",
+        PrivilegeLvel::Supervisor => "S",
+        PrivilegeLvel::Machine    => "M"
+    }
+}
+
+
+// Decode the instruction at an address without fetching it, so nothing about the hart changes.
+fn decode(cpu: &mut Cpu, address: usize) -> Option<Instruction>
+{
+    let mut low = [ 0; 2 ];
+    let mut high = [ 0; 2 ];
+
+    if !cpu.read_debug(address, &mut low)
+    {
+        return None;
+    }
+
+    let low = u16::from_le_bytes(low);
+
+    if is_compressed(low)
+    {
+        // One that doesn't expand is still shown, as the bits fetched.
+        return Some(Instruction::compressed(low, expand(low).unwrap_or(0)));
+    }
+
+    if !cpu.read_debug(address.wrapping_add(2), &mut high)
+    {
+        return None;
+    }
+
+    Some(Instruction::new(((u16::from_le_bytes(high) as u32) << 16) | low as u32))
+}
+
+
+// An address along with the symbol it's in, if it's in one.
+fn location(program: Option<&Elf>, address: usize) -> String
+{
+    match program.and_then(|elf| elf.symbol_at(address as u64))
+    {
+        Some(( name, 0 ))      => format!("0x{:x} <{}>", address, name),
+        Some(( name, offset )) => format!("0x{:x} <{}+{}>", address, name, offset),
+        None                   => format!("0x{:x}", address)
+    }
+}
+
+
+// The instruction at the pc is marked, and a symbol starting at the address is shown above it.
+fn show_instruction(cpu: &mut Cpu, program: Option<&Elf>, address: usize) -> Option<usize>
+{
+    let marker = if address == cpu.pc { "=>" } else { "  " };
+
+    if let Some(( name, 0 )) = program.and_then(|elf| elf.symbol_at(address as u64))
+    {
+        eprintln!("{}:", name);
+    }
+
+    match decode(cpu, address)
+    {
+        Some(instruction) =>
+            {
+                let encoding = format!("{:0width$x}",
+                                       instruction.encoding,
+                                       width = instruction.length * 2);
+
+                eprintln!("{} 0x{:016x}  {:<8}  {}",
+                          marker,
+                          address,
+                          encoding,
+                          instruction.disassemble(address));
+
+                Some(instruction.length)
+            },
+
+        None =>
+            {
+                eprintln!("{} 0x{:016x}  Memory here can't be read.", marker, address);
+                None
+            }
+    }
+}
+
+
+fn disassemble(cpu: &mut Cpu, program: Option<&Elf>, address: usize, count: usize)
+{
+    let mut address = address;
+
+    for _ in 0..count
+    {
+        match show_instruction(cpu, program, address)
+        {
+            Some(length) => address = address.wrapping_add(length),
+            None         => break
+        }
+    }
+}
+
+
+// Instructions can't be decoded backwards, so the disassembly around the pc starts as far back
+// as possible from where decoding forwards lands on the pc.
+fn context_start(cpu: &mut Cpu) -> usize
+{
+    for back in (2..=DISASSEMBLY_CONTEXT).rev().step_by(2)
+    {
+        let start = cpu.pc.wrapping_sub(back);
+        let mut address = start;
+
+        while address < cpu.pc
+        {
+            match decode(cpu, address)
+            {
+                Some(instruction) => address += instruction.length,
+                None              => break
+            }
+        }
+
+        if address == cpu.pc
+        {
+            return start;
+        }
+    }
+
+    cpu.pc
+}
+
+
+// Print registers a few to a line.
+fn print_registers(registers: &[ ( &str, u64 ) ], per_line: usize)
+{
+    let width = registers.iter().map(|( name, _ )| name.len()).max().unwrap_or(0);
+
+    for line in registers.chunks(per_line)
+    {
+        let line: Vec<String> = line.iter()
+                                    .map(|( name, value )| format!("{:<width$} 0x{:016x}",
+                                                                   name,
+                                                                   value,
+                                                                   width = width))
+                                    .collect();
+
+        eprintln!("{}", line.join("  "));
+    }
+}
+
+
+
+impl Condition
+{
+    fn holds(&self, cpu: &mut Cpu) -> bool
+    {
+        let mut value = |operand| match operand
+            {
+                Operand::Number(number)     => number,
+                Operand::Register(register) => read_register(cpu, register).unwrap_or(0)
+            };
+
+        let ( left, right ) = ( value(self.left), value(self.right) );
+
+        match self.comparison
+        {
+            Comparison::Equal          => left == right,
+            Comparison::NotEqual       => left != right,
+            Comparison::Less           => left < right,
+            Comparison::LessOrEqual    => left <= right,
+            Comparison::Greater        => left > right,
+            Comparison::GreaterOrEqual => left >= right
+        }
+    }
+}
+
+
+
+impl<'a> Monitor<'a>
+{
+    // Commands come from the console when there is one, from stdin otherwise.
+    pub fn new(console: Option<Rc<RefCell<Uart>>>, program: Option<&'a Elf>) -> Self
+    {
+        let input = match console
+            {
+                Some(uart) => Input::Console(uart),
+                None       => Input::Stdin
+            };
+
+        eprintln!("Stopped at the start.  Type help for the commands.");
+
+        Self { input,
+               program,
+               state: State::Stopped,
+               breakpoints: Vec::new(),
+               next_breakpoint: 1 }
+    }
+
+
+    // Called before each instruction.  Stops the hart once it has finished its steps, reached the
+    // address it was running to or reached a breakpoint whose condition holds, then takes commands
+    // until it's resumed.  False when the program has been ended.
+    pub fn update(&mut self, cpu: &mut Cpu) -> bool
+    {
+        let state = self.state;
+
+        let stop = match state
+            {
+                State::Detached =>
+                    return true,
+
+                State::Stopped =>
+                    true,
+
+                _ if self.breakpoint_reached(cpu) =>
+                    true,
+
+                State::Stepping(count) if count > 1 =>
+                    {
+                        self.state = State::Stepping(count - 1);
+                        false
+                    },
+
+                State::Stepping(_) =>
+                    true,
+
+                State::RunningTo(address) =>
+                    cpu.pc == address,
+
+                State::Running =>
+                    false
+            };
+
+        if !stop
+        {
+            return true;
+        }
+
+        self.state = State::Stopped;
+        show_instruction(cpu, self.program, cpu.pc);
+
+        self.serve(cpu)
+    }
+
+
+    // An exception the program can't handle stops the hart instead of ending the program.  False
+    // when the monitor has gone.
+    pub fn trapped(&mut self, cpu: &mut Cpu, exception: Exception) -> bool
+    {
+        if self.state == State::Detached
+        {
+            return false;
+        }
+
+        eprintln!("Stopped by {:?} at {}.", exception, location(self.program, cpu.pc));
+        self.state = State::Stopped;
+
+        true
+    }
+
+
+    pub fn exited(&mut self, status: i32)
+    {
+        if self.state != State::Detached
+        {
+            eprintln!("The program exited with status {}.", status);
+        }
+    }
+
+
+    // Whether the hart is at a breakpoint that it should stop on.
+    fn breakpoint_reached(&mut self, cpu: &mut Cpu) -> bool
+    {
+        let breakpoint = self.breakpoints.iter().find(|breakpoint|
+            {
+                   breakpoint.address == cpu.pc
+                && breakpoint.condition.as_ref().is_none_or(|condition| condition.holds(cpu))
+            });
+
+        match breakpoint
+        {
+            Some(breakpoint) =>
+                {
+                    eprintln!("Breakpoint {}.", breakpoint.number);
+                    true
+                },
+
+            None =>
+                false
+        }
+    }
+
+
+    fn read_line(&mut self) -> Option<String>
+    {
+        let mut line = Vec::new();
+
+        match &self.input
+        {
+            Input::Console(uart) =>
+                loop
+                {
+                    match uart.borrow_mut().wait_input()
+                    {
+                        Some(b'\n')                    => break,
+                        Some(byte)                     => line.push(byte),
+                        None if line.is_empty()        => return None,
+                        None                           => break
+                    }
+                },
+
+            Input::Stdin =>
+                if io::stdin().lock().read_until(b'\n', &mut line).ok()? == 0
+                {
+                    return None;
+                }
+        }
+
+        Some(String::from_utf8_lossy(&line).trim().to_string())
+    }
+
+
+    // Take commands until one resumes the hart.  Once the console closes the hart is left to run
+    // on by itself.
+    fn serve(&mut self, cpu: &mut Cpu) -> bool
+    {
+        loop
+        {
+            eprint!("{}", PROMPT);
+
+            let line = match self.read_line()
+                {
+                    Some(line) => line,
+                    None       => { self.state = State::Detached; return true; }
+                };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match self.handle(cpu, &words)
+            {
+                Ok(Action::Stay) =>
+                    {},
+
+                Ok(Action::Resume(state)) =>
+                    {
+                        self.state = state;
+                        return true;
+                    },
+
+                Ok(Action::Quit) =>
+                    return false,
+
+                Err(message) =>
+                    eprintln!("{}", message)
+            }
+        }
+    }
+
+
+    fn handle(&mut self, cpu: &mut Cpu, words: &[ &str ]) -> Result<Action, String>
+    {
+        let ( command, arguments ) = match words.split_first()
+            {
+                Some(( command, arguments )) => ( *command, arguments ),
+                None                         => return Ok(Action::Resume(State::Stepping(1)))
+            };
+
+        let value = |cpu: &mut Cpu, index: usize| -> Result<Option<u64>, String>
+            {
+                match arguments.get(index)
+                {
+                    Some(text) => match parse_operand(text)?
+                        {
+                            Operand::Number(number) => Ok(Some(number)),
+                            Operand::Register(register) =>
+                                read_register(cpu, register)
+                                    .map(Some)
+                                    .ok_or_else(|| format!("{} can't be read.", text))
+                        },
+
+                    None => Ok(None)
+                }
+            };
+
+        let required = |value: Option<u64>| value.ok_or_else(|| format!("{} needs a value.",
+                                                                         command));
+
+        match command
+        {
+            "help" | "h" =>
+                eprintln!("{}", HELP),
+
+            "step" | "s" =>
+                {
+                    let count = value(cpu, 0)?.unwrap_or(1).max(1);
+                    return Ok(Action::Resume(State::Stepping(count)));
+                },
+
+            "continue" | "c" =>
+                return Ok(Action::Resume(State::Running)),
+
+            "until" | "u" =>
+                {
+                    let address = required(value(cpu, 0)?)? as usize;
+                    return Ok(Action::Resume(State::RunningTo(address)));
+                },
+
+            "regs" | "r" =>
+                {
+                    eprintln!("pc   0x{:016x}  priv {}", cpu.pc, privilege_name(cpu.privilege));
+
+                    let registers: Vec<( &str, u64 )> =
+                        REGISTER_NAMES.iter()
+                                      .enumerate()
+                                      .map(|( number, &name )|
+                                          ( name, read_register(cpu, Register::Integer(number))
+                                                      .unwrap_or(0) ))
+                                      .collect();
+
+                    print_registers(&registers, REGISTERS_PER_LINE);
+                },
+
+            "fregs" =>
+                {
+                    let registers: Vec<( &str, u64 )> = FP_REGISTER_NAMES.iter()
+                                                                         .copied()
+                                                                         .zip(cpu.fregs)
+                                                                         .collect();
+
+                    print_registers(&registers, REGISTERS_PER_LINE);
+                },
+
+            "csrs" =>
+                {
+                    let csrs: Vec<( &str, u64 )> =
+                        CSR_NAMES.iter()
+                                 .map(|&( address, name )|
+                                     ( name, cpu.read_csr_debug(address).unwrap_or(0) ))
+                                 .collect();
+
+                    print_registers(&csrs, CSRS_PER_LINE);
+                },
+
+            "csr" =>
+                {
+                    let name = arguments.first().ok_or("csr needs a CSR.")?;
+                    let address = parse_csr(name).ok_or_else(|| format!("No CSR {}.", name))?;
+
+                    if let Some(value) = value(cpu, 1)?
+                    {
+                        cpu.write_csr_debug(address, value)
+                           .ok_or_else(|| format!("{} can't be written.", name))?;
+                    }
+
+                    let value = cpu.read_csr_debug(address)
+                                   .ok_or_else(|| format!("{} can't be read.", name))?;
+
+                    eprintln!("{} (0x{:03x}) 0x{:016x}",
+                              csr_name(address).unwrap_or(name),
+                              address,
+                              value);
+                },
+
+            "set" =>
+                {
+                    let name = arguments.first().ok_or("set needs a register.")?;
+                    let register = parse_register(name)
+                                       .ok_or_else(|| format!("No register {}.", name))?;
+                    let value = required(value(cpu, 1)?)?;
+
+                    write_register(cpu, register, value)
+                        .ok_or_else(|| format!("{} can't be set to 0x{:x}.", name, value))?;
+                },
+
+            "x" =>
+                {
+                    let address = required(value(cpu, 0)?)?;
+                    let length = value(cpu, 1)?.unwrap_or(HEXDUMP_LENGTH);
+
+                    hexdump(cpu, address, length)?;
+                },
+
+            "patch" =>
+                {
+                    let address = required(value(cpu, 0)?)?;
+                    let mut bytes = Vec::new();
+
+                    for index in 1..arguments.len().max(2)
+                    {
+                        let byte = required(value(cpu, index)?)?;
+                        bytes.push(u8::try_from(byte)
+                                       .map_err(|_| format!("0x{:x} isn't a byte.", byte))?);
+                    }
+
+                    if !cpu.write_debug(address as usize, &bytes)
+                    {
+                        return Err(format!("Memory at 0x{:x} can't be written.", address));
+                    }
+                },
+
+            "dis" | "d" =>
+                match value(cpu, 0)?
+                {
+                    Some(address) =>
+                        {
+                            let count = value(cpu, 1)?.map_or(DISASSEMBLY_LENGTH,
+                                                              |count| count as usize);
+                            disassemble(cpu, self.program, address as usize, count);
+                        },
+
+                    None =>
+                        {
+                            let start = context_start(cpu);
+                            disassemble(cpu, self.program, start, DISASSEMBLY_LENGTH);
+                        }
+                },
+
+            "break" | "b" =>
+                {
+                    let address = required(value(cpu, 0)?)? as usize;
+
+                    let condition = match arguments.get(1..)
+                        {
+                            Some([ "if", left, comparison, right ]) =>
+                                Some(Condition { left: parse_operand(left)?,
+                                                 comparison: parse_comparison(comparison)?,
+                                                 right: parse_operand(right)?,
+                                                 text: arguments[2..].join(" ") }),
+
+                            Some([]) =>
+                                None,
+
+                            _ =>
+                                return Err("A condition looks like: if a0 == 0x10".to_string())
+                        };
+
+                    let number = self.next_breakpoint;
+
+                    self.next_breakpoint += 1;
+                    self.breakpoints.push(Breakpoint { number, address, condition });
+
+                    eprintln!("Breakpoint {} at {}.", number, location(self.program, address));
+                },
+
+            "delete" =>
+                match value(cpu, 0)?
+                {
+                    Some(number) =>
+                        {
+                            let count = self.breakpoints.len();
+
+                            self.breakpoints.retain(|breakpoint|
+                                                    breakpoint.number as u64 != number);
+
+                            if self.breakpoints.len() == count
+                            {
+                                return Err(format!("No breakpoint {}.", number));
+                            }
+                        },
+
+                    None =>
+                        self.breakpoints.clear()
+                },
+
+            "breaks" =>
+                for breakpoint in &self.breakpoints
+                {
+                    match &breakpoint.condition
+                    {
+                        Some(condition) => eprintln!("{:<3} 0x{:016x}  if {}",
+                                                     breakpoint.number,
+                                                     breakpoint.address,
+                                                     condition.text),
+                        None            => eprintln!("{:<3} 0x{:016x}",
+                                                     breakpoint.number,
+                                                     breakpoint.address)
+                    }
+                },
+
+            "quit" | "q" =>
+                return Ok(Action::Quit),
+
+            _ =>
+                return Err(format!("Unknown command {}, type help for the commands.", command))
+        }
+
+        Ok(Action::Stay)
+    }
+}
+
+
+// Sixteen bytes to a line, with their characters alongside.
+fn hexdump(cpu: &mut Cpu, address: u64, length: u64) -> Result<(), String>
+{
+    let mut offset = 0;
+
+    while offset < length
+    {
+        let line_address = address.wrapping_add(offset);
+        let mut bytes = vec![ 0; (length - offset).min(HEXDUMP_WIDTH as u64) as usize ];
+
+        if !cpu.read_debug(line_address as usize, &mut bytes)
+        {
+            return Err(format!("Memory at 0x{:x} can't be read.", line_address));
+        }
+
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = bytes.iter()
+                                .map(|&byte| if byte.is_ascii_graphic() || byte == b' '
+                                    {
+                                        byte as char
+                                    }
+                                    else
+                                    {
+                                        '.'
+                                    })
+                                .collect();
+
+        eprintln!("0x{:016x}  {:<width$}  {}",
+                  line_address,
+                  hex.join(" "),
+                  text,
+                  width = HEXDUMP_WIDTH * 3 - 1);
+
+        offset += bytes.len() as u64;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,524 @@
+
+// System calls for bare-metal programs built against newlib and libgloss, which make them with
+// ecall and expect a host to carry them out, as Spike's proxy kernel does.  They follow the Linux
+// numbering and calling convention: the call number in a7, arguments from a0, and the result or a
+// negated errno back in a0.  Guest file descriptors map onto host files, with the first three
+// being the console.
+
+
+use std::{ cell::RefCell,
+           fs::{ File, Metadata, OpenOptions },
+           io::{ self, Error, Read, Seek, SeekFrom, Write },
+           rc::Rc,
+           time::{ SystemTime, UNIX_EPOCH } };
+use crate::{ cpu::Cpu, devices::Uart };
+
+
+// Call numbers, in a7.
+const SYS_OPENAT:       u64 = 56;
+const SYS_CLOSE:        u64 = 57;
+const SYS_LSEEK:        u64 = 62;
+const SYS_READ:         u64 = 63;
+const SYS_WRITE:        u64 = 64;
+const SYS_FSTAT:        u64 = 80;
+const SYS_EXIT:         u64 = 93;
+const SYS_EXIT_GROUP:   u64 = 94;
+const SYS_GETTIMEOFDAY: u64 = 169;
+const SYS_BRK:          u64 = 214;
+const SYS_OPEN:         u64 = 1024;
+
+// Error numbers, as newlib has them.
+pub const EIO:     i64 = 5;
+pub const EBADF:   i64 = 9;
+pub const EFAULT:  i64 = 14;
+pub const EINVAL:  i64 = 22;
+pub const ESPIPE:  i64 = 29;
+pub const ENOSYS:  i64 = 38;
+
+// Flags for open.
+pub const O_ACCMODE: u64 = 0b_11;
+pub const O_RDONLY:  u64 = 0b_00;
+pub const O_WRONLY:  u64 = 0b_01;
+pub const O_RDWR:    u64 = 0b_10;
+pub const O_CREAT:   u64 = 0o_100;
+pub const O_EXCL:    u64 = 0o_200;
+pub const O_TRUNC:   u64 = 0o_1000;
+pub const O_APPEND:  u64 = 0o_2000;
+
+// Relative paths are taken from the host's working directory, there's no other to use.
+pub const AT_FDCWD: i64 = -100;
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+// File types and permissions in st_mode.
+const S_IFCHR: u32 = 0o_020000;
+const S_IFDIR: u32 = 0o_040000;
+const S_IFREG: u32 = 0o_100000;
+
+const CONSOLE_MODE:   u32 = S_IFCHR | 0o_620;
+const READ_ONLY_MODE: u32 = 0o_444;
+const WRITABLE_MODE:  u32 = 0o_644;
+
+// The layout of the kernel's struct stat that libgloss converts from.
+pub const STAT_SIZE:   usize = 128;
+const STAT_MODE:       usize = 16;
+const STAT_NLINK:      usize = 20;
+const STAT_SIZE_FIELD: usize = 48;
+const STAT_BLKSIZE:    usize = 56;
+const STAT_BLOCKS:     usize = 64;
+const STAT_ATIME:      usize = 72;
+const STAT_MTIME:      usize = 88;
+const STAT_CTIME:      usize = 104;
+
+const BLOCK_SIZE:      u64 = 4096;
+const STAT_BLOCK_UNIT: u64 = 512;
+
+// Longer reads and writes are cut short, which the caller has to allow for anyway.
+const MAX_TRANSFER: u64 = 1024 * 1024;
+
+// Paths longer than this are taken to be missing their terminator.
+const PATH_MAX: u64 = 4096;
+
+// Indices of the registers used by the calling convention.
+const REG_A0: usize = 10;
+const REG_A7: usize = 17;
+
+
+
+pub enum Syscall
+{
+    Returned(i64),
+    Exited(i32)
+}
+
+
+enum Handle
+{
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File)
+}
+
+
+pub struct Newlib
+{
+    uart: Rc<RefCell<Uart>>,
+
+    // Indexed by the guest's file descriptor, closed ones are left empty for reuse.
+    files: Vec<Option<Handle>>,
+
+    // The heap runs from the end of the program up to the break, which can't pass the limit.
+    heap_start: u64,
+    brk: u64,
+    brk_limit: u64
+}
+
+
+// Errors from the host are passed on as they are, newlib shares Linux's numbering.
+pub fn errno(error: &Error) -> i64
+{
+    -(error.raw_os_error().map_or(EIO, |code| code as i64))
+}
+
+
+pub fn put_u32(buffer: &mut [ u8 ], offset: usize, value: u32)
+{
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+
+pub fn put_u64(buffer: &mut [ u8 ], offset: usize, value: u64)
+{
+    buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+
+// Seconds and nanoseconds since the epoch, times before it are left as zero.
+pub fn timestamp(time: io::Result<SystemTime>) -> ( u64, u64 )
+{
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(( 0, 0 ), |since| ( since.as_secs(), since.subsec_nanos() as u64 ))
+}
+
+
+// A NUL terminated string from guest memory.
+pub fn read_path(cpu: &mut Cpu, address: u64) -> Option<String>
+{
+    let mut path = Vec::new();
+
+    for offset in 0..PATH_MAX
+    {
+        let mut byte = [ 0 ];
+
+        if !cpu.bus.read(address + offset, &mut byte)
+        {
+            return None;
+        }
+
+        if byte[0] == 0
+        {
+            return Some(String::from_utf8_lossy(&path).into_owned());
+        }
+
+        path.push(byte[0]);
+    }
+
+    None
+}
+
+
+// How the host should open a file for the guest's open flags.
+pub fn open_options(flags: u64) -> OpenOptions
+{
+    let access = flags & O_ACCMODE;
+    let mut options = OpenOptions::new();
+
+    options.read(access != O_WRONLY)
+           .write(access == O_WRONLY || access == O_RDWR)
+           .append(flags & O_APPEND != 0)
+           .truncate(flags & O_TRUNC != 0)
+           .create(flags & O_CREAT != 0)
+           .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0);
+
+    options
+}
+
+
+// A struct stat for a host file, or for the console without one.  Only the fields a program is
+// likely to look at are filled in.  The console shows up as a character device, which is how
+// newlib decides to line buffer it.
+pub fn stat(metadata: Option<&Metadata>) -> [ u8; STAT_SIZE ]
+{
+    let mut stat = [ 0; STAT_SIZE ];
+
+    put_u32(&mut stat, STAT_NLINK, 1);
+    put_u32(&mut stat, STAT_BLKSIZE, BLOCK_SIZE as u32);
+
+    let metadata = match metadata
+        {
+            Some(metadata) => metadata,
+            None           => { put_u32(&mut stat, STAT_MODE, CONSOLE_MODE); return stat; }
+        };
+
+    let kind = if metadata.is_dir() { S_IFDIR } else { S_IFREG };
+    let permissions = if metadata.permissions().readonly()
+        {
+            READ_ONLY_MODE
+        }
+        else
+        {
+            WRITABLE_MODE
+        };
+
+    put_u32(&mut stat, STAT_MODE, kind | permissions);
+    put_u64(&mut stat, STAT_SIZE_FIELD, metadata.len());
+    put_u64(&mut stat, STAT_BLOCKS, metadata.len().div_ceil(STAT_BLOCK_UNIT));
+
+    let times = [ ( STAT_ATIME, timestamp(metadata.accessed()) ),
+                  ( STAT_MTIME, timestamp(metadata.modified()) ),
+                  ( STAT_CTIME, timestamp(metadata.modified()) ) ];
+
+    for ( offset, ( seconds, nanoseconds ) ) in times
+    {
+        put_u64(&mut stat, offset, seconds);
+        put_u64(&mut stat, offset + 8, nanoseconds);
+    }
+
+    stat
+}
+
+
+
+impl Newlib
+{
+    // The console input is shared with the UART, which is already reading stdin.
+    pub fn new(uart: Rc<RefCell<Uart>>, heap_start: u64, brk_limit: u64) -> Self
+    {
+        Self { uart,
+               files: vec![ Some(Handle::Stdin), Some(Handle::Stdout), Some(Handle::Stderr) ],
+               heap_start,
+               brk: heap_start,
+               brk_limit }
+    }
+
+
+    // Carry out the system call the hart has stopped at, leaving the pc past the ecall.  Gives the
+    // exit status once the program has exited.
+    pub fn call(&mut self, cpu: &mut Cpu) -> Option<i32>
+    {
+        let number = cpu.regs[REG_A7 - 1];
+        let args = [ 0, 1, 2, 3 ].map(|index| cpu.regs[REG_A0 - 1 + index]);
+
+        cpu.pc += 4;
+
+        match self.syscall(cpu, number, args)
+        {
+            Syscall::Returned(result) => { cpu.regs[REG_A0 - 1] = result as u64; None },
+            Syscall::Exited(status)   => Some(status)
+        }
+    }
+
+
+    // Carry out a system call however it was made.
+    pub fn syscall(&mut self, cpu: &mut Cpu, number: u64, args: [ u64; 4 ]) -> Syscall
+    {
+        let result = match number
+            {
+                SYS_EXIT | SYS_EXIT_GROUP =>
+                    return Syscall::Exited(args[0] as i32),
+
+                SYS_OPEN =>
+                    self.open(cpu, args[0], args[1]),
+
+                SYS_OPENAT if args[0] as i64 == AT_FDCWD =>
+                    self.open(cpu, args[1], args[2]),
+
+                SYS_OPENAT =>
+                    -EBADF,
+
+                SYS_CLOSE =>
+                    self.close(args[0]),
+
+                SYS_READ =>
+                    self.read(cpu, args[0], args[1], args[2]),
+
+                SYS_WRITE =>
+                    self.write(cpu, args[0], args[1], args[2]),
+
+                SYS_LSEEK =>
+                    self.lseek(args[0], args[1] as i64, args[2]),
+
+                SYS_FSTAT =>
+                    self.fstat(cpu, args[0], args[1]),
+
+                SYS_BRK =>
+                    self.brk(args[0]) as i64,
+
+                SYS_GETTIMEOFDAY =>
+                    Newlib::gettimeofday(cpu, args[0]),
+
+                _ =>
+                    -ENOSYS
+            };
+
+        Syscall::Returned(result)
+    }
+
+
+    fn handle(&mut self, fd: u64) -> Option<&mut Handle>
+    {
+        self.files.get_mut(fd as usize).and_then(|handle| handle.as_mut())
+    }
+
+
+    pub fn is_console(&mut self, fd: u64) -> bool
+    {
+        matches!(self.handle(fd), Some(Handle::Stdin) | Some(Handle::Stdout) | Some(Handle::Stderr))
+    }
+
+
+    pub fn open(&mut self, cpu: &mut Cpu, path: u64, flags: u64) -> i64
+    {
+        let path = match read_path(cpu, path)
+            {
+                Some(path) => path,
+                None       => return -EFAULT
+            };
+
+        let file = match open_options(flags).open(path)
+            {
+                Ok(file)   => file,
+                Err(error) => return errno(&error)
+            };
+
+        let handle = Some(Handle::File(file));
+
+        match self.files.iter().position(|handle| handle.is_none())
+        {
+            Some(fd) => { self.files[fd] = handle; fd as i64 },
+            None     => { self.files.push(handle); self.files.len() as i64 - 1 }
+        }
+    }
+
+
+    pub fn close(&mut self, fd: u64) -> i64
+    {
+        match self.files.get_mut(fd as usize)
+        {
+            Some(handle @ Some(_)) => { *handle = None; 0 },
+            _                      => -EBADF
+        }
+    }
+
+
+    // Reading the console waits for the first byte, then takes whatever else has been typed.
+    pub fn read(&mut self, cpu: &mut Cpu, fd: u64, address: u64, length: u64) -> i64
+    {
+        let mut buffer = vec![ 0; length.min(MAX_TRANSFER) as usize ];
+
+        let count = match self.handle(fd)
+            {
+                Some(Handle::File(file)) =>
+                    match file.read(&mut buffer)
+                    {
+                        Ok(count)  => count,
+                        Err(error) => return errno(&error)
+                    },
+
+                Some(Handle::Stdin) =>
+                    {
+                        let mut uart = self.uart.borrow_mut();
+                        let mut count = 0;
+
+                        if !buffer.is_empty()
+                        {
+                            if let Some(byte) = uart.wait_input()
+                            {
+                                buffer[0] = byte;
+                                count = 1;
+                            }
+                        }
+
+                        while count > 0 && count < buffer.len()
+                        {
+                            match uart.take_input()
+                            {
+                                Some(byte) => { buffer[count] = byte; count += 1; },
+                                None       => break
+                            }
+                        }
+
+                        count
+                    },
+
+                _ =>
+                    return -EBADF
+            };
+
+        if !cpu.bus.write(address, &buffer[..count])
+        {
+            return -EFAULT;
+        }
+
+        count as i64
+    }
+
+
+    pub fn write(&mut self, cpu: &mut Cpu, fd: u64, address: u64, length: u64) -> i64
+    {
+        let mut buffer = vec![ 0; length.min(MAX_TRANSFER) as usize ];
+
+        if !cpu.bus.read(address, &mut buffer)
+        {
+            return -EFAULT;
+        }
+
+        let written = match self.handle(fd)
+            {
+                Some(Handle::File(file)) => file.write(&buffer),
+
+                Some(Handle::Stdout) =>
+                    {
+                        let mut stdout = io::stdout();
+                        stdout.write_all(&buffer).and_then(|_| stdout.flush()).map(|_| buffer.len())
+                    },
+
+                Some(Handle::Stderr) =>
+                    io::stderr().write_all(&buffer).map(|_| buffer.len()),
+
+                _ =>
+                    return -EBADF
+            };
+
+        match written
+        {
+            Ok(count)  => count as i64,
+            Err(error) => errno(&error)
+        }
+    }
+
+
+    pub fn lseek(&mut self, fd: u64, offset: i64, whence: u64) -> i64
+    {
+        let position = match whence
+            {
+                SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+                SEEK_CUR                => SeekFrom::Current(offset),
+                SEEK_END                => SeekFrom::End(offset),
+                _                       => return -EINVAL
+            };
+
+        match self.handle(fd)
+        {
+            Some(Handle::File(file)) =>
+                file.seek(position).map_or_else(|error| errno(&error), |offset| offset as i64),
+
+            Some(_) =>
+                -ESPIPE,
+
+            None =>
+                -EBADF
+        }
+    }
+
+
+    fn fstat(&mut self, cpu: &mut Cpu, fd: u64, address: u64) -> i64
+    {
+        let metadata = match self.handle(fd)
+            {
+                Some(Handle::File(file)) =>
+                    match file.metadata()
+                    {
+                        Ok(metadata) => Some(metadata),
+                        Err(error)   => return errno(&error)
+                    },
+
+                Some(_) =>
+                    None,
+
+                None =>
+                    return -EBADF
+            };
+
+        if !cpu.bus.write(address, &stat(metadata.as_ref()))
+        {
+            return -EFAULT;
+        }
+
+        0
+    }
+
+
+    // A break outside the heap is refused by leaving it where it is.
+    fn brk(&mut self, address: u64) -> u64
+    {
+        if address >= self.heap_start && address <= self.brk_limit
+        {
+            self.brk = address;
+        }
+
+        self.brk
+    }
+
+
+    // The host's wall clock, the time zone is long obsolete and left alone.
+    fn gettimeofday(cpu: &mut Cpu, address: u64) -> i64
+    {
+        let ( seconds, nanoseconds ) = timestamp(Ok(SystemTime::now()));
+        let mut timeval = [ 0; 16 ];
+
+        put_u64(&mut timeval, 0, seconds);
+        put_u64(&mut timeval, 8, nanoseconds / 1000);
+
+        if address != 0 && !cpu.bus.write(address, &timeval)
+        {
+            return -EFAULT;
+        }
+
+        0
+    }
+}
@@ -0,0 +1,351 @@
+
+// A minimal built-in implementation of the RISC-V Supervisor Binary Interface, standing in for
+// machine-mode firmware so that a kernel can be run in supervisor mode on its own.  Environment
+// calls from supervisor mode are handled here instead of being trapped, and everything else is
+// delegated straight to the kernel.  The supervisor timer interrupt follows the CLINT's timer, so
+// setting the timer just sets mtimecmp.
+
+
+use std::{ cell::RefCell, io::{ self, Write }, rc::Rc };
+use crate::{ cpu::*, devices::Uart };
+
+
+// Extension ids, in a7.
+const SBI_EXT_LEGACY_CONSOLE_PUTCHAR: u64 = 0x_01;
+const SBI_EXT_LEGACY_CONSOLE_GETCHAR: u64 = 0x_02;
+const SBI_EXT_BASE:                   u64 = 0x_10;
+const SBI_EXT_TIME:                   u64 = 0x_5449_4d45;
+const SBI_EXT_IPI:                    u64 = 0x_0073_5049;
+const SBI_EXT_RFENCE:                 u64 = 0x_5246_4e43;
+const SBI_EXT_HSM:                    u64 = 0x_0048_534d;
+const SBI_EXT_SRST:                   u64 = 0x_5352_5354;
+
+// Function ids, in a6.
+const SBI_BASE_GET_SPEC_VERSION:     u64 = 0;
+const SBI_BASE_GET_IMPL_ID:          u64 = 1;
+const SBI_BASE_GET_IMPL_VERSION:     u64 = 2;
+const SBI_BASE_PROBE_EXTENSION:      u64 = 3;
+const SBI_BASE_GET_MVENDORID:        u64 = 4;
+const SBI_BASE_GET_MARCHID:          u64 = 5;
+const SBI_BASE_GET_MIMPID:           u64 = 6;
+
+const SBI_TIME_SET_TIMER:            u64 = 0;
+
+const SBI_IPI_SEND_IPI:              u64 = 0;
+
+const SBI_RFENCE_REMOTE_FENCE_I:     u64 = 0;
+const SBI_RFENCE_REMOTE_SFENCE_VMA:  u64 = 1;
+const SBI_RFENCE_REMOTE_SFENCE_ASID: u64 = 2;
+
+const SBI_HSM_HART_START:            u64 = 0;
+const SBI_HSM_HART_STOP:             u64 = 1;
+const SBI_HSM_HART_GET_STATUS:       u64 = 2;
+const SBI_HSM_HART_SUSPEND:          u64 = 3;
+
+const SBI_SRST_SYSTEM_RESET:         u64 = 0;
+
+// Error codes, returned in a0.
+const SBI_SUCCESS:               i64 = 0;
+const SBI_ERR_FAILED:            i64 = -1;
+const SBI_ERR_NOT_SUPPORTED:     i64 = -2;
+const SBI_ERR_INVALID_PARAM:     i64 = -3;
+const SBI_ERR_ALREADY_AVAILABLE: i64 = -6;
+
+// Version 2.0 of the specification.
+const SBI_SPEC_VERSION: u64 = 2 << 24;
+
+// An implementation id outside of those assigned so far.
+const SBI_IMPL_ID:      u64 = 0x_7276;
+const SBI_IMPL_VERSION: u64 = 1;
+
+const SBI_HSM_STATE_STARTED:          u64 = 0;
+const SBI_HSM_SUSPEND_RETENTIVE:      u64 = 0;
+
+pub const SBI_RESET_SHUTDOWN:    u64 = 0;
+pub const SBI_RESET_COLD_REBOOT: u64 = 1;
+pub const SBI_RESET_WARM_REBOOT: u64 = 2;
+
+pub const SBI_RESET_REASON_NONE: u64 = 0;
+
+// A hart mask base of all ones means every hart.
+const SBI_HART_MASK_ALL: u64 = u64::MAX;
+
+// Flushing more than this many pages one at a time isn't worth it.
+const SBI_MAX_FLUSH_PAGES: u64 = 64;
+
+// Indices of the registers used by the calling convention.
+const REG_A0: usize = 10;
+const REG_A1: usize = 11;
+const REG_A6: usize = 16;
+const REG_A7: usize = 17;
+
+
+
+// The supervisor asked for the machine to be shut down or rebooted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SystemReset
+{
+    pub kind: u64,
+    pub reason: u64
+}
+
+
+pub struct Sbi
+{
+    uart: Rc<RefCell<Uart>>
+}
+
+
+impl Sbi
+{
+    // The console extensions share the UART's input with the guest.
+    pub fn new(uart: Rc<RefCell<Uart>>) -> Self
+    {
+        Self { uart }
+    }
+
+
+    // Do what firmware would before entering the kernel: delegate every trap and supervisor
+    // interrupt, let the kernel read the counters, and drop to supervisor mode.
+    pub fn start(&self, cpu: &mut Cpu)
+    {
+        let delegated = cpu.write_csr(CSR_MEDELEG, !(1 << CAUSE_ECALL_FROM_S_MODE))
+                           .and_then(|_| cpu.write_csr(CSR_MIDELEG, MIP_SSIP | MIP_STIP | MIP_SEIP))
+                           .and_then(|_| cpu.write_csr(CSR_MCOUNTEREN, 0b_111));
+
+        assert!(delegated.is_some(), "The SBI has to start out in machine mode.");
+
+        cpu.privilege = PrivilegeLvel::Supervisor;
+    }
+
+
+    // The supervisor timer interrupt is pending while the timer is past its compare value.
+    pub fn update(&self, cpu: &mut Cpu)
+    {
+        let timer = match &cpu.clint
+            {
+                Some(clint) => clint.borrow().timer_pending(),
+                None        => false
+            };
+
+        if timer
+        {
+            cpu.csrs[CSR_MIP] |= MIP_STIP;
+        }
+        else
+        {
+            cpu.csrs[CSR_MIP] &= !MIP_STIP;
+        }
+    }
+
+
+    // Handle an environment call from supervisor mode, which has left the pc pointing at it.  The
+    // error and value are returned in a0 and a1, and the legacy extensions return a single value in
+    // a0.
+    pub fn call(&mut self, cpu: &mut Cpu) -> Option<SystemReset>
+    {
+        let extension = cpu.regs[REG_A7 - 1];
+        let function = cpu.regs[REG_A6 - 1];
+        let args = [ 0, 1, 2, 3 ].map(|index| cpu.regs[REG_A0 - 1 + index]);
+
+        cpu.pc += 4;
+
+        let ( error, value ) = match extension
+            {
+                SBI_EXT_LEGACY_CONSOLE_PUTCHAR =>
+                    {
+                        let mut stdout = io::stdout();
+                        let _ = stdout.write_all(&[ args[0] as u8 ]).and_then(|_| stdout.flush());
+
+                        cpu.regs[REG_A0 - 1] = 0;
+                        return None;
+                    },
+
+                SBI_EXT_LEGACY_CONSOLE_GETCHAR =>
+                    {
+                        let byte = self.uart.borrow_mut().take_input();
+
+                        cpu.regs[REG_A0 - 1] = byte.map_or(-1_i64 as u64, |byte| byte as u64);
+                        return None;
+                    },
+
+                SBI_EXT_BASE =>
+                    self.base(cpu, function, args[0]),
+
+                SBI_EXT_TIME if function == SBI_TIME_SET_TIMER =>
+                    {
+                        if let Some(clint) = &cpu.clint
+                        {
+                            clint.borrow_mut().set_mtimecmp(args[0]);
+                        }
+
+                        ( SBI_SUCCESS, 0 )
+                    },
+
+                SBI_EXT_IPI if function == SBI_IPI_SEND_IPI =>
+                    {
+                        let ( error, targeted ) = Sbi::hart_mask(cpu, args[0], args[1]);
+
+                        if targeted
+                        {
+                            cpu.csrs[CSR_MIP] |= MIP_SSIP;
+                        }
+
+                        ( error, 0 )
+                    },
+
+                SBI_EXT_RFENCE =>
+                    Sbi::remote_fence(cpu, function, args),
+
+                SBI_EXT_HSM =>
+                    Sbi::hart_state(cpu, function, args),
+
+                SBI_EXT_SRST if function == SBI_SRST_SYSTEM_RESET =>
+                    {
+                        if matches!(args[0], SBI_RESET_SHUTDOWN
+                                           | SBI_RESET_COLD_REBOOT
+                                           | SBI_RESET_WARM_REBOOT)
+                        {
+                            return Some(SystemReset { kind: args[0], reason: args[1] });
+                        }
+
+                        ( SBI_ERR_INVALID_PARAM, 0 )
+                    },
+
+                _ =>
+                    ( SBI_ERR_NOT_SUPPORTED, 0 )
+            };
+
+        cpu.regs[REG_A0 - 1] = error as u64;
+        cpu.regs[REG_A1 - 1] = value;
+
+        None
+    }
+
+
+    fn base(&self, cpu: &Cpu, function: u64, argument: u64) -> ( i64, u64 )
+    {
+        match function
+        {
+            SBI_BASE_GET_SPEC_VERSION => ( SBI_SUCCESS, SBI_SPEC_VERSION ),
+            SBI_BASE_GET_IMPL_ID      => ( SBI_SUCCESS, SBI_IMPL_ID ),
+            SBI_BASE_GET_IMPL_VERSION => ( SBI_SUCCESS, SBI_IMPL_VERSION ),
+            SBI_BASE_GET_MVENDORID    => ( SBI_SUCCESS, cpu.csrs[CSR_MVENDORID] ),
+            SBI_BASE_GET_MARCHID      => ( SBI_SUCCESS, cpu.csrs[CSR_MARCHID] ),
+            SBI_BASE_GET_MIMPID       => ( SBI_SUCCESS, cpu.csrs[CSR_MIMPID] ),
+
+            SBI_BASE_PROBE_EXTENSION =>
+                {
+                    let available = matches!(argument,
+                                             SBI_EXT_LEGACY_CONSOLE_PUTCHAR |
+                                             SBI_EXT_LEGACY_CONSOLE_GETCHAR |
+                                             SBI_EXT_BASE |
+                                             SBI_EXT_TIME |
+                                             SBI_EXT_IPI |
+                                             SBI_EXT_RFENCE |
+                                             SBI_EXT_HSM |
+                                             SBI_EXT_SRST);
+
+                    ( SBI_SUCCESS, available as u64 )
+                },
+
+            _ =>
+                ( SBI_ERR_NOT_SUPPORTED, 0 )
+        }
+    }
+
+
+    // Work out whether a hart mask includes this hart.  It's an error for the mask to name a hart
+    // that doesn't exist.
+    fn hart_mask(cpu: &Cpu, mask: u64, base: u64) -> ( i64, bool )
+    {
+        let hart = cpu.csrs[CSR_MHARTID];
+
+        if base == SBI_HART_MASK_ALL
+        {
+            return ( SBI_SUCCESS, true );
+        }
+
+        let bit = hart.checked_sub(base)
+                      .and_then(|index| 1_u64.checked_shl(index as u32))
+                      .unwrap_or(0);
+
+        if mask & !bit != 0
+        {
+            ( SBI_ERR_INVALID_PARAM, false )
+        }
+        else
+        {
+            ( SBI_SUCCESS, mask & bit != 0 )
+        }
+    }
+
+
+    // Instructions are always fetched from memory, so only the TLB ever needs flushing.  Its
+    // entries aren't tagged with an address space, so flushing an ASID flushes the addresses for
+    // every address space.
+    fn remote_fence(cpu: &mut Cpu, function: u64, args: [ u64; 4 ]) -> ( i64, u64 )
+    {
+        let sfence = match function
+            {
+                SBI_RFENCE_REMOTE_FENCE_I                                   => false,
+                SBI_RFENCE_REMOTE_SFENCE_VMA | SBI_RFENCE_REMOTE_SFENCE_ASID => true,
+                _                                                           =>
+                    return ( SBI_ERR_NOT_SUPPORTED, 0 )
+            };
+
+        let ( error, targeted ) = Sbi::hart_mask(cpu, args[0], args[1]);
+
+        if error != SBI_SUCCESS || !targeted || !sfence
+        {
+            return ( error, 0 );
+        }
+
+        let ( start, size ) = ( args[2], args[3] );
+        let pages = size.div_ceil(PAGE_SIZE as u64);
+
+        // A start and size of zero, or a size of all ones, means everything.
+        if (start == 0 && size == 0) || pages > SBI_MAX_FLUSH_PAGES
+        {
+            cpu.tlb.flush();
+        }
+        else
+        {
+            let levels = cpu.translation_levels();
+
+            for page in 0..pages
+            {
+                cpu.tlb.flush_page(start.wrapping_add(page * PAGE_SIZE as u64) as usize, levels);
+            }
+        }
+
+        ( SBI_SUCCESS, 0 )
+    }
+
+
+    // There's only the one hart, and it's always running.
+    fn hart_state(cpu: &Cpu, function: u64, args: [ u64; 4 ]) -> ( i64, u64 )
+    {
+        let hart = cpu.csrs[CSR_MHARTID];
+
+        match function
+        {
+            SBI_HSM_HART_START if args[0] == hart => ( SBI_ERR_ALREADY_AVAILABLE, 0 ),
+            SBI_HSM_HART_START                    => ( SBI_ERR_INVALID_PARAM, 0 ),
+
+            // Stopping the last hart would leave nothing running.
+            SBI_HSM_HART_STOP =>
+                ( SBI_ERR_FAILED, 0 ),
+
+            SBI_HSM_HART_GET_STATUS if args[0] == hart => ( SBI_SUCCESS, SBI_HSM_STATE_STARTED ),
+            SBI_HSM_HART_GET_STATUS                    => ( SBI_ERR_INVALID_PARAM, 0 ),
+
+            // A retentive suspend can wake up straight away, like wfi.
+            SBI_HSM_HART_SUSPEND if args[0] as u32 as u64 == SBI_HSM_SUSPEND_RETENTIVE =>
+                ( SBI_SUCCESS, 0 ),
+
+            _ =>
+                ( SBI_ERR_NOT_SUPPORTED, 0 )
+        }
+    }
+}
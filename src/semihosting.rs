@@ -0,0 +1,310 @@
+
+// RISC-V semihosting, which carries ARM's semihosting operations over a breakpoint.  A call is an
+// ebreak with a shift of x0 either side of it, which is otherwise a pair of no-ops: the operation
+// goes in a0, a parameter or the address of a block of parameters in a1, and the result comes back
+// in a0.  Files are opened through the newlib proxy, so semihosting handles are its descriptors,
+// with the console as the special file ":tt".
+
+
+use std::time::{ Instant, SystemTime, UNIX_EPOCH };
+use crate::{ cpu::Cpu, newlib::{ self, Newlib } };
+
+
+// The instructions around the ebreak: slli x0, x0, 0x1f and srai x0, x0, 7.
+const SEMIHOSTING_ENTRY:  u32 = 0x_01f0_1013;
+const SEMIHOSTING_EBREAK: u32 = 0x_0010_0073;
+const SEMIHOSTING_EXIT:   u32 = 0x_4070_5013;
+
+// Operations, in a0.
+const SYS_OPEN:          u64 = 0x_01;
+const SYS_CLOSE:         u64 = 0x_02;
+const SYS_WRITEC:        u64 = 0x_03;
+const SYS_WRITE0:        u64 = 0x_04;
+const SYS_WRITE:         u64 = 0x_05;
+const SYS_READ:          u64 = 0x_06;
+const SYS_ISTTY:         u64 = 0x_09;
+const SYS_SEEK:          u64 = 0x_0a;
+const SYS_FLEN:          u64 = 0x_0c;
+const SYS_CLOCK:         u64 = 0x_10;
+const SYS_TIME:          u64 = 0x_11;
+const SYS_ERRNO:         u64 = 0x_13;
+const SYS_GET_CMDLINE:   u64 = 0x_15;
+const SYS_EXIT:          u64 = 0x_18;
+const SYS_EXIT_EXTENDED: u64 = 0x_20;
+
+// An exit for this reason is a normal one, with the status alongside.  Any other is a failure.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x_2_0026;
+
+// The console.  The modes go four at a time to stdin, stdout and stderr, so it's stdin for any
+// of the reading modes, "r+" included, stdout for the writing ones and stderr for the appending
+// ones.
+const CONSOLE_NAME: &str = ":tt";
+const CONSOLE_IN:  u64 = 0;
+const CONSOLE_OUT: u64 = 1;
+const CONSOLE_ERR: u64 = 2;
+
+const CONSOLE_MODES: u64 = 4;
+
+// Open modes are fopen's, in order, each with a binary twin.
+const MODE_FLAGS: [ u64; 6 ] = [ newlib::O_RDONLY,
+                                 newlib::O_RDWR,
+                                 newlib::O_WRONLY | newlib::O_CREAT | newlib::O_TRUNC,
+                                 newlib::O_RDWR | newlib::O_CREAT | newlib::O_TRUNC,
+                                 newlib::O_WRONLY | newlib::O_CREAT | newlib::O_APPEND,
+                                 newlib::O_RDWR | newlib::O_CREAT | newlib::O_APPEND ];
+
+// SYS_WRITE0's string is passed on to the console this many bytes at a time.
+const WRITE0_CHUNK: u64 = 4096;
+
+const CENTISECONDS_PER_SECOND: u128 = 100;
+
+const FAILED: i64 = -1;
+
+// Indices of the registers used by the calling convention.
+const REG_A0: usize = 10;
+const REG_A1: usize = 11;
+
+// Parameter blocks are made of XLEN sized fields.
+const FIELD_SIZE: u64 = 8;
+
+
+
+pub struct Semihosting
+{
+    // What SYS_GET_CMDLINE reports, the program and its arguments.
+    cmdline: String,
+    started: Instant,
+
+    // From the last operation that failed.
+    errno: i64
+}
+
+
+fn read_word(cpu: &mut Cpu, address: u64) -> Option<u32>
+{
+    let mut bytes = [ 0; 4 ];
+
+    if cpu.bus.read(address, &mut bytes) { Some(u32::from_le_bytes(bytes)) } else { None }
+}
+
+
+fn read_field(cpu: &mut Cpu, block: u64, index: u64) -> u64
+{
+    let mut bytes = [ 0; FIELD_SIZE as usize ];
+
+    cpu.bus.read(block + index * FIELD_SIZE, &mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+
+// Write a NUL terminated string to the console byte for byte, however long it is, stopping early
+// at anything that can't be read.
+fn write0(cpu: &mut Cpu, newlib: &mut Newlib, address: u64)
+{
+    let mut start = address;
+    let mut end = address;
+
+    loop
+    {
+        let mut byte = [ 0 ];
+        let terminated = !cpu.bus.read(end, &mut byte) || byte[0] == 0;
+
+        if terminated || end.wrapping_sub(start) == WRITE0_CHUNK
+        {
+            newlib.write(cpu, CONSOLE_OUT, start, end.wrapping_sub(start));
+            start = end;
+        }
+
+        if terminated
+        {
+            return;
+        }
+
+        end = end.wrapping_add(1);
+    }
+}
+
+
+impl Semihosting
+{
+    pub fn new(cmdline: String) -> Self
+    {
+        Self { cmdline, started: Instant::now(), errno: 0 }
+    }
+
+
+    // Whether the breakpoint at the pc is a semihosting call.  The sequence is always made of full
+    // size instructions.
+    pub fn is_call(cpu: &mut Cpu) -> bool
+    {
+        let pc = cpu.pc as u64;
+
+        pc >= 4
+            && read_word(cpu, pc - 4) == Some(SEMIHOSTING_ENTRY)
+            && read_word(cpu, pc) == Some(SEMIHOSTING_EBREAK)
+            && read_word(cpu, pc + 4) == Some(SEMIHOSTING_EXIT)
+    }
+
+
+    // Carry out the call, leaving the pc past the ebreak.  Gives the exit status once the program
+    // has exited.
+    pub fn call(&mut self, cpu: &mut Cpu, newlib: &mut Newlib) -> Option<i32>
+    {
+        let operation = cpu.regs[REG_A0 - 1];
+        let parameter = cpu.regs[REG_A1 - 1];
+        let field = |cpu: &mut Cpu, index| read_field(cpu, parameter, index);
+
+        cpu.pc += 4;
+
+        let result = match operation
+            {
+                SYS_EXIT | SYS_EXIT_EXTENDED =>
+                    {
+                        let ( reason, status ) = ( field(cpu, 0), field(cpu, 1) );
+
+                        if reason != ADP_STOPPED_APPLICATION_EXIT
+                        {
+                            return Some(1);
+                        }
+
+                        return Some(status as i32);
+                    },
+
+                SYS_OPEN =>
+                    {
+                        let ( name, mode ) = ( field(cpu, 0), field(cpu, 1) );
+                        self.open(cpu, newlib, name, mode)
+                    },
+
+                SYS_CLOSE =>
+                    self.check(newlib.close(field(cpu, 0))),
+
+                SYS_WRITEC =>
+                    {
+                        newlib.write(cpu, CONSOLE_OUT, parameter, 1);
+                        return None;
+                    },
+
+                SYS_WRITE0 =>
+                    {
+                        write0(cpu, newlib, parameter);
+                        return None;
+                    },
+
+                // Reads and writes give the number of bytes left over.
+                SYS_WRITE | SYS_READ =>
+                    {
+                        let handle = field(cpu, 0);
+                        let ( buffer, length ) = ( field(cpu, 1), field(cpu, 2) );
+
+                        let done = if operation == SYS_WRITE
+                            {
+                                newlib.write(cpu, handle, buffer, length)
+                            }
+                            else
+                            {
+                                newlib.read(cpu, handle, buffer, length)
+                            };
+
+                        length as i64 - self.check(done).max(0)
+                    },
+
+                SYS_ISTTY =>
+                    newlib.is_console(field(cpu, 0)) as i64,
+
+                SYS_SEEK =>
+                    {
+                        let ( handle, position ) = ( field(cpu, 0), field(cpu, 1) );
+                        self.check(newlib.lseek(handle, position as i64, newlib::SEEK_SET)).min(0)
+                    },
+
+                SYS_FLEN =>
+                    {
+                        let handle = field(cpu, 0);
+                        let position = newlib.lseek(handle, 0, newlib::SEEK_CUR);
+                        let length = newlib.lseek(handle, 0, newlib::SEEK_END);
+
+                        newlib.lseek(handle, position, newlib::SEEK_SET);
+                        self.check(length)
+                    },
+
+                SYS_CLOCK =>
+                    (self.started.elapsed().as_millis() * CENTISECONDS_PER_SECOND / 1000) as i64,
+
+                SYS_TIME =>
+                    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+                        as i64,
+
+                SYS_ERRNO =>
+                    self.errno,
+
+                SYS_GET_CMDLINE =>
+                    self.get_cmdline(cpu, parameter),
+
+                _ =>
+                    FAILED
+            };
+
+        cpu.regs[REG_A0 - 1] = result as u64;
+
+        None
+    }
+
+
+    // Failures come back from the proxy as a negated errno, semihosting keeps the errno for later
+    // and reports failure as -1.
+    fn check(&mut self, result: i64) -> i64
+    {
+        if result < 0
+        {
+            self.errno = -result;
+            return FAILED;
+        }
+
+        result
+    }
+
+
+    fn open(&mut self, cpu: &mut Cpu, newlib: &mut Newlib, name: u64, mode: u64) -> i64
+    {
+        let flags = match MODE_FLAGS.get(mode as usize / 2)
+            {
+                Some(&flags) => flags,
+                None         => return FAILED
+            };
+
+        if newlib::read_path(cpu, name).is_some_and(|name| name == CONSOLE_NAME)
+        {
+            return match mode / CONSOLE_MODES
+                {
+                    0 => CONSOLE_IN as i64,
+                    1 => CONSOLE_OUT as i64,
+                    _ => CONSOLE_ERR as i64
+                };
+        }
+
+        let fd = newlib.open(cpu, name, flags);
+        self.check(fd)
+    }
+
+
+    // The command line goes in the buffer given, and its length in place of the buffer's.
+    fn get_cmdline(&mut self, cpu: &mut Cpu, block: u64) -> i64
+    {
+        let buffer = read_field(cpu, block, 0);
+        let size = read_field(cpu, block, 1);
+
+        let mut cmdline = self.cmdline.clone().into_bytes();
+        let length = cmdline.len() as u64;
+
+        cmdline.push(0);
+
+        if cmdline.len() as u64 > size || !cpu.bus.write(buffer, &cmdline)
+        {
+            return FAILED;
+        }
+
+        cpu.bus.write(block + FIELD_SIZE, &length.to_le_bytes());
+        0
+    }
+}
@@ -1,6 +1,5 @@
 
-# riscv64-unknown-elf-gcc -Wl,-Ttext=0x0 -nostdlib -o add-addi add-addi.s
-# riscv64-unknown-elf-objcopy -O binary add-addi add-addi.bin
+# riscv64-unknown-elf-gcc -Wl,-Ttext=0x80000000 -nostdlib -o add-addi add-addi.s
 
 main:
     addi x29, x0, 5
@@ -1,8 +1,7 @@
 
 /*
 riscv64-unknown-elf-gcc -S fib.c && \
-riscv64-unknown-elf-gcc -Wl,-Ttext=0x0 -nostdlib -o fib fib.s && \
-riscv64-unknown-elf-objcopy -O binary fib fib.bin
+riscv64-unknown-elf-gcc -Wl,-Ttext=0x80000000 -nostdlib -o fib fib.s
 */
 
 